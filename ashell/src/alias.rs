@@ -0,0 +1,84 @@
+//! Aliases, `alias name='pwmin start $1'`: a command with its arguments
+//! filled in, run as `name 0`.
+//!
+//! `$1` to `$9` outside single quotes are replaced by the arguments after
+//! the name, quoted so each one stays one argument. Arguments after the
+//! last one referred to are appended, so `alias p='pwmin'` runs `p start 0`
+//! as `pwmin start 0`. The first word of an alias is not expanded again,
+//! an alias may have the name of the command it calls.
+//!
+//! An alias is one command, which may end with `| filters`. A
+//! [`ScriptStore`](crate::script::ScriptStore) keeps them, like scripts.
+
+use core::fmt::{self, Write};
+
+/// Longest alias text.
+pub const ALIAS_LEN: usize = 64;
+
+/// Write `text` to `out` with `$1..$9` replaced by `args`.
+pub fn substitute(text: &str, args: &[&str], out: &mut dyn Write) -> fmt::Result {
+    let bytes = text.as_bytes();
+    let mut quote = None;
+    let mut used = 0;
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match (quote, bytes[idx]) {
+            (Some(b'\''), b'\'') | (Some(b'"'), b'"') => quote = None,
+            (Some(b'\''), _) => {}
+            (_, b'\\') => idx += 1,
+            (None, byte @ (b'\'' | b'"')) => quote = Some(byte),
+            (_, b'$') => {
+                if let Some(digit @ b'1'..=b'9') = bytes.get(idx + 1) {
+                    let n = (digit - b'0') as usize;
+                    out.write_str(&text[start..idx])?;
+                    if let Some(arg) = args.get(n - 1) {
+                        write_arg(arg, quote.is_some(), out)?;
+                    }
+                    used = used.max(n);
+                    idx += 2;
+                    start = idx;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    out.write_str(&text[start..])?;
+    for arg in args.iter().skip(used) {
+        out.write_str(" ")?;
+        write_arg(arg, false, out)?;
+    }
+    Ok(())
+}
+
+/// `arg` as the tokenizer reads it back: escaped inside double quotes,
+/// in single quotes outside if it needs any.
+fn write_arg(arg: &str, in_double: bool, out: &mut dyn Write) -> fmt::Result {
+    if in_double {
+        for ch in arg.chars() {
+            if matches!(ch, '"' | '\\' | '$') {
+                out.write_char('\\')?;
+            }
+            out.write_char(ch)?;
+        }
+        return Ok(());
+    }
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.' | ',' | ':' | '/' | '+' | '='));
+    if plain {
+        return out.write_str(arg);
+    }
+    out.write_char('\'')?;
+    for (i, part) in arg.split('\'').enumerate() {
+        if i > 0 {
+            //close the quote, an escaped ', open again
+            out.write_str("'\\''")?;
+        }
+        out.write_str(part)?;
+    }
+    out.write_char('\'')
+}
@@ -4,6 +4,32 @@ use crate::heapless::String;
 
 pub trait Autocomplete<const CMD_LEN: usize> {
     fn suggest(&self, prefix: &str) -> Option<String<CMD_LEN>>;
+
+    /// Call `add` with every full word that completes the last word of `line`.
+    ///
+    /// The default only knows the single suffix returned by `suggest`.
+    fn candidates(&self, line: &str, add: &mut dyn FnMut(&str)) {
+        if let Some(suffix) = self.suggest(line) {
+            let mut word: String<CMD_LEN> = String::new();
+            if word.push_str(last_word(line)).is_ok() && word.push_str(&suffix).is_ok() {
+                add(&word);
+            }
+        }
+    }
+}
+
+/// The word being completed: everything after the last blank.
+pub fn last_word(line: &str) -> &str {
+    let start = line.rfind(|c: char| c == ' ' || c == '\t').map_or(0, |i| i + 1);
+    &line[start..]
+}
+
+/// Length of the longest common prefix of `a` and `b`, on a char boundary.
+pub fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, ca), cb)| ca != cb)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
 }
 
 pub struct NoAutocomplete;
@@ -12,6 +38,8 @@ impl<const CMD_LEN: usize> Autocomplete<CMD_LEN> for NoAutocomplete {
     fn suggest(&self, _prefix: &str) -> Option<String<CMD_LEN>> {
         None
     }
+
+    fn candidates(&self, _line: &str, _add: &mut dyn FnMut(&str)) {}
 }
 
 pub struct FnAutocomplete<const CMD_LEN: usize>(pub fn(&str) -> Option<String<CMD_LEN>>);
@@ -45,4 +73,14 @@ impl<const CMD_LEN: usize, const N: usize> Autocomplete<CMD_LEN> for StaticAutoc
         }
         None
     }
+
+    fn candidates(&self, line: &str, add: &mut dyn FnMut(&str)) {
+        if line.len() == 0 {
+            return;
+        }
+        self.0
+            .iter()
+            .filter(|item| item.starts_with(line))
+            .for_each(|item| add(item));
+    }
 }
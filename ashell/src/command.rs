@@ -0,0 +1,521 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::autocomplete::{common_prefix_len, last_word, Autocomplete};
+use crate::heapless::{String, Vec};
+use crate::{ShellError, ShellResult};
+
+/// Maximum number of arguments a [`Command`] can declare.
+pub const MAX_ARGS: usize = 8;
+
+pub type HandlerFn = fn(&Args, &mut dyn fmt::Write) -> ShellResult;
+
+/// How a command runs.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// Runs to completion inside [`Registry::dispatch`].
+    Sync(HandlerFn),
+    /// Long-running command: [`Registry::dispatch`] hands it back as a
+    /// [`Task`] and the environment awaits it, so the shell can cancel it
+    /// with Ctrl-C. The number tells the environment which one it is.
+    Async(u16),
+}
+
+/// An async command and its parsed arguments, see [`Handler::Async`].
+pub struct Task<'a> {
+    pub id: u16,
+    pub args: Args<'a>,
+}
+
+/// Completion hook of an argument: call `add` with every candidate value.
+/// Candidates not starting with the typed prefix are dropped by the caller.
+pub type Completer = fn(add: &mut dyn FnMut(&str));
+
+/// Type of a command argument, and the range of values it accepts.
+#[derive(Clone, Copy)]
+pub enum ArgKind {
+    /// Decimal or `0x` hex integer in `min..=max`.
+    Int { min: i32, max: i32 },
+    /// One of the listed words.
+    Choice(&'static [&'static str]),
+    /// One or more pin numbers in `0..=max`, separated by blanks or `,`,
+    /// or `all`. Takes the positional arguments up to the next flag.
+    Pins { max: u8 },
+    /// Flag without value, only valid for `-x` style arguments.
+    Switch,
+    /// Any string.
+    Text,
+}
+
+/// Declaration of one positional argument, or a flag when `name` starts with `-`.
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+    pub help: &'static str,
+    /// Candidates for tab completion, instead of the ones derived from `kind`.
+    pub complete: Option<Completer>,
+}
+
+impl Arg {
+    fn is_flag(&self) -> bool {
+        self.name.starts_with('-')
+    }
+
+    fn candidates(&self, add: &mut dyn FnMut(&str)) {
+        if let Some(complete) = self.complete {
+            return complete(add);
+        }
+        match self.kind {
+            ArgKind::Choice(choices) => choices.iter().for_each(|choice| add(choice)),
+            ArgKind::Pins { max } => {
+                add("all");
+                for pin in 0..=max.min(31) {
+                    let mut buf = [0u8; 2];
+                    add(fmt_u8(pin, &mut buf));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A command, or a subcommand when it is listed in another command's
+/// `subcommands`. `argv` is matched against the subcommand names first;
+/// `handler` runs when none matches and may be `None` for pure groups.
+pub struct Command {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub usage: &'static str,
+    pub args: &'static [Arg],
+    pub subcommands: &'static [Command],
+    pub handler: Option<Handler>,
+}
+
+impl Command {
+    fn subcommand(&self, name: &str) -> Option<&'static Command> {
+        self.subcommands.iter().find(|cmd| cmd.name == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    None,
+    Int(i32),
+    Choice(&'static str),
+    /// Bit `n` is set for pin `n`.
+    Pins(u32),
+    Switch,
+    Text(&'a str),
+}
+
+/// Why an argument list was rejected. Names refer to [`Arg::name`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgError {
+    Missing(&'static str),
+    NotANumber(&'static str),
+    OutOfRange { name: &'static str, min: i32, max: i32 },
+    BadChoice(&'static str),
+    /// Flag that needs a value was last on the line.
+    NoValue(&'static str),
+    UnknownFlag,
+    /// Group command called without a valid subcommand.
+    NoSubcommand,
+    TooManyArgs,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing(name) => write!(f, "missing argument <{}>", name),
+            ArgError::NotANumber(name) => write!(f, "<{}> is not a number", name),
+            ArgError::OutOfRange { name, min, max } => {
+                write!(f, "<{}> out of range {}..{}", name, min, max)
+            }
+            ArgError::BadChoice(name) => write!(f, "invalid value for <{}>", name),
+            ArgError::NoValue(name) => write!(f, "{} needs a value", name),
+            ArgError::UnknownFlag => write!(f, "unknown flag"),
+            ArgError::NoSubcommand => write!(f, "missing or unknown subcommand"),
+            ArgError::TooManyArgs => write!(f, "too many arguments"),
+        }
+    }
+}
+
+impl From<ArgError> for ShellError {
+    fn from(err: ArgError) -> Self {
+        ShellError::ArgError(err)
+    }
+}
+
+/// Parsed arguments of one invocation, looked up by [`Arg::name`].
+pub struct Args<'a> {
+    spec: &'static [Arg],
+    values: [Value<'a>; MAX_ARGS],
+}
+
+impl<'a> Args<'a> {
+    /// Parse `argv` (without the command name) against `spec`.
+    pub fn parse(spec: &'static [Arg], argv: &[&'a str]) -> Result<Self, ArgError> {
+        if spec.len() > MAX_ARGS {
+            return Err(ArgError::TooManyArgs);
+        }
+        let mut values = [Value::None; MAX_ARGS];
+        let mut positional = spec.iter().enumerate().filter(|(_, arg)| !arg.is_flag());
+        let mut i = 0;
+        while i < argv.len() {
+            let word = argv[i];
+            i += 1;
+            if is_flag_word(word) {
+                let idx = spec
+                    .iter()
+                    .position(|arg| arg.is_flag() && arg.name == word)
+                    .ok_or(ArgError::UnknownFlag)?;
+                let arg = &spec[idx];
+                values[idx] = match arg.kind {
+                    ArgKind::Switch => Value::Switch,
+                    _ => {
+                        let value = argv.get(i).ok_or(ArgError::NoValue(arg.name))?;
+                        i += 1;
+                        parse_value(arg, value)?
+                    }
+                };
+                continue;
+            }
+            let (idx, arg) = positional.next().ok_or(ArgError::TooManyArgs)?;
+            values[idx] = match arg.kind {
+                ArgKind::Pins { max } => {
+                    // pin list takes the words up to the next flag
+                    let mut pins = parse_pins(arg.name, word, max)?;
+                    while i < argv.len() && !is_flag_word(argv[i]) {
+                        pins |= parse_pins(arg.name, argv[i], max)?;
+                        i += 1;
+                    }
+                    Value::Pins(pins)
+                }
+                _ => parse_value(arg, word)?,
+            };
+        }
+        for (arg, value) in spec.iter().zip(values.iter()) {
+            if arg.required && *value == Value::None {
+                return Err(ArgError::Missing(arg.name));
+            }
+        }
+        Ok(Self { spec, values })
+    }
+
+    pub fn get(&self, name: &str) -> Value<'a> {
+        self.spec
+            .iter()
+            .position(|arg| arg.name == name)
+            .map(|idx| self.values[idx])
+            .unwrap_or(Value::None)
+    }
+
+    pub fn int(&self, name: &str) -> Option<i32> {
+        match self.get(name) {
+            Value::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn choice(&self, name: &str) -> Option<&'static str> {
+        match self.get(name) {
+            Value::Choice(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Pins as a bitmask, bit `n` for pin `n`.
+    pub fn pins(&self, name: &str) -> u32 {
+        match self.get(name) {
+            Value::Pins(v) => v,
+            _ => 0,
+        }
+    }
+
+    pub fn switch(&self, name: &str) -> bool {
+        self.get(name) == Value::Switch
+    }
+
+    pub fn text(&self, name: &str) -> Option<&'a str> {
+        match self.get(name) {
+            Value::Text(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// `-x`, not a negative number.
+fn is_flag_word(word: &str) -> bool {
+    word.starts_with('-') && parse_int(word).is_none()
+}
+
+fn parse_int(word: &str) -> Option<i32> {
+    let (neg, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    let (radix, digits) = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => (16, hex),
+        None => (10, digits),
+    };
+    //from_str_radix takes a sign of its own, `0x-5` or `--5`
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i64::from_str_radix(digits, radix).ok()?;
+    let value = if neg { -value } else { value };
+    i32::try_from(value).ok()
+}
+
+fn parse_value<'a>(arg: &Arg, word: &'a str) -> Result<Value<'a>, ArgError> {
+    match arg.kind {
+        ArgKind::Int { min, max } => {
+            let value = parse_int(word).ok_or(ArgError::NotANumber(arg.name))?;
+            if value < min || value > max {
+                return Err(ArgError::OutOfRange { name: arg.name, min, max });
+            }
+            Ok(Value::Int(value))
+        }
+        ArgKind::Choice(choices) => choices
+            .iter()
+            .find(|choice| **choice == word)
+            .map(|choice| Value::Choice(choice))
+            .ok_or(ArgError::BadChoice(arg.name)),
+        ArgKind::Pins { max } => parse_pins(arg.name, word, max).map(Value::Pins),
+        ArgKind::Switch => Ok(Value::Switch),
+        ArgKind::Text => Ok(Value::Text(word)),
+    }
+}
+
+fn fmt_u8(value: u8, buf: &mut [u8; 2]) -> &str {
+    let len = if value < 10 {
+        buf[0] = b'0' + value;
+        1
+    } else {
+        buf[0] = b'0' + value / 10;
+        buf[1] = b'0' + value % 10;
+        2
+    };
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+fn parse_pins(name: &'static str, word: &str, max: u8) -> Result<u32, ArgError> {
+    let max = max.min(31);
+    if word == "all" {
+        return Ok(u32::MAX >> (31 - max));
+    }
+    let mut pins = 0;
+    for item in word.split(',').filter(|item| !item.is_empty()) {
+        let pin = parse_int(item).ok_or(ArgError::NotANumber(name))?;
+        if pin < 0 || pin > max as i32 {
+            return Err(ArgError::OutOfRange { name, min: 0, max: max as i32 });
+        }
+        pins |= 1 << pin;
+    }
+    Ok(pins)
+}
+
+/// Fixed size table of commands. `help` is built in.
+pub struct Registry<const N: usize> {
+    commands: Vec<&'static Command, N>,
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Add `cmd`, replacing a command with the same name.
+    pub fn register(&mut self, cmd: &'static Command) -> Result<(), ()> {
+        self.unregister(cmd.name);
+        self.commands.push(cmd).map_err(|_| ())
+    }
+
+    pub fn unregister(&mut self, name: &str) {
+        self.commands.retain(|cmd| cmd.name != name);
+    }
+
+    pub fn find(&self, name: &str) -> Option<&'static Command> {
+        self.commands.iter().find(|cmd| cmd.name == name).copied()
+    }
+
+    /// Names of all commands, `help` first.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        core::iter::once("help").chain(self.commands.iter().map(|cmd| cmd.name))
+    }
+
+    /// Find the command named by `argv[0]` and walk down its subcommands.
+    /// Returns the command and the arguments left for it.
+    pub fn resolve<'a, 'b>(&self, argv: &'b [&'a str]) -> Option<(&'static Command, &'b [&'a str])> {
+        let (name, mut rest) = argv.split_first()?;
+        let mut cmd = self.find(name)?;
+        while let Some((sub, sub_rest)) = rest
+            .split_first()
+            .and_then(|(name, sub_rest)| cmd.subcommand(name).map(|sub| (sub, sub_rest)))
+        {
+            cmd = sub;
+            rest = sub_rest;
+        }
+        Some((cmd, rest))
+    }
+
+    /// Parse `argv` for the command named by `argv[0]` and run it. An async
+    /// command is not run but returned, for the caller to await.
+    pub fn dispatch<'a>(&self, argv: &[&'a str], out: &mut dyn fmt::Write) -> Result<Option<Task<'a>>, ShellError> {
+        match argv.first() {
+            None => return Ok(None),
+            Some(&"help") => return self.help(&argv[1..], out).map(|_| None),
+            _ => {}
+        }
+        let (cmd, rest) = self.resolve(argv).ok_or(ShellError::CommandNotFound)?;
+        let ret = match cmd.handler {
+            Some(handler) => Args::parse(cmd.args, rest).map(|args| (args, handler)),
+            None => Err(ArgError::NoSubcommand),
+        };
+        match ret {
+            Ok((args, Handler::Sync(handler))) => handler(&args, out).map(|_| None),
+            Ok((args, Handler::Async(id))) => Ok(Some(Task { id, args })),
+            Err(err) => {
+                write!(out, "{}: {}\r\nusage: {}\r\n", cmd.name, err, cmd.usage)?;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Write the command list, or the usage of the command `path` leads to.
+    pub fn help(&self, path: &[&str], out: &mut dyn fmt::Write) -> ShellResult {
+        if path.is_empty() {
+            write!(out, "  {:<10}{}\r\n", "help", "list commands, or usage of one")?;
+            for cmd in self.commands.iter() {
+                write!(out, "  {:<10}{}\r\n", cmd.name, cmd.summary)?;
+            }
+            return Ok(());
+        }
+        let (cmd, _) = self.resolve(path).ok_or(ShellError::CommandNotFound)?;
+        write!(out, "{} - {}\r\nusage: {}\r\n", cmd.name, cmd.summary, cmd.usage)?;
+        for sub in cmd.subcommands {
+            write!(out, "  {:<10}{}\r\n", sub.name, sub.summary)?;
+        }
+        for arg in cmd.args {
+            write!(out, "  {:<10}", arg.name)?;
+            match arg.kind {
+                ArgKind::Int { min, max } => write!(out, "{}..{}  ", min, max)?,
+                ArgKind::Choice(choices) => {
+                    for (i, choice) in choices.iter().enumerate() {
+                        let sep = if i == 0 { "" } else { "|" };
+                        write!(out, "{}{}", sep, choice)?;
+                    }
+                    write!(out, "  ")?;
+                }
+                ArgKind::Pins { max } => write!(out, "0..{}|all  ", max)?,
+                ArgKind::Switch | ArgKind::Text => {}
+            }
+            write!(out, "{}\r\n", arg.help)?;
+        }
+        Ok(())
+    }
+
+    /// Call `add` with every completion of the last word of `line`: command
+    /// names first, then subcommand names, then argument values.
+    pub fn complete(&self, line: &str, add: &mut dyn FnMut(&str)) {
+        let partial = last_word(line);
+        let partial_start = line.len() - partial.len();
+        let mut filter = |candidate: &str| {
+            if candidate.starts_with(partial) {
+                add(candidate)
+            }
+        };
+
+        let mut words = line[..partial_start].split_ascii_whitespace();
+        let first = match words.next() {
+            None => return self.names().for_each(|name| filter(name)),
+            Some(first) => first,
+        };
+        if first == "help" {
+            // help takes a command path
+            let mut path: Vec<&str, MAX_ARGS> = Vec::new();
+            for word in words {
+                if path.push(word).is_err() {
+                    return;
+                }
+            }
+            return match path.is_empty() {
+                true => self.commands.iter().for_each(|cmd| filter(cmd.name)),
+                false => {
+                    if let Some((cmd, [])) = self.resolve(&path) {
+                        cmd.subcommands.iter().for_each(|sub| filter(sub.name))
+                    }
+                }
+            };
+        }
+
+        let mut cmd = match self.find(first) {
+            Some(cmd) => cmd,
+            None => return,
+        };
+        let mut positional = 0;
+        let mut after_flag: Option<&Arg> = None;
+        for word in words {
+            if positional == 0 && after_flag.is_none() {
+                if let Some(sub) = cmd.subcommand(word) {
+                    cmd = sub;
+                    continue;
+                }
+            }
+            if after_flag.take().is_some() {
+                continue;
+            }
+            if let Some(flag) = cmd.args.iter().find(|arg| arg.is_flag() && arg.name == word) {
+                if !matches!(flag.kind, ArgKind::Switch) {
+                    after_flag = Some(flag);
+                }
+                continue;
+            }
+            positional += 1;
+        }
+
+        if let Some(flag) = after_flag {
+            return flag.candidates(&mut filter);
+        }
+        if positional == 0 {
+            cmd.subcommands.iter().for_each(|sub| filter(sub.name));
+        }
+        if partial.starts_with('-') {
+            return cmd
+                .args
+                .iter()
+                .filter(|arg| arg.is_flag())
+                .for_each(|arg| filter(arg.name));
+        }
+        let args = cmd.args.iter().filter(|arg| !arg.is_flag());
+        let arg = args.clone().nth(positional).or_else(|| {
+            // a pin list keeps taking words
+            args.last().filter(|arg| matches!(arg.kind, ArgKind::Pins { .. }))
+        });
+        if let Some(arg) = arg {
+            arg.candidates(&mut filter);
+        }
+    }
+}
+
+impl<const CMD_LEN: usize, const N: usize> Autocomplete<CMD_LEN> for Registry<N> {
+    /// Suffix shared by all candidates.
+    fn suggest(&self, prefix: &str) -> Option<String<CMD_LEN>> {
+        let partial_len = last_word(prefix).len();
+        let mut common: Option<String<CMD_LEN>> = None;
+        self.complete(prefix, &mut |candidate| match common.as_mut() {
+            None => common = String::from_str(candidate).ok(),
+            Some(common) => common.truncate(common_prefix_len(common, candidate)),
+        });
+        common
+            .filter(|common| common.len() > partial_len)
+            .and_then(|common| String::from_str(&common[partial_len..]).ok())
+    }
+
+    fn candidates(&self, line: &str, add: &mut dyn FnMut(&str)) {
+        self.complete(line, add)
+    }
+}
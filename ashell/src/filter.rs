@@ -0,0 +1,289 @@
+//! Pipelines: `cmd | filter | filter ..` with line filters built into the shell.
+//!
+//! The command writes into a [`Pipeline`], which cuts its output into lines
+//! and runs each of them through the filters on the way to the real output:
+//!
+//! - `grep [-v] <text>` keeps the lines containing `text`, or the others
+//! - `head <n>` keeps the first `n` lines, then stops the command
+//! - `tail <n>` keeps the last `n` lines, printed when the command ends
+//! - `count` prints the number of lines when the command ends
+//! - `field <n..>` keeps the `n`th `:` separated fields, counted from 1
+//!
+//! `|` has to be an argument of its own, `cmd|grep` is one word.
+
+use core::fmt::{self, Write};
+use core::task::{Context, Poll};
+
+use crate::heapless::{Deque, String, Vec};
+use crate::output::CommandOutput;
+use crate::ShellError;
+
+/// Most filters after one command.
+pub const MAX_FILTERS: usize = 4;
+/// Longer lines are cut.
+pub const LINE_LEN: usize = 128;
+/// Most lines `tail` keeps.
+pub const TAIL_LINES: usize = 8;
+/// Most fields `field` selects.
+const MAX_FIELDS: usize = 8;
+const SEPARATOR: char = ':';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// Nothing before a `|`.
+    NoCommand,
+    Unknown,
+    BadArg,
+    TooMany,
+    /// Only one `tail` per pipeline.
+    TwoTails,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::NoCommand => write!(f, "missing command before |"),
+            FilterError::Unknown => write!(f, "unknown filter, try grep, head, tail, count or field"),
+            FilterError::BadArg => write!(f, "bad filter argument"),
+            FilterError::TooMany => write!(f, "too many filters"),
+            FilterError::TwoTails => write!(f, "only one tail per pipeline"),
+        }
+    }
+}
+
+impl From<FilterError> for ShellError {
+    fn from(err: FilterError) -> Self {
+        ShellError::FilterError(err)
+    }
+}
+
+/// One stage of a pipeline and what it counted so far.
+enum Filter<'a> {
+    Grep { text: &'a str, invert: bool },
+    Head { left: usize },
+    Tail { keep: usize },
+    Count { lines: usize },
+    Field(Vec<u8, MAX_FIELDS>),
+}
+
+impl<'a> Filter<'a> {
+    fn parse(argv: &[&'a str]) -> Result<Self, FilterError> {
+        let number = |arg: &str| arg.parse::<usize>().map_err(|_| FilterError::BadArg);
+        Ok(match argv {
+            ["grep", text] => Filter::Grep { text, invert: false },
+            ["grep", "-v", text] => Filter::Grep { text, invert: true },
+            ["head", n] => Filter::Head { left: number(n)? },
+            ["tail", n] => match number(n)? {
+                keep @ 1..=TAIL_LINES => Filter::Tail { keep },
+                _ => return Err(FilterError::BadArg),
+            },
+            ["count"] => Filter::Count { lines: 0 },
+            ["field", fields @ ..] if !fields.is_empty() => {
+                let mut select = Vec::new();
+                for field in fields {
+                    match field.parse::<u8>() {
+                        Ok(n) if n > 0 => select.push(n).map_err(|_| FilterError::TooMany)?,
+                        _ => return Err(FilterError::BadArg),
+                    }
+                }
+                Filter::Field(select)
+            }
+            ["grep" | "head" | "tail" | "count" | "field", ..] => return Err(FilterError::BadArg),
+            _ => return Err(FilterError::Unknown),
+        })
+    }
+}
+
+/// Split `argv` at `|`: the command, and the filters of a [`Pipeline`].
+pub fn split_pipeline<'a, 'b>(argv: &'b [&'a str]) -> Result<(&'b [&'a str], Filters<'a>), FilterError> {
+    let mut parts = argv.split(|arg| *arg == "|");
+    let command = parts.next().unwrap_or(&[]);
+    let mut filters = Filters(Vec::new());
+    for part in parts {
+        let filter = Filter::parse(part)?;
+        if matches!(filter, Filter::Tail { .. }) && filters.0.iter().any(|f| matches!(f, Filter::Tail { .. })) {
+            return Err(FilterError::TwoTails);
+        }
+        filters.0.push(filter).map_err(|_| FilterError::TooMany)?;
+    }
+    if command.is_empty() && !filters.0.is_empty() {
+        return Err(FilterError::NoCommand);
+    }
+    Ok((command, filters))
+}
+
+/// Parsed filters of a command line, see [`split_pipeline`].
+pub struct Filters<'a>(Vec<Filter<'a>, MAX_FILTERS>);
+
+impl<'a> Filters<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Output of one command going through its filters to `out`.
+///
+/// Without filters the output passes as it is. Filtered lines are separated
+/// by `\r\n`, the last one is not ended, like the output of a command.
+/// Once a `head` has all its lines, writes fail, so a command that checks
+/// what `write!` returns stops there; [`Pipeline::done`] tells it apart from
+/// a real error. Call [`Pipeline::finish`] when the command ended, for the
+/// last partial line, `tail` and `count`.
+pub struct Pipeline<'a, 'w> {
+    filters: Vec<Filter<'a>, MAX_FILTERS>,
+    out: &'w mut dyn CommandOutput,
+    line: String<LINE_LEN>,
+    tail: Deque<String<LINE_LEN>, TAIL_LINES>,
+    /// the last thing written to `out` was not a line end
+    mid_line: bool,
+}
+
+impl<'a, 'w> Pipeline<'a, 'w> {
+    pub fn new(filters: Filters<'a>, out: &'w mut dyn CommandOutput) -> Self {
+        Self {
+            filters: filters.0,
+            out,
+            line: String::new(),
+            tail: Deque::new(),
+            mid_line: false,
+        }
+    }
+
+    /// What went out so far does not end with a line end.
+    pub fn mid_line(&self) -> bool {
+        self.mid_line
+    }
+
+    /// The command was cancelled, echo `^C` after its output.
+    pub fn interrupted(&mut self) -> fmt::Result {
+        if self.filters.is_empty() {
+            self.mid_line = true;
+            return self.out.write_str("^C");
+        }
+        self.emit("^C")
+    }
+
+    /// A `head` is full, nothing the command still writes can get out.
+    pub fn done(&self) -> bool {
+        self.filters.iter().any(|f| matches!(f, Filter::Head { left: 0 }))
+    }
+
+    /// Flush what the filters hold back.
+    pub fn finish(&mut self) -> fmt::Result {
+        if !self.line.is_empty() {
+            self.end_line()?;
+        }
+        for stage in 0..self.filters.len() {
+            match self.filters[stage] {
+                Filter::Tail { .. } => {
+                    while let Some(line) = self.tail.pop_front() {
+                        self.run(stage + 1, &line)?;
+                    }
+                }
+                Filter::Count { lines } => {
+                    let mut line: String<12> = String::new();
+                    write!(line, "{}", lines)?;
+                    self.run(stage + 1, &line)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> fmt::Result {
+        let line = core::mem::take(&mut self.line);
+        self.run(0, &line)
+    }
+
+    /// Run `line` through the filters from `stage` on.
+    fn run(&mut self, stage: usize, line: &str) -> fmt::Result {
+        let mut line = line;
+        let mut selected: String<LINE_LEN>;
+        for filter in self.filters[stage..].iter_mut() {
+            match filter {
+                Filter::Grep { text, invert } => {
+                    if line.contains(*text) == *invert {
+                        return Ok(());
+                    }
+                }
+                Filter::Head { left } => {
+                    if *left == 0 {
+                        return Ok(());
+                    }
+                    *left -= 1;
+                }
+                Filter::Tail { keep } => {
+                    if self.tail.len() == *keep {
+                        self.tail.pop_front();
+                    }
+                    let _ = self.tail.push_back(String::from(line));
+                    return Ok(());
+                }
+                Filter::Count { lines } => {
+                    *lines += 1;
+                    return Ok(());
+                }
+                Filter::Field(fields) => {
+                    let mut picked: String<LINE_LEN> = String::new();
+                    for n in fields.iter() {
+                        if let Some(field) = line.split(SEPARATOR).nth(*n as usize - 1) {
+                            if !picked.is_empty() {
+                                let _ = picked.push(SEPARATOR);
+                            }
+                            let _ = picked.push_str(field);
+                        }
+                    }
+                    selected = picked;
+                    line = &selected;
+                }
+            }
+        }
+        self.emit(line)
+    }
+
+    fn emit(&mut self, line: &str) -> fmt::Result {
+        if self.mid_line {
+            self.out.write_str("\r\n")?;
+        }
+        self.mid_line = true;
+        self.out.write_str(line)
+    }
+}
+
+impl<'a, 'w> fmt::Write for Pipeline<'a, 'w> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.filters.is_empty() {
+            if !s.is_empty() {
+                self.mid_line = !s.ends_with('\n');
+            }
+            return self.out.write_str(s);
+        }
+        if self.done() {
+            return Err(fmt::Error);
+        }
+        for ch in s.chars() {
+            match ch {
+                '\r' => {}
+                '\n' => self.end_line()?,
+                ch => {
+                    if self.line.push(ch).is_err() {
+                        self.end_line()?;
+                        let _ = self.line.push(ch);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'w> CommandOutput for Pipeline<'a, 'w> {
+    fn poll_room(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()> {
+        if self.filters.is_empty() {
+            return self.out.poll_room(cx, len);
+        }
+        //at most the line held back and this, after a line end
+        self.out.poll_room(cx, self.line.len() + len + 2)
+    }
+}
@@ -0,0 +1,266 @@
+//! History kept in a NOR flash region, so it survives resets.
+//!
+//! The region is a ring of erase sectors filled with append-only records:
+//!
+//! ```text
+//! | magic | len | crc16 (le) | seq (le, u32) | command | 0xff padding |
+//! ```
+//!
+//! A sector is only erased when the ring wraps around into it, which drops
+//! the oldest records. Records are replayed into a [`LRUHistory`] on start,
+//! so navigation and search never touch the flash.
+
+use core::str::from_utf8;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::heapless::String;
+use crate::history::{History, LRUHistory};
+
+const MAGIC: u8 = 0xa5;
+const ERASED: u8 = 0xff;
+const HEADER_LEN: usize = 8;
+const MAX_RECORD_LEN: usize = HEADER_LEN + u8::MAX as usize + 8;
+
+pub struct FlashHistory<F: NorFlash, const CMD_LEN: usize, const CAP: usize> {
+    flash: F,
+    start: u32,
+    end: u32,
+    /// sector `write_pos` belongs to, which may be its end
+    write_sector: u32,
+    write_pos: u32,
+    next_seq: u32,
+    cache: LRUHistory<CMD_LEN, CAP>,
+}
+
+/// A valid record, and where the next one starts.
+struct Record {
+    seq: u32,
+    len: usize,
+    next: u32,
+}
+
+impl<F: NorFlash, const CMD_LEN: usize, const CAP: usize> FlashHistory<F, CMD_LEN, CAP> {
+    /// Use `start..end` of `flash`, both aligned to `F::ERASE_SIZE`, and load
+    /// the history stored there. `F::WRITE_SIZE` must not exceed 8 bytes.
+    pub fn new(flash: F, start: u32, end: u32) -> Self {
+        let mut history = Self {
+            flash,
+            start,
+            end,
+            write_sector: 0,
+            write_pos: start,
+            next_seq: 0,
+            cache: LRUHistory::default(),
+        };
+        history.load();
+        history
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn sector_size() -> u32 {
+        F::ERASE_SIZE as u32
+    }
+
+    fn sectors(&self) -> u32 {
+        (self.end - self.start) / Self::sector_size()
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+        self.start + sector * Self::sector_size()
+    }
+
+    fn record_len(len: usize) -> u32 {
+        let align = F::WRITE_SIZE.max(F::READ_SIZE).max(1);
+        (((HEADER_LEN + len) + align - 1) / align * align) as u32
+    }
+
+    /// Read the record at `pos`. `Ok(None)` is erased flash, `Err(())` is
+    /// anything that is neither erased nor a valid record.
+    fn read_record(&mut self, pos: u32, sector_end: u32, data: &mut [u8; MAX_RECORD_LEN]) -> Result<Option<Record>, ()> {
+        if pos + Self::record_len(0) > sector_end {
+            return Ok(None);
+        }
+        let header_len = Self::record_len(0) as usize;
+        self.flash.read(pos, &mut data[..header_len]).map_err(|_| ())?;
+        if data[0] == ERASED {
+            return Ok(None);
+        }
+        let len = data[1] as usize;
+        let next = pos + Self::record_len(len);
+        if data[0] != MAGIC || len > CMD_LEN || next > sector_end {
+            return Err(());
+        }
+        self.flash.read(pos, &mut data[..(next - pos) as usize]).map_err(|_| ())?;
+        let crc = u16::from_le_bytes([data[2], data[3]]);
+        if crc != crc16(&data[4..HEADER_LEN + len]) {
+            return Err(());
+        }
+        let seq = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        Ok(Some(Record { seq, len, next }))
+    }
+
+    /// Walk the valid records of `sector`, returns where free space starts,
+    /// or the sector end if it holds garbage.
+    fn scan(&mut self, sector: u32, data: &mut [u8; MAX_RECORD_LEN], mut f: impl FnMut(&Record)) -> u32 {
+        let sector_end = self.sector_start(sector) + Self::sector_size();
+        let mut pos = self.sector_start(sector);
+        loop {
+            match self.read_record(pos, sector_end, data) {
+                Ok(Some(record)) => {
+                    f(&record);
+                    pos = record.next;
+                }
+                Ok(None) => return pos,
+                Err(()) => return sector_end,
+            }
+        }
+    }
+
+    fn load(&mut self) {
+        let mut data = [0; MAX_RECORD_LEN];
+        let sectors = self.sectors();
+        if sectors == 0 {
+            return;
+        }
+
+        // the sector holding the newest record is where writing continues
+        let mut head: Option<(u32, u32)> = None;
+        for sector in 0..sectors {
+            let mut last_seq = None;
+            let tail = self.scan(sector, &mut data, |record| last_seq = Some(record.seq));
+            if let Some(seq) = last_seq {
+                //newer when ahead by less than half the range, seq wraps
+                if head.map_or(true, |(_, newest)| seq.wrapping_sub(newest) as i32 > 0) {
+                    head = Some((sector, seq));
+                    self.write_sector = sector;
+                    self.write_pos = tail;
+                    self.next_seq = seq.wrapping_add(1);
+                }
+            }
+        }
+        let head = match head {
+            Some((sector, _)) => sector,
+            None => {
+                self.write_sector = 0;
+                self.write_pos = self.start;
+                // nothing valid: make sure writing starts on erased flash
+                let _ = self.flash.erase(self.start, self.start + Self::sector_size());
+                return;
+            }
+        };
+
+        // replay from the oldest sector, the one after head
+        for i in 1..=sectors {
+            let sector = (head + i) % sectors;
+            let sector_end = self.sector_start(sector) + Self::sector_size();
+            let mut pos = self.sector_start(sector);
+            while let Ok(Some(record)) = self.read_record(pos, sector_end, &mut data) {
+                if let Ok(command) = from_utf8(&data[HEADER_LEN..HEADER_LEN + record.len]) {
+                    let _ = self.cache.push(command);
+                }
+                pos = record.next;
+            }
+        }
+    }
+
+    fn append(&mut self, command: &str) -> Result<(), ()> {
+        let bytes = command.as_bytes();
+        if bytes.len() > u8::MAX as usize || self.sectors() == 0 {
+            return Err(());
+        }
+        let record_len = Self::record_len(bytes.len());
+        let sector_end = self.sector_start(self.write_sector) + Self::sector_size();
+        if self.write_pos + record_len > sector_end {
+            // move on to the next sector, dropping the oldest records
+            self.write_sector = (self.write_sector + 1) % self.sectors();
+            self.write_pos = self.sector_start(self.write_sector);
+            self.flash
+                .erase(self.write_pos, self.write_pos + Self::sector_size())
+                .map_err(|_| ())?;
+        }
+
+        let mut data = [ERASED; MAX_RECORD_LEN];
+        data[0] = MAGIC;
+        data[1] = bytes.len() as u8;
+        data[4..HEADER_LEN].copy_from_slice(&self.next_seq.to_le_bytes());
+        data[HEADER_LEN..HEADER_LEN + bytes.len()].copy_from_slice(bytes);
+        let crc = crc16(&data[4..HEADER_LEN + bytes.len()]);
+        data[2..4].copy_from_slice(&crc.to_le_bytes());
+
+        let pos = self.write_pos;
+        // skip the slot even if the write fails half way
+        self.write_pos += record_len;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.flash
+            .write(pos, &data[..record_len as usize])
+            .map_err(|_| ())
+    }
+}
+
+impl<F: NorFlash, const CMD_LEN: usize, const CAP: usize> History<CMD_LEN> for FlashHistory<F, CMD_LEN, CAP> {
+    fn reset(&mut self) {
+        self.cache.reset();
+        self.write_sector = 0;
+        self.write_pos = self.start;
+        self.next_seq = 0;
+        let _ = self.flash.erase(self.start, self.end);
+    }
+
+    fn push(&mut self, command: &str) -> Result<(), ()> {
+        if command.len() == 0 {
+            return Ok(());
+        }
+        // repeating the last command does not cost a flash write
+        let repeated = self.cache.newest() == Some(command);
+        self.cache.push(command)?;
+        if repeated {
+            Ok(())
+        } else {
+            self.append(command)
+        }
+    }
+
+    fn go_back(&mut self) -> Option<String<CMD_LEN>> {
+        self.cache.go_back()
+    }
+
+    fn go_forward(&mut self) -> Option<String<CMD_LEN>> {
+        self.cache.go_forward()
+    }
+
+    fn search(&self, pattern: &str, from: usize, backward: bool) -> Option<(usize, String<CMD_LEN>)> {
+        self.cache.search(pattern, from, backward)
+    }
+
+    fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    fn nth(&self, idx: usize) -> Option<(usize, String<CMD_LEN>)> {
+        self.cache.nth(idx)
+    }
+}
+
+/// CRC-16/CCITT-FALSE
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xffff, data)
+}
+
+/// Go on with the CRC of more data, starting from `crc16` of the first part.
+pub(crate) fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,165 @@
+//! Scripts kept in a NOR flash region, so they survive resets.
+//!
+//! Every script has a slot of its own, one after the other from the start
+//! of the region:
+//!
+//! ```text
+//! | magic | name len | text len (le) | crc16 (le) | 0xff 0xff | name, 0xff padded | text |
+//! ```
+//!
+//! Scripts change seldom, so every change erases the region and writes all
+//! slots again. The scripts are loaded into RAM on start, running one never
+//! touches the flash.
+
+use core::cell::RefCell;
+use core::str::from_utf8;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::flash_history::{crc16, crc16_update};
+use crate::script::{ScriptError, ScriptStore, ScriptTable, NAME_LEN};
+
+const MAGIC: u8 = 0x5c;
+const ERASED: u8 = 0xff;
+const HEADER_LEN: usize = 8;
+
+struct Region<F: NorFlash, const N: usize, const LEN: usize> {
+    flash: F,
+    start: u32,
+    table: ScriptTable<N, LEN>,
+}
+
+/// Up to `N` scripts of at most `LEN` bytes in flash, see [`ScriptStore`].
+pub struct FlashScripts<M: RawMutex, F: NorFlash, const N: usize, const LEN: usize> {
+    region: Mutex<M, RefCell<Region<F, N, LEN>>>,
+}
+
+impl<M: RawMutex, F: NorFlash, const N: usize, const LEN: usize> FlashScripts<M, F, N, LEN> {
+    /// Use [`FlashScripts::region_len`] bytes of `flash` from `start`,
+    /// aligned to `F::ERASE_SIZE`, and load the scripts stored there.
+    /// `F::WRITE_SIZE` and `F::READ_SIZE` must divide 8, and `LEN` must be
+    /// a multiple of 8.
+    pub fn new(flash: F, start: u32) -> Self {
+        let mut region = Region {
+            flash,
+            start,
+            table: ScriptTable::new(),
+        };
+        region.load();
+        Self {
+            region: Mutex::new(RefCell::new(region)),
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.region.into_inner().into_inner().flash
+    }
+
+    /// Flash the scripts take, whole erase sectors.
+    pub const fn region_len() -> u32 {
+        Region::<F, N, LEN>::REGION_LEN
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Region<F, N, LEN>) -> R) -> R {
+        self.region.lock(|region| f(&mut region.borrow_mut()))
+    }
+}
+
+impl<F: NorFlash, const N: usize, const LEN: usize> Region<F, N, LEN> {
+    const SLOT_LEN: usize = HEADER_LEN + NAME_LEN + LEN;
+    const REGION_LEN: u32 = ((N * Self::SLOT_LEN + F::ERASE_SIZE - 1) / F::ERASE_SIZE * F::ERASE_SIZE) as u32;
+
+    fn slot(&self, idx: usize) -> u32 {
+        self.start + (idx * Self::SLOT_LEN) as u32
+    }
+
+    fn load(&mut self) {
+        let mut header = [0; HEADER_LEN];
+        let mut name = [0; NAME_LEN];
+        let mut text = [0; LEN];
+        for idx in 0..N {
+            let pos = self.slot(idx);
+            if self.flash.read(pos, &mut header).is_err() || header[0] != MAGIC {
+                //slots are written in order, the first empty one is the end
+                return;
+            }
+            let name_len = header[1] as usize;
+            let text_len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            if name_len > NAME_LEN || text_len > LEN {
+                continue;
+            }
+            let text_pos = pos + (HEADER_LEN + NAME_LEN) as u32;
+            if self.flash.read(pos + HEADER_LEN as u32, &mut name).is_err()
+                || self.flash.read(text_pos, &mut text[..align(text_len)]).is_err()
+            {
+                continue;
+            }
+            let crc = crc16_update(crc16(&name[..name_len]), &text[..text_len]);
+            if crc != u16::from_le_bytes([header[4], header[5]]) {
+                continue;
+            }
+            if let (Ok(name), Ok(text)) = (from_utf8(&name[..name_len]), from_utf8(&text[..text_len])) {
+                let _ = self.table.save(name, text);
+            }
+        }
+    }
+
+    /// Write the whole table again.
+    fn store(&mut self) -> Result<(), ScriptError> {
+        let end = self.start + Self::REGION_LEN;
+        self.flash.erase(self.start, end).map_err(|_| ScriptError::StoreError)?;
+        for (idx, (name, text)) in self.table.scripts.iter().enumerate() {
+            let pos = self.slot(idx);
+            let mut head = [ERASED; HEADER_LEN + NAME_LEN];
+            head[0] = MAGIC;
+            head[1] = name.len() as u8;
+            head[2..4].copy_from_slice(&(text.len() as u16).to_le_bytes());
+            let crc = crc16_update(crc16(name.as_bytes()), text.as_bytes());
+            head[4..6].copy_from_slice(&crc.to_le_bytes());
+            head[HEADER_LEN..HEADER_LEN + name.len()].copy_from_slice(name.as_bytes());
+            let mut body = [ERASED; LEN];
+            body[..text.len()].copy_from_slice(text.as_bytes());
+            self.flash.write(pos, &head).map_err(|_| ScriptError::StoreError)?;
+            self.flash
+                .write(pos + head.len() as u32, &body[..align(text.len())])
+                .map_err(|_| ScriptError::StoreError)?;
+        }
+        Ok(())
+    }
+}
+
+/// Round `len` up to 8 bytes, the largest read and write size supported.
+fn align(len: usize) -> usize {
+    (len + 7) / 8 * 8
+}
+
+impl<M: RawMutex, F: NorFlash, const N: usize, const LEN: usize> ScriptStore for FlashScripts<M, F, N, LEN> {
+    fn load(&self, name: &str, buf: &mut [u8]) -> Option<usize> {
+        self.with(|region| region.table.load(name, buf))
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.with(|region| region.table.contains(name))
+    }
+
+    /// The script is kept in RAM even if writing it to flash fails.
+    fn save(&self, name: &str, text: &str) -> Result<(), ScriptError> {
+        self.with(|region| {
+            region.table.save(name, text)?;
+            region.store()
+        })
+    }
+
+    fn remove(&self, name: &str) -> Result<(), ScriptError> {
+        self.with(|region| {
+            region.table.remove(name)?;
+            region.store()
+        })
+    }
+
+    fn each(&self, f: &mut dyn FnMut(&str, &str)) {
+        self.with(|region| region.table.each(f))
+    }
+}
@@ -1,3 +1,4 @@
+use core::fmt;
 use core::str::FromStr;
 
 use crate::heapless::String;
@@ -8,6 +9,15 @@ pub trait History<const CMD_LEN: usize> {
     fn push(&mut self, command: &str) -> Result<(), ()>;
     fn go_back(&mut self) -> Option<String<CMD_LEN>>;
     fn go_forward(&mut self) -> Option<String<CMD_LEN>>;
+    /// Find an entry containing `pattern`, starting at entry `from` and going
+    /// to older entries, or to newer ones when `backward` is false. Entries are
+    /// numbered from 0, the newest. Returns the number and the entry.
+    fn search(&self, pattern: &str, from: usize, backward: bool) -> Option<(usize, String<CMD_LEN>)>;
+    /// Number of entries.
+    fn len(&self) -> usize;
+    /// Entry `idx`, 0 is the newest, with its history number. History numbers
+    /// start at 1, grow with every push and do not change while the entry is kept.
+    fn nth(&self, idx: usize) -> Option<(usize, String<CMD_LEN>)>;
 }
 
 pub struct NoHistory;
@@ -26,12 +36,30 @@ impl<const CMD_LEN: usize> History<CMD_LEN> for NoHistory {
     fn go_forward(&mut self) -> Option<String<CMD_LEN>> {
         None
     }
+
+    fn search(&self, _pattern: &str, _from: usize, _backward: bool) -> Option<(usize, String<CMD_LEN>)> {
+        None
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn nth(&self, _idx: usize) -> Option<(usize, String<CMD_LEN>)> {
+        None
+    }
+}
+
+struct Entry<const CMD_LEN: usize> {
+    number: usize,
+    line: String<CMD_LEN>,
 }
 
 #[derive(Default)]
 pub struct LRUHistory<const CMD_LEN: usize, const CAP: usize> {
-    history: LRUCache<String<CMD_LEN>, CAP>,
+    history: LRUCache<Entry<CMD_LEN>, CAP>,
     cursor: usize,
+    next_number: usize,
 }
 
 impl<const CMD_LEN: usize, const CAP: usize> History<CMD_LEN> for LRUHistory<CMD_LEN, CAP> {
@@ -42,9 +70,15 @@ impl<const CMD_LEN: usize, const CAP: usize> History<CMD_LEN> for LRUHistory<CMD
 
     fn push(&mut self, command: &str) -> Result<(), ()> {
         if command.len() > 0 && CAP > 0 {
-            if self.history.find(|item| item.as_str() == command).is_none() {
-                let history_entry = String::from_str(command)?;
-                self.history.insert(history_entry);
+            // a repeated command moves to the front and gets a new number
+            self.next_number += 1;
+            let number = self.next_number;
+            match self.history.find(|item| item.line.as_str() == command) {
+                Some(item) => item.number = number,
+                None => {
+                    let line = String::from_str(command)?;
+                    self.history.insert(Entry { number, line });
+                }
             }
         }
         self.cursor = 0;
@@ -57,17 +91,139 @@ impl<const CMD_LEN: usize, const CAP: usize> History<CMD_LEN> for LRUHistory<CMD
         } else {
             let cursor = self.cursor;
             self.cursor += 1;
-            self.history.get(cursor).cloned()
+            self.history.get(cursor).map(|item| item.line.clone())
         }
     }
 
     fn go_forward(&mut self) -> Option<String<CMD_LEN>> {
-        if self.cursor == 0 || self.history.len() == 0 {
+        // `cursor` counts the entries stepped back, the shown one is cursor - 1
+        if self.cursor <= 1 || self.history.len() == 0 {
+            self.cursor = 0;
             None
         } else {
             self.cursor -= 1;
-            let cursor = self.cursor;
-            self.history.get(cursor).cloned()
+            let cursor = self.cursor - 1;
+            self.history.get(cursor).map(|item| item.line.clone())
+        }
+    }
+
+    fn search(&self, pattern: &str, from: usize, backward: bool) -> Option<(usize, String<CMD_LEN>)> {
+        let len = self.history.len();
+        let found = if backward {
+            (from..len).find(|idx| self.matches(*idx, pattern))
+        } else {
+            (0..=from.min(len.checked_sub(1)?)).rev().find(|idx| self.matches(*idx, pattern))
+        };
+        found.and_then(|idx| self.history.get(idx).map(|item| (idx, item.line.clone())))
+    }
+
+    fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    fn nth(&self, idx: usize) -> Option<(usize, String<CMD_LEN>)> {
+        self.history.get(idx).map(|item| (item.number, item.line.clone()))
+    }
+}
+
+impl<const CMD_LEN: usize, const CAP: usize> LRUHistory<CMD_LEN, CAP> {
+    /// The most recently pushed command.
+    pub fn newest(&self) -> Option<&str> {
+        self.history.get(0).map(|item| item.line.as_str())
+    }
+
+    fn matches(&self, idx: usize, pattern: &str) -> bool {
+        self.history
+            .get(idx)
+            .map_or(false, |item| item.line.as_str().contains(pattern))
+    }
+}
+
+/// Why [`expand`] failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpandError {
+    /// `!n`, `!prefix` or `^old^new` did not match any entry.
+    EventNotFound,
+    /// The expanded line does not fit in `CMD_LEN`.
+    TooLong,
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpandError::EventNotFound => write!(f, "event not found"),
+            ExpandError::TooLong => write!(f, "expanded line too long"),
+        }
+    }
+}
+
+/// Expand history references in `line`, like sh:
+///
+/// - `!!` the last command, `!n` entry number `n`, `!-n` the n-th last command
+/// - `!prefix` the last command starting with `prefix`
+/// - `^old^new` the last command with the first `old` replaced by `new`
+///
+/// `!` followed by a blank, `=`, `(` or the end of the line, and anything in
+/// single quotes, is left alone. Returns `None` when nothing was expanded.
+pub fn expand<const CMD_LEN: usize>(
+    line: &str,
+    history: &impl History<CMD_LEN>,
+) -> Result<Option<String<CMD_LEN>>, ExpandError> {
+    let mut out: String<CMD_LEN> = String::new();
+
+    if let Some(rest) = line.strip_prefix('^') {
+        let (old, new) = rest.split_once('^').ok_or(ExpandError::EventNotFound)?;
+        let new = new.strip_suffix('^').unwrap_or(new);
+        let (_, last) = history.nth(0).ok_or(ExpandError::EventNotFound)?;
+        let (head, tail) = last.split_once(old).ok_or(ExpandError::EventNotFound)?;
+        for part in [head, new, tail] {
+            out.push_str(part).map_err(|_| ExpandError::TooLong)?;
+        }
+        return Ok(Some(out));
+    }
+
+    let mut expanded = false;
+    let mut quoted = false;
+    let mut rest = line;
+    while let Some(pos) = rest.find(|c| c == '!' || c == '\'') {
+        let (head, tail) = rest.split_at(pos);
+        out.push_str(head).map_err(|_| ExpandError::TooLong)?;
+        let event = &tail[1..];
+        let word_len = event
+            .find(|c: char| c.is_ascii_whitespace() || c == ';' || c == '|' || c == '&')
+            .unwrap_or(event.len());
+        let word = &event[..word_len];
+        if tail.starts_with('\'') || quoted || word.is_empty() || word.starts_with(['=', '(']) {
+            quoted ^= tail.starts_with('\'');
+            out.push_str(&tail[..1]).map_err(|_| ExpandError::TooLong)?;
+            rest = event;
+            continue;
         }
+
+        let (word_len, entry) = if word.starts_with('!') {
+            (1, history.nth(0))
+        } else if let Ok(n) = word.parse::<isize>() {
+            let entry = match n {
+                n if n < 0 => history.nth(n.unsigned_abs() - 1),
+                n => (0..history.len())
+                    .filter_map(|idx| history.nth(idx))
+                    .find(|(number, _)| *number == n as usize),
+            };
+            (word_len, entry)
+        } else {
+            let entry = (0..history.len())
+                .filter_map(|idx| history.nth(idx))
+                .find(|(_, line)| line.starts_with(word));
+            (word_len, entry)
+        };
+        let (_, entry) = entry.ok_or(ExpandError::EventNotFound)?;
+        out.push_str(&entry).map_err(|_| ExpandError::TooLong)?;
+        expanded = true;
+        rest = &event[word_len..];
+    }
+    if !expanded {
+        return Ok(None);
     }
+    out.push_str(rest).map_err(|_| ExpandError::TooLong)?;
+    Ok(Some(out))
 }
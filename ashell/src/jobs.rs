@@ -0,0 +1,250 @@
+//! Background jobs, started with a trailing `&`.
+//!
+//! [`Jobs`] is a fixed table of command lines. The shell books a line in it
+//! and goes on reading input, while [`Jobs::run`] loops, spawned next to the
+//! shell, pick booked lines up and run them in an environment of their own.
+//! The `jobs`, `fg` and `kill` built-ins go through [`JobControl`].
+
+use core::cell::RefCell;
+use core::fmt;
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::MultiWakerRegistration;
+
+use crate::heapless::{String, Vec};
+use crate::filter::{split_pipeline, Pipeline};
+use crate::output::CommandOutput;
+use crate::tokenizer::tokenize;
+use crate::ArgvEnvironment;
+
+/// Tasks waiting on a job table at once: runners, running jobs and `fg`.
+const WAITERS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobError {
+    /// The shell has no job table.
+    NoJobControl,
+    /// Every slot of the table is taken.
+    TableFull,
+    /// The line does not fit a slot.
+    TooLong,
+    /// No job with that number.
+    NoSuchJob,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JobError::NoJobControl => "no job control",
+            JobError::TableFull => "too many jobs",
+            JobError::TooLong => "line too long",
+            JobError::NoSuchJob => "no such job",
+        })
+    }
+}
+
+/// What the shell needs from a job table, without its sizes.
+pub trait JobControl {
+    /// Book `line` to run in the background, returns its job number.
+    fn start(&self, line: &str) -> Result<u16, JobError>;
+
+    /// Stop job `number`: a waiting job is dropped, a running one cancelled.
+    fn kill(&self, number: u16) -> Result<(), JobError>;
+
+    /// Number of the newest job, if any.
+    fn last(&self) -> Option<u16>;
+
+    /// Write the command line of job `number`.
+    fn describe(&self, number: u16, out: &mut dyn fmt::Write) -> Result<(), JobError>;
+
+    /// One line per job, oldest first, `[n] Running  line`. Lines are
+    /// separated by `\r\n`, the last one is not ended.
+    fn list(&self, out: &mut dyn fmt::Write) -> fmt::Result;
+
+    /// Ready once job `number` has left the table.
+    fn poll_gone(&self, number: u16, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+struct Job<const LEN: usize> {
+    number: u16,
+    line: String<LEN>,
+    running: bool,
+    killed: bool,
+}
+
+struct Table<const N: usize, const LEN: usize> {
+    jobs: Vec<Job<LEN>, N>,
+    next: u16,
+    waiters: MultiWakerRegistration<WAITERS>,
+}
+
+impl<const N: usize, const LEN: usize> Table<N, LEN> {
+    fn find(&mut self, number: u16) -> Option<&mut Job<LEN>> {
+        self.jobs.iter_mut().find(|job| job.number == number)
+    }
+
+    /// Something changed, every waiter looks again.
+    fn changed(&mut self) {
+        self.waiters.wake();
+    }
+}
+
+/// Up to `N` jobs of at most `LEN` bytes each, shared by a shell and its
+/// runners, so usually a `static`.
+pub struct Jobs<M: RawMutex, const N: usize, const LEN: usize> {
+    table: Mutex<M, RefCell<Table<N, LEN>>>,
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> Jobs<M, N, LEN> {
+    pub const fn new() -> Self {
+        Self {
+            table: Mutex::new(RefCell::new(Table {
+                jobs: Vec::new(),
+                next: 1,
+                waiters: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Table<N, LEN>) -> R) -> R {
+        self.table.lock(|table| f(&mut table.borrow_mut()))
+    }
+
+    /// Run booked jobs in `env` one after the other, forever. Their output,
+    /// and a `[n] Done  line` note when one ends, goes to `out`.
+    ///
+    /// Each loop runs one job at a time, spawn as many as jobs should run
+    /// side by side.
+    pub async fn run<const ARGC: usize>(&self, env: &mut impl ArgvEnvironment, out: &mut dyn CommandOutput) -> ! {
+        loop {
+            let (number, line) = poll_fn(|cx| self.poll_take(cx)).await;
+            let mut buf = [0u8; LEN];
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            let mut mid_line = false;
+            let ended = match tokenize::<ARGC>(&mut buf[..line.len()]) {
+                Ok(argv) => match split_pipeline(&argv) {
+                    Ok((argv, filters)) => {
+                        let mut pipeline = Pipeline::new(filters, out);
+                        let killed = poll_fn(|cx| self.poll_killed(number, cx));
+                        let ended = select(env.command(argv, &mut pipeline), killed).await;
+                        let ended = match ended {
+                            Either::First(Ok(())) => "Done",
+                            Either::First(Err(_)) if pipeline.done() => "Done",
+                            Either::First(Err(_)) => "Failed",
+                            Either::Second(()) => "Killed",
+                        };
+                        let _ = pipeline.finish();
+                        mid_line = pipeline.mid_line();
+                        ended
+                    }
+                    Err(_) => "Failed",
+                },
+                Err(_) => "Failed",
+            };
+            self.with(|table| {
+                table.jobs.retain(|job| job.number != number);
+                table.changed();
+            });
+            //the note gets a line of its own
+            let sep = if mid_line { "\r\n" } else { "" };
+            let _ = write!(out, "{}[{}] {}  {}\r\n", sep, number, ended, line);
+        }
+    }
+
+    /// Take the oldest waiting job and mark it running.
+    fn poll_take(&self, cx: &mut Context<'_>) -> Poll<(u16, String<LEN>)> {
+        self.with(|table| match table.jobs.iter_mut().find(|job| !job.running) {
+            Some(job) => {
+                job.running = true;
+                Poll::Ready((job.number, job.line.clone()))
+            }
+            None => {
+                let _ = table.waiters.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    fn poll_killed(&self, number: u16, cx: &mut Context<'_>) -> Poll<()> {
+        self.with(|table| match table.find(number) {
+            Some(job) if !job.killed => {
+                let _ = table.waiters.register(cx.waker());
+                Poll::Pending
+            }
+            _ => Poll::Ready(()),
+        })
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> Default for Jobs<M, N, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> JobControl for Jobs<M, N, LEN> {
+    fn start(&self, line: &str) -> Result<u16, JobError> {
+        let mut text = String::new();
+        text.push_str(line).map_err(|_| JobError::TooLong)?;
+        self.with(|table| {
+            let number = table.next;
+            table
+                .jobs
+                .push(Job { number, line: text, running: false, killed: false })
+                .map_err(|_| JobError::TableFull)?;
+            table.next = table.next.wrapping_add(1).max(1);
+            table.changed();
+            Ok(number)
+        })
+    }
+
+    fn kill(&self, number: u16) -> Result<(), JobError> {
+        self.with(|table| {
+            let job = table.find(number).ok_or(JobError::NoSuchJob)?;
+            if job.running {
+                job.killed = true;
+            } else {
+                table.jobs.retain(|job| job.number != number);
+            }
+            table.changed();
+            Ok(())
+        })
+    }
+
+    fn last(&self) -> Option<u16> {
+        self.with(|table| table.jobs.last().map(|job| job.number))
+    }
+
+    fn describe(&self, number: u16, out: &mut dyn fmt::Write) -> Result<(), JobError> {
+        self.with(|table| {
+            let job = table.find(number).ok_or(JobError::NoSuchJob)?;
+            let _ = out.write_str(&job.line);
+            Ok(())
+        })
+    }
+
+    fn list(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        self.with(|table| {
+            for (idx, job) in table.jobs.iter().enumerate() {
+                let sep = if idx == 0 { "" } else { "\r\n" };
+                let state = if job.running { "Running" } else { "Waiting" };
+                write!(out, "{}[{}] {}  {}", sep, job.number, state, job.line)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn poll_gone(&self, number: u16, cx: &mut Context<'_>) -> Poll<()> {
+        self.with(|table| match table.find(number) {
+            Some(_) => {
+                let _ = table.waiters.register(cx.waker());
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        })
+    }
+}
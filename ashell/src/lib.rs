@@ -9,19 +9,34 @@ extern crate heapless;
 extern crate nb;
 extern crate uluru;
 extern crate embedded_io;
+extern crate embedded_storage;
+extern crate embassy_futures;
 
 use core::{fmt, str::Utf8Error};
 use embedded_io::asynch::{Read as AsyncRead, Write as AsyncWrite};
 
+pub mod alias;
 pub mod autocomplete;
+pub mod command;
 pub mod control;
+pub mod filter;
+pub mod flash_history;
+pub mod flash_script;
 pub mod history;
+pub mod jobs;
+pub mod machine;
+pub mod output;
+pub mod scpi;
+pub mod script;
+pub mod tokenizer;
+pub mod vars;
 
 mod shell;
 
 pub use shell::*;
 pub type ShellResult = Result<(), ShellError>;
 
+#[derive(Debug)]
 pub enum ShellError 
 {
     ReadError,
@@ -32,6 +47,57 @@ pub enum ShellError
     FormatError(fmt::Error),
     ExecuteError(i32),
     BadInputError(Utf8Error),
+    TokenizeError(tokenizer::TokenizeError),
+    ArgError(command::ArgError),
+    /// The command was cancelled with Ctrl-C.
+    Interrupted,
+    JobError(jobs::JobError),
+    FilterError(filter::FilterError),
+    VarError(vars::VarError),
+    ScriptError(script::ScriptError),
+}
+
+impl ShellError
+{
+    /// Exit status for `$?`, sh style: 127 for an unknown command, 130 for
+    /// Ctrl-C, 2 for a bad command line.
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::ExecuteError(status) => *status,
+            ShellError::CommandNotFound => 127,
+            ShellError::Interrupted => 130,
+            ShellError::TokenizeError(_)
+            | ShellError::ArgError(_)
+            | ShellError::FilterError(_)
+            | ShellError::VarError(_)
+            | ShellError::ScriptError(_)
+            | ShellError::JobError(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for ShellError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::ReadError => write!(f, "read error"),
+            ShellError::WriteError => write!(f, "write error"),
+            ShellError::HistoryError => write!(f, "history error"),
+            ShellError::CommandNotFound => write!(f, "command not found"),
+            ShellError::KeyNotFound => write!(f, "key not found"),
+            ShellError::FormatError(_) => write!(f, "output error"),
+            ShellError::ExecuteError(status) => write!(f, "failed with status {}", status),
+            ShellError::BadInputError(_) => write!(f, "invalid UTF-8"),
+            ShellError::TokenizeError(err) => write!(f, "{}", err),
+            ShellError::ArgError(err) => write!(f, "{}", err),
+            ShellError::Interrupted => write!(f, "interrupted"),
+            ShellError::JobError(err) => write!(f, "{}", err),
+            ShellError::FilterError(err) => write!(f, "{}", err),
+            ShellError::VarError(err) => write!(f, "{}", err),
+            ShellError::ScriptError(err) => write!(f, "{}", err),
+        }
+    }
 }
 
 impl From<Utf8Error> for ShellError
@@ -48,6 +114,20 @@ impl From<fmt::Error> for ShellError
     }
 }
 
+impl From<tokenizer::TokenizeError> for ShellError
+{
+    fn from(err:tokenizer::TokenizeError) -> Self {
+        ShellError::TokenizeError(err)
+    }
+}
+
+impl From<jobs::JobError> for ShellError
+{
+    fn from(err:jobs::JobError) -> Self {
+        ShellError::JobError(err)
+    }
+}
+
 impl From<i32> for ShellError
 {
     fn from(err:i32) -> Self {
@@ -65,6 +145,8 @@ pub enum Input<'a> {
     // S: AsyncRead + AsyncWrite,
     // A: autocomplete::Autocomplete<CMD_LEN>,
     // H: history::History<CMD_LEN>,
+/// Runs the lines of [`AShell::feed`] as they are: `cmd` is the first word
+/// and `args` the rest of the line. Output goes to `out`.
 pub trait Environment
 {
     async fn command(
@@ -72,6 +154,7 @@ pub trait Environment
         // shell: &mut AShell<A, H, CMD_LEN, LOG_SIZE>,
         cmd: &str,
         args: &str,
+        out: &mut dyn fmt::Write,
     ) -> ShellResult;
 
     async fn control(
@@ -81,6 +164,32 @@ pub trait Environment
     ) -> ShellResult;
 }
 
+/// Like [`Environment`], but the command line is already split into
+/// arguments by [`tokenizer::tokenize`]; `argv[0]` is the command name.
+/// Command output goes to `out`, the output of the shell that ran it; async
+/// commands can wait for room there with [`output::write_all`].
+pub trait ArgvEnvironment
+{
+    async fn command(&mut self, argv: &[&str], out: &mut dyn output::CommandOutput) -> ShellResult;
+
+    async fn control(&mut self, code: u8) -> ShellResult;
+}
+
+/// What a [`Source`] has for the shell.
+pub enum Event {
+    /// `n` bytes of user input.
+    Input(usize),
+    /// `n` bytes to print above the prompt, log lines for example.
+    Print(usize),
+}
+
+/// Everything that reaches one shell: user input and text to print,
+/// whichever comes first. Also read while a command runs, to catch Ctrl-C.
+pub trait Source
+{
+    async fn next(&mut self, buf: &mut [u8]) -> Event;
+}
+
 // pub struct Serial<T, TX: Write, RX: Read> {
 //     w: PhantomData<T>,
 //     tx: TX,
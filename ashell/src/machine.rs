@@ -0,0 +1,220 @@
+//! Machine mode: requests and framed responses on the shell port, for
+//! programs instead of people.
+//!
+//! The line `@machine` switches a shell to machine mode, `@interactive`
+//! back. In machine mode nothing is echoed, there is no prompt, no line
+//! editing, completion or history. Every line is a request:
+//!
+//! ```text
+//! [<seq>] <command line>
+//! ```
+//!
+//! Without a number first, the sequence number is the one of the last
+//! request plus one. Everything the shell sends back is a frame:
+//!
+//! ```text
+//! @<kind> <seq> <status> <len>\r\n<payload of len bytes>\r\n
+//! ```
+//!
+//! - `R` answers request `seq`, `status` is its exit status, what `$?`
+//!   holds, and the payload is its output, at most [`FRAME_LEN`] bytes
+//! - `L` is text nobody asked for, log lines and the output of background
+//!   jobs, with `seq` and `status` 0
+//!
+//! Switching to machine mode is answered with `@R 0 0 0`. Ctrl-C cancels
+//! the running request, which ends with status 130. Output longer than a
+//! frame is cut, run commands that print without end as a job, `cmd &`,
+//! their output comes in `L` frames.
+
+use core::fmt::{self, Write};
+use core::str::from_utf8;
+use core::task::{Context, Poll};
+
+use crate::heapless::{String, Vec};
+use crate::output::Output;
+
+pub const ENTER: &str = "@machine";
+pub const LEAVE: &str = "@interactive";
+/// Most output of one request.
+pub const FRAME_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Response,
+    Log,
+}
+
+/// The first line of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub kind: Kind,
+    pub seq: u32,
+    pub status: i32,
+    pub len: usize,
+}
+
+impl Header {
+    /// Read `@R 1 0 5`, without the line end.
+    pub fn parse(line: &[u8]) -> Option<Header> {
+        let line = from_utf8(line).ok()?;
+        let mut fields = line.strip_prefix('@')?.split(' ');
+        let kind = match fields.next()? {
+            "R" => Kind::Response,
+            "L" => Kind::Log,
+            _ => return None,
+        };
+        let header = Header {
+            kind,
+            seq: fields.next()?.parse().ok()?,
+            status: fields.next()?.parse().ok()?,
+            len: fields.next()?.parse().ok()?,
+        };
+        match fields.next() {
+            None => Some(header),
+            Some(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Header {
+    /// The header line, with its line end.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Response => 'R',
+            Kind::Log => 'L',
+        };
+        write!(f, "@{} {} {} {}\r\n", kind, self.seq, self.status, self.len)
+    }
+}
+
+/// The output of a shell. It passes as it is, except while a request in
+/// machine mode runs: then it is kept for the response frame, and text to
+/// print goes out in frames of its own.
+pub struct Framer<O: Output> {
+    out: O,
+    /// sequence number of the request running
+    request: Option<u32>,
+    payload: Vec<u8, FRAME_LEN>,
+}
+
+impl<O: Output> Framer<O> {
+    pub fn new(out: O) -> Self {
+        Self {
+            out,
+            request: None,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.out
+    }
+
+    /// Keep what is written as the response to request `seq`.
+    pub fn begin(&mut self, seq: u32) {
+        self.request = Some(seq);
+        self.payload.clear();
+    }
+
+    /// Send the response to the request begun, if any.
+    pub async fn end(&mut self, status: i32) {
+        if let Some(seq) = self.request.take() {
+            let header = Header {
+                kind: Kind::Response,
+                seq,
+                status,
+                len: self.payload.len(),
+            };
+            write_frame(&mut self.out, header, &self.payload).await;
+            self.payload.clear();
+        }
+    }
+
+    /// Send `text` in a log frame.
+    pub async fn log(&mut self, text: &[u8]) {
+        let header = Header {
+            kind: Kind::Log,
+            seq: 0,
+            status: 0,
+            len: text.len(),
+        };
+        write_frame(&mut self.out, header, text).await;
+    }
+
+    fn keep(&mut self, bytes: &[u8]) -> usize {
+        let n = bytes.len().min(FRAME_LEN - self.payload.len());
+        let _ = self.payload.extend_from_slice(&bytes[..n]);
+        n
+    }
+}
+
+async fn write_frame(out: &mut impl Output, header: Header, payload: &[u8]) {
+    let mut line: String<32> = String::new();
+    let _ = write!(line, "{}", header);
+    out.write_all(line.as_bytes()).await;
+    out.write_all(payload).await;
+    out.write_all(b"\r\n").await;
+}
+
+impl<O: Output> Output for Framer<O> {
+    async fn write_all(&mut self, bytes: &[u8]) {
+        match self.request {
+            Some(_) => {
+                self.keep(bytes);
+            }
+            None => self.out.write_all(bytes).await,
+        }
+    }
+
+    fn try_write(&mut self, bytes: &[u8]) -> usize {
+        match self.request {
+            //what does not fit is dropped, not left to the caller
+            Some(_) => {
+                self.keep(bytes);
+                bytes.len()
+            }
+            None => self.out.try_write(bytes),
+        }
+    }
+
+    fn room(&self) -> usize {
+        match self.request {
+            Some(_) => FRAME_LEN - self.payload.len(),
+            None => self.out.room(),
+        }
+    }
+
+    fn print(&mut self, bytes: &[u8]) -> usize {
+        if self.request.is_none() {
+            return self.out.try_write(bytes);
+        }
+        let header = Header {
+            kind: Kind::Log,
+            seq: 0,
+            status: 0,
+            len: bytes.len(),
+        };
+        let mut line: String<32> = String::new();
+        let _ = write!(line, "{}", header);
+        //a frame cut short would take the bytes after it for its payload
+        if self.out.room() < line.len() + bytes.len() + 2 {
+            return 0;
+        }
+        self.out.try_write(line.as_bytes());
+        self.out.try_write(bytes);
+        self.out.try_write(b"\r\n");
+        bytes.len()
+    }
+
+    async fn flush(&mut self) {
+        self.out.flush().await
+    }
+
+    fn poll_room(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()> {
+        match self.request {
+            //the payload is kept whole or cut, nothing to wait for
+            Some(_) => Poll::Ready(()),
+            None => self.out.poll_room(cx, len),
+        }
+    }
+}
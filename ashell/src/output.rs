@@ -0,0 +1,157 @@
+//! Where a shell writes its echo, prompt and command output.
+//!
+//! Every [`AShell`](crate::AShell) owns one [`Output`], so a shell on the
+//! UART and one on USB each get their own stream.
+
+use core::fmt;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Context, Poll};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::pipe::Pipe;
+use embedded_io::asynch::Write as AsyncWrite;
+
+use crate::heapless::Vec;
+
+pub trait Output {
+    /// Write all of `bytes`, waiting for room.
+    async fn write_all(&mut self, bytes: &[u8]);
+
+    /// Write what fits without waiting, returns how many bytes were taken.
+    fn try_write(&mut self, bytes: &[u8]) -> usize;
+
+    /// How many bytes [`Output::try_write`] takes now, at least.
+    fn room(&self) -> usize;
+
+    /// Like [`Output::try_write`], for text printed while a command runs
+    /// that is not its output, a log line for example.
+    fn print(&mut self, bytes: &[u8]) -> usize {
+        self.try_write(bytes)
+    }
+
+    /// Push out anything [`Output::try_write`] left buffered.
+    async fn flush(&mut self) {}
+
+    /// Ready once `len` bytes fit, or as many as ever will. An output that
+    /// can't wait is always ready.
+    fn poll_room(&mut self, _cx: &mut Context<'_>, _len: usize) -> Poll<()> {
+        Poll::Ready(())
+    }
+}
+
+/// Shell output into an embassy pipe, read by a transport task.
+impl<M: RawMutex, const N: usize> Output for &Pipe<M, N> {
+    async fn write_all(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let n = self.write(bytes).await;
+            bytes = &bytes[n..];
+        }
+    }
+
+    fn try_write(&mut self, bytes: &[u8]) -> usize {
+        //a write stops at the end of the ring, the rest goes at its start
+        let mut n = 0;
+        while n < bytes.len() {
+            match Pipe::try_write(self, &bytes[n..]) {
+                Ok(taken) if taken > 0 => n += taken,
+                _ => break,
+            }
+        }
+        n
+    }
+
+    fn room(&self) -> usize {
+        self.free_capacity()
+    }
+
+    fn poll_room(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()> {
+        if self.free_capacity() >= len.min(N) {
+            return Poll::Ready(());
+        }
+        //an empty write only waits on a full pipe, with some room look again soon
+        if pin!(Pipe::write(self, &[])).poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+/// Shell output straight into an async writer, a UART tx half for example.
+///
+/// Writes that can't wait are kept in a buffer of `N` bytes until the next
+/// [`Output::write_all`] or [`Output::flush`]; what does not fit is dropped.
+pub struct IoOutput<W: AsyncWrite, const N: usize> {
+    io: W,
+    pending: Vec<u8, N>,
+}
+
+impl<W: AsyncWrite, const N: usize> IoOutput<W, N> {
+    pub fn new(io: W) -> Self {
+        Self {
+            io,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.io
+    }
+}
+
+impl<W: AsyncWrite, const N: usize> Output for IoOutput<W, N> {
+    async fn write_all(&mut self, bytes: &[u8]) {
+        self.flush().await;
+        let _ = self.io.write_all(bytes).await;
+    }
+
+    fn try_write(&mut self, bytes: &[u8]) -> usize {
+        let n = bytes.len().min(N - self.pending.len());
+        let _ = self.pending.extend_from_slice(&bytes[..n]);
+        n
+    }
+
+    fn room(&self) -> usize {
+        N - self.pending.len()
+    }
+
+    async fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.io.write_all(&self.pending).await;
+            self.pending.clear();
+        }
+        let _ = self.io.flush().await;
+    }
+}
+
+/// Where a command writes: `core::fmt::Write` for what fits now, and room to
+/// wait for, so async commands can stream more than fits at once.
+pub trait CommandOutput: fmt::Write {
+    /// Ready once `len` bytes fit, see [`Output::poll_room`].
+    fn poll_room(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()>;
+}
+
+/// Write `s` to `out` once there is room for it, for async commands.
+pub async fn write_all(out: &mut dyn CommandOutput, s: &str) -> fmt::Result {
+    poll_fn(|cx| out.poll_room(cx, s.len())).await;
+    out.write_str(s)
+}
+
+/// `core::fmt::Write` over an [`Output`], for `write!` and command handlers.
+/// What does not fit without waiting is an error.
+pub struct OutputWriter<'a, O: Output>(pub &'a mut O);
+
+impl<'a, O: Output> fmt::Write for OutputWriter<'a, O> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.0.try_write(s.as_bytes()) == s.len() {
+            true => Ok(()),
+            false => Err(fmt::Error),
+        }
+    }
+}
+
+impl<'a, O: Output> CommandOutput for OutputWriter<'a, O> {
+    fn poll_room(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()> {
+        self.0.poll_room(cx, len)
+    }
+}
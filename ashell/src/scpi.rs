@@ -0,0 +1,554 @@
+//! SCPI, the command language of test instruments, as an alternative to
+//! the shell commands: `*IDN?`, `MEAS:PWM:FREQ? (@0)`, `SYST:ERR?`.
+//!
+//! A line is a program message, commands separated by `;`. After the first
+//! one, a header without a leading `:` is relative to the node of the one
+//! before it, `CONF:PWMIN:THRES 20;THRES?` sets and reads one threshold.
+//! Mnemonics match in their short form, the upper case letters of the
+//! pattern, or in full, in any case. Nodes in `[ ]` may be left out.
+//!
+//! The common commands `*IDN?`, `*RST`, `*OPT?`, `*CLS`, `*ESR?`, `*OPC`,
+//! `*OPC?`, `*WAI` and `*TST?`, and `SYSTem:ERRor[:NEXT]?`,
+//! `SYSTem:ERRor:COUNt?` and `SYSTem:VERSion?` are handled here, an
+//! [`Instrument`] does the rest. Errors go to the error queue, read with
+//! `SYST:ERR?`, and stop the rest of the line. The answers to the queries
+//! of one line are separated by `;`.
+
+use core::fmt::{self, Write};
+use core::str::{from_utf8, Split};
+
+use crate::heapless::{Deque, String, Vec};
+use crate::{Environment, ShellResult};
+
+/// Longest program message.
+pub const LINE_LEN: usize = 128;
+/// The SCPI standard followed, for `SYSTem:VERSion?`.
+pub const SCPI_VERSION: &str = "1999.0";
+
+/// Bits of the standard event status register, `*ESR?`.
+const ESR_OPERATION_COMPLETE: u8 = 0x01;
+const ESR_QUERY_ERROR: u8 = 0x04;
+const ESR_DEVICE_ERROR: u8 = 0x08;
+const ESR_EXECUTION_ERROR: u8 = 0x10;
+const ESR_COMMAND_ERROR: u8 = 0x20;
+
+/// The standard errors of the error queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScpiError {
+    CommandError,
+    InvalidCharacter,
+    SyntaxError,
+    DataTypeError,
+    ParameterNotAllowed,
+    MissingParameter,
+    UndefinedHeader,
+    NumericDataError,
+    ExecutionError,
+    SettingsConflict,
+    DataOutOfRange,
+    IllegalParameterValue,
+    HardwareMissing,
+    QueueOverflow,
+    InputOverrun,
+}
+
+impl ScpiError {
+    pub fn code(&self) -> i16 {
+        match self {
+            ScpiError::CommandError => -100,
+            ScpiError::InvalidCharacter => -101,
+            ScpiError::SyntaxError => -102,
+            ScpiError::DataTypeError => -104,
+            ScpiError::ParameterNotAllowed => -108,
+            ScpiError::MissingParameter => -109,
+            ScpiError::UndefinedHeader => -113,
+            ScpiError::NumericDataError => -120,
+            ScpiError::ExecutionError => -200,
+            ScpiError::SettingsConflict => -221,
+            ScpiError::DataOutOfRange => -222,
+            ScpiError::IllegalParameterValue => -224,
+            ScpiError::HardwareMissing => -241,
+            ScpiError::QueueOverflow => -350,
+            ScpiError::InputOverrun => -363,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ScpiError::CommandError => "Command error",
+            ScpiError::InvalidCharacter => "Invalid character",
+            ScpiError::SyntaxError => "Syntax error",
+            ScpiError::DataTypeError => "Data type error",
+            ScpiError::ParameterNotAllowed => "Parameter not allowed",
+            ScpiError::MissingParameter => "Missing parameter",
+            ScpiError::UndefinedHeader => "Undefined header",
+            ScpiError::NumericDataError => "Numeric data error",
+            ScpiError::ExecutionError => "Execution error",
+            ScpiError::SettingsConflict => "Settings conflict",
+            ScpiError::DataOutOfRange => "Data out of range",
+            ScpiError::IllegalParameterValue => "Illegal parameter value",
+            ScpiError::HardwareMissing => "Hardware missing",
+            ScpiError::QueueOverflow => "Queue overflow",
+            ScpiError::InputOverrun => "Input buffer overrun",
+        }
+    }
+
+    /// The bit of `*ESR?` the error sets.
+    fn esr_bit(&self) -> u8 {
+        match self.code() {
+            -199..=-100 => ESR_COMMAND_ERROR,
+            -299..=-200 => ESR_EXECUTION_ERROR,
+            -399..=-300 => ESR_DEVICE_ERROR,
+            _ => ESR_QUERY_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ScpiError {
+    /// As `SYST:ERR?` answers, `-113,"Undefined header"`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code(), self.message())
+    }
+}
+
+/// Whether `header`, absolute and without its leading `:`, names the
+/// command `pattern`: `meas:pwm:frequency?` does `MEASure:PWM:FREQuency?`.
+pub fn header_matches(pattern: &str, header: &str) -> bool {
+    let (pattern, query) = split_query(pattern);
+    let (header, header_query) = split_query(header);
+    if query != header_query {
+        return false;
+    }
+    match pattern.strip_prefix('*') {
+        Some(common) => header
+            .strip_prefix('*')
+            .map_or(false, |header| header.eq_ignore_ascii_case(common)),
+        None => match_nodes(Nodes(pattern), header.split(':')),
+    }
+}
+
+fn split_query(header: &str) -> (&str, bool) {
+    match header.strip_suffix('?') {
+        Some(header) => (header, true),
+        None => (header, false),
+    }
+}
+
+/// The nodes of a pattern, and whether they may be left out.
+#[derive(Clone)]
+struct Nodes<'p>(&'p str);
+
+impl<'p> Iterator for Nodes<'p> {
+    type Item = (&'p str, bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.0.trim_start_matches(':');
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(optional) = rest.strip_prefix('[') {
+            let end = optional.find(']').unwrap_or(optional.len());
+            self.0 = optional.get(end + 1..).unwrap_or("");
+            return Some((optional[..end].trim_start_matches(':'), true));
+        }
+        let end = rest.find([':', '[']).unwrap_or(rest.len());
+        self.0 = &rest[end..];
+        Some((&rest[..end], false))
+    }
+}
+
+fn match_nodes(mut pattern: Nodes<'_>, mut header: Split<'_, char>) -> bool {
+    loop {
+        match pattern.next() {
+            None => return header.next().is_none(),
+            Some((node, optional)) => {
+                if optional && match_nodes(pattern.clone(), header.clone()) {
+                    return true;
+                }
+                match header.next() {
+                    Some(word) if mnemonic_matches(node, word) => {}
+                    _ => return false,
+                }
+            }
+        }
+    }
+}
+
+/// `FREQ` or `frequency` for `FREQuency`.
+fn mnemonic_matches(mnemonic: &str, word: &str) -> bool {
+    let short = mnemonic.len() - mnemonic.trim_start_matches(|ch: char| !ch.is_ascii_lowercase()).len();
+    word.eq_ignore_ascii_case(mnemonic) || word.eq_ignore_ascii_case(&mnemonic[..short])
+}
+
+/// Split `text` at the first `sep` outside quotes and `( )`.
+fn split_top(text: &str, sep: u8) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut depth = 0u8;
+    for (idx, byte) in text.bytes().enumerate() {
+        match (quote, byte) {
+            (Some(open), byte) if byte == open => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (None, b'(') => depth = depth.saturating_add(1),
+            (None, b')') => depth = depth.saturating_sub(1),
+            (None, byte) if byte == sep && depth == 0 => return (&text[..idx], Some(&text[idx + 1..])),
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+/// The parameters of a command, separated by `,`.
+pub struct Params<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Params<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let text = text.trim();
+        Self {
+            rest: if text.is_empty() { None } else { Some(text) },
+        }
+    }
+
+    /// The next parameter as it is written.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&'a str> {
+        let (param, rest) = split_top(self.rest?, b',');
+        self.rest = rest;
+        Some(param.trim())
+    }
+
+    fn required(&mut self) -> Result<&'a str, ScpiError> {
+        self.next().ok_or(ScpiError::MissingParameter)
+    }
+
+    /// A whole number in `min..=max`, or `MINimum` or `MAXimum`.
+    pub fn int(&mut self, min: i32, max: i32) -> Result<i32, ScpiError> {
+        let param = self.required()?;
+        let value = if mnemonic_matches("MINimum", param) {
+            min
+        } else if mnemonic_matches("MAXimum", param) {
+            max
+        } else if param.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-' || ch == '+') {
+            param.parse().map_err(|_| ScpiError::NumericDataError)?
+        } else {
+            return Err(ScpiError::DataTypeError);
+        };
+        if value < min || value > max {
+            return Err(ScpiError::DataOutOfRange);
+        }
+        Ok(value)
+    }
+
+    /// `ON`, `OFF`, `1` or `0`.
+    pub fn boolean(&mut self) -> Result<bool, ScpiError> {
+        let param = self.required()?;
+        if param.eq_ignore_ascii_case("ON") || param == "1" {
+            Ok(true)
+        } else if param.eq_ignore_ascii_case("OFF") || param == "0" {
+            Ok(false)
+        } else {
+            Err(ScpiError::IllegalParameterValue)
+        }
+    }
+
+    /// A channel list, `(@0,2:4)`, as a mask of channels up to `max`.
+    pub fn channels(&mut self, max: u8) -> Result<u32, ScpiError> {
+        let list = self
+            .required()?
+            .strip_prefix('(')
+            .and_then(|param| param.strip_suffix(')'))
+            .and_then(|param| param.trim().strip_prefix('@'))
+            .ok_or(ScpiError::DataTypeError)?;
+        let mut mask = 0;
+        for item in list.split(',') {
+            let (first, last) = item.split_once(':').unwrap_or((item, item));
+            let first: u8 = first.trim().parse().map_err(|_| ScpiError::IllegalParameterValue)?;
+            let last: u8 = last.trim().parse().map_err(|_| ScpiError::IllegalParameterValue)?;
+            if first > last || last > max.min(31) {
+                return Err(ScpiError::DataOutOfRange);
+            }
+            for ch in first..=last {
+                mask |= 1 << ch;
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Fails when parameters are left.
+    pub fn done(&self) -> Result<(), ScpiError> {
+        match self.rest {
+            Some(_) => Err(ScpiError::ParameterNotAllowed),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A command of an [`Instrument`]: `MEASure:PWM:FREQuency?` and the `id`
+/// it is run by, queries have patterns of their own.
+pub struct ScpiCommand {
+    pub pattern: &'static str,
+    pub id: u16,
+}
+
+/// What is measured or controlled, behind the SCPI commands.
+pub trait Instrument {
+    /// `*IDN?`: manufacturer, model, serial number, firmware version.
+    fn identity(&self) -> &str;
+
+    /// `*OPT?`, `0` for no options.
+    fn options(&self) -> &str {
+        "0"
+    }
+
+    /// `*RST`: back to the state after power on.
+    fn reset(&mut self);
+
+    fn commands(&self) -> &'static [ScpiCommand];
+
+    /// Run the command `id` of [`Instrument::commands`], a query writes its
+    /// answer to `out`. Parameters not read are an error.
+    async fn execute(&mut self, id: u16, params: &mut Params<'_>, out: &mut dyn Write) -> Result<(), ScpiError>;
+}
+
+#[derive(Clone, Copy)]
+enum Common {
+    Idn,
+    Rst,
+    Opt,
+    Cls,
+    Esr,
+    Opc,
+    OpcQuery,
+    Wai,
+    Tst,
+    Error,
+    ErrorCount,
+    Version,
+}
+
+const COMMON: [(&str, Common); 12] = [
+    ("*IDN?", Common::Idn),
+    ("*RST", Common::Rst),
+    ("*OPT?", Common::Opt),
+    ("*CLS", Common::Cls),
+    ("*ESR?", Common::Esr),
+    ("*OPC", Common::Opc),
+    ("*OPC?", Common::OpcQuery),
+    ("*WAI", Common::Wai),
+    ("*TST?", Common::Tst),
+    ("SYSTem:ERRor[:NEXT]?", Common::Error),
+    ("SYSTem:ERRor:COUNt?", Common::ErrorCount),
+    ("SYSTem:VERSion?", Common::Version),
+];
+
+/// Writes `;` before the answer of every query after the first.
+struct Answers<'o> {
+    out: &'o mut dyn Write,
+    answered: bool,
+    started: bool,
+}
+
+impl<'o> Write for Answers<'o> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.started && !s.is_empty() {
+            if self.answered {
+                self.out.write_char(';')?;
+            }
+            self.started = true;
+            self.answered = true;
+        }
+        self.out.write_str(s)
+    }
+}
+
+/// An SCPI parser in front of `instrument`, with an error queue of `Q`
+/// errors.
+pub struct Scpi<I: Instrument, const Q: usize> {
+    instrument: I,
+    errors: Deque<ScpiError, Q>,
+    /// standard event status register
+    esr: u8,
+    /// line read by [`Scpi::feed`]
+    line: Vec<u8, LINE_LEN>,
+    overrun: bool,
+}
+
+impl<I: Instrument, const Q: usize> Scpi<I, Q> {
+    pub fn new(instrument: I) -> Self {
+        Self {
+            instrument,
+            errors: Deque::new(),
+            esr: 0,
+            line: Vec::new(),
+            overrun: false,
+        }
+    }
+
+    pub fn instrument_mut(&mut self) -> &mut I {
+        &mut self.instrument
+    }
+
+    /// Queue `err`. In a full queue the last error becomes a queue
+    /// overflow.
+    pub fn push_error(&mut self, err: ScpiError) {
+        self.esr |= err.esr_bit();
+        if self.errors.is_full() {
+            self.errors.pop_back();
+            let _ = self.errors.push_back(ScpiError::QueueOverflow);
+        } else {
+            let _ = self.errors.push_back(err);
+        }
+    }
+
+    /// The oldest error, as `SYST:ERR?` reads it.
+    pub fn next_error(&mut self) -> Option<ScpiError> {
+        self.errors.pop_front()
+    }
+
+    /// Run the program message `line`, answers go to `out`. Returns
+    /// whether a query answered.
+    pub async fn execute(&mut self, line: &str, out: &mut dyn Write) -> bool {
+        let mut answers = Answers {
+            out,
+            answered: false,
+            started: false,
+        };
+        //the node relative headers are in, with its `:`
+        let mut path: String<LINE_LEN> = String::new();
+        let mut rest = Some(line);
+        while let Some(text) = rest {
+            let (unit, next) = split_top(text, b';');
+            rest = next;
+            let unit = unit.trim();
+            if unit.is_empty() {
+                continue;
+            }
+            let (header, params) = unit
+                .split_once(|ch: char| ch.is_ascii_whitespace())
+                .unwrap_or((unit, ""));
+            let mut absolute: String<LINE_LEN> = String::new();
+            //common commands leave the path as it is
+            let header = if header.starts_with('*') {
+                header
+            } else {
+                match header.strip_prefix(':') {
+                    Some(header) => {
+                        let _ = absolute.push_str(header);
+                    }
+                    None => {
+                        //path and header come from the same line, they fit
+                        let _ = absolute.push_str(&path);
+                        let _ = absolute.push_str(header);
+                    }
+                }
+                path.clear();
+                if let Some(end) = absolute.rfind(':') {
+                    let _ = path.push_str(&absolute[..=end]);
+                }
+                &absolute
+            };
+            answers.started = false;
+            if let Err(err) = self.run(header, Params::new(params), &mut answers).await {
+                self.push_error(err);
+                break;
+            }
+        }
+        answers.answered
+    }
+
+    async fn run(&mut self, header: &str, mut params: Params<'_>, out: &mut dyn Write) -> Result<(), ScpiError> {
+        if let Some((_, common)) = COMMON.iter().find(|(pattern, _)| header_matches(pattern, header)) {
+            params.done()?;
+            let _ = self.common(*common, out);
+            return Ok(());
+        }
+        let id = self
+            .instrument
+            .commands()
+            .iter()
+            .find(|command| header_matches(command.pattern, header))
+            .ok_or(ScpiError::UndefinedHeader)?
+            .id;
+        self.instrument.execute(id, &mut params, out).await?;
+        params.done()
+    }
+
+    fn common(&mut self, common: Common, out: &mut dyn Write) -> fmt::Result {
+        match common {
+            Common::Idn => out.write_str(self.instrument.identity()),
+            Common::Rst => {
+                self.instrument.reset();
+                Ok(())
+            }
+            Common::Opt => out.write_str(self.instrument.options()),
+            Common::Cls => {
+                self.errors.clear();
+                self.esr = 0;
+                Ok(())
+            }
+            Common::Esr => write!(out, "{}", core::mem::take(&mut self.esr)),
+            Common::Opc => {
+                //commands run one after the other, all are complete
+                self.esr |= ESR_OPERATION_COMPLETE;
+                Ok(())
+            }
+            Common::OpcQuery => out.write_str("1"),
+            Common::Wai => Ok(()),
+            Common::Tst => out.write_str("0"),
+            Common::Error => match self.next_error() {
+                Some(err) => write!(out, "{}", err),
+                None => out.write_str("0,\"No error\""),
+            },
+            Common::ErrorCount => write!(out, "{}", self.errors.len()),
+            Common::Version => out.write_str(SCPI_VERSION),
+        }
+    }
+
+    /// Serve a port byte by byte, without echo: a line runs when its `\n`
+    /// arrives, and its answer is ended with `\n`.
+    pub async fn feed(&mut self, byte: u8, out: &mut dyn Write) {
+        match byte {
+            b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.overrun) {
+                    self.push_error(ScpiError::InputOverrun);
+                    return;
+                }
+                match from_utf8(&line) {
+                    Ok(line) => {
+                        if self.execute(line, out).await {
+                            let _ = out.write_char('\n');
+                        }
+                    }
+                    Err(_) => self.push_error(ScpiError::InvalidCharacter),
+                }
+            }
+            b'\r' => {}
+            byte => {
+                if self.line.push(byte).is_err() {
+                    self.overrun = true;
+                }
+            }
+        }
+    }
+}
+
+/// SCPI typed into a shell, see [`AShell::feed`](crate::AShell::feed).
+/// Errors are queued, not printed.
+impl<I: Instrument, const Q: usize> Environment for Scpi<I, Q> {
+    async fn command(&mut self, cmd: &str, args: &str, out: &mut dyn Write) -> ShellResult {
+        let mut line: String<LINE_LEN> = String::new();
+        if write!(line, "{} {}", cmd, args).is_err() {
+            self.push_error(ScpiError::InputOverrun);
+            return Ok(());
+        }
+        self.execute(&line, out).await;
+        Ok(())
+    }
+
+    async fn control(&mut self, _code: u8) -> ShellResult {
+        Ok(())
+    }
+}
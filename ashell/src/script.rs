@@ -0,0 +1,367 @@
+//! Scripts: more than one command on a line, and named scripts kept in a
+//! [`ScriptStore`] that run like a command.
+//!
+//! ```text
+//! pwmin start 0; sleep 500; pwmin status
+//! pwm 1 && pwmin status || pwm 0
+//! repeat 3 { pwmin status; sleep 100 }
+//! if $? == 0 { pwm 1 } else if $? == 2 { pwm 2 } else { pwm 0 }
+//! if pwmin status { pwm 1 }
+//! ```
+//!
+//! - `;` and new lines separate statements, a failed command does not stop
+//!   the ones after it, Ctrl-C stops the whole script
+//! - `a && b` runs `b` if `a` succeeded, `a || b` if it failed
+//! - `repeat <n> { .. }` runs the block `n` times, `n` may be a `$NAME`
+//! - `if <a> <op> <b> { .. }` compares, `op` is one of `==`, `!=`, `<`, `>`,
+//!   `<=` and `>=`; both sides are numbers, or strings for `==` and `!=`
+//! - `if <command> { .. }` runs the block if the command succeeded
+//! - `else { .. }` and `else if` go on the line of the closing `}`
+//!
+//! Braces, `;`, `&&` and `||` in quotes are plain text. `$?` is the status
+//! of the last command, so `if $? == 0` tests the command before the `if`.
+
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::heapless::{String, Vec};
+use crate::ShellError;
+
+/// Most `repeat` and `if` blocks inside each other.
+pub const MAX_DEPTH: usize = 8;
+pub const NAME_LEN: usize = 16;
+/// Longest stored script the shell runs.
+pub const SCRIPT_LEN: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    /// A `{` without its `}`, or the other way round.
+    Unbalanced,
+    /// `repeat` or `if` without its block, or text after a `}`.
+    Syntax,
+    /// The count of a `repeat` is not a number.
+    BadCount,
+    /// `<`, `>`, `<=` or `>=` between things that are not numbers.
+    NotANumber,
+    /// Blocks nested deeper than [`MAX_DEPTH`].
+    TooDeep,
+    /// Not letters, digits, `_` and `-`, or a word of the script syntax.
+    BadName,
+    TooLong,
+    /// Every slot of the store is taken.
+    Full,
+    NoSuchScript,
+    NoSuchAlias,
+    /// A stored script runs on a line of its own, not from another script.
+    Nested,
+    /// The store could not be written.
+    StoreError,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Unbalanced => write!(f, "unbalanced {{ }}"),
+            ScriptError::Syntax => write!(f, "syntax error"),
+            ScriptError::BadCount => write!(f, "bad count"),
+            ScriptError::NotANumber => write!(f, "not a number"),
+            ScriptError::TooDeep => write!(f, "blocks nested too deep"),
+            ScriptError::BadName => write!(f, "bad name"),
+            ScriptError::TooLong => write!(f, "too long"),
+            ScriptError::Full => write!(f, "too many scripts"),
+            ScriptError::NoSuchScript => write!(f, "no such script"),
+            ScriptError::NoSuchAlias => write!(f, "no such alias"),
+            ScriptError::Nested => write!(f, "scripts run on a line of their own"),
+            ScriptError::StoreError => write!(f, "cannot store script"),
+        }
+    }
+}
+
+impl From<ScriptError> for ShellError {
+    fn from(err: ScriptError) -> Self {
+        ShellError::ScriptError(err)
+    }
+}
+
+/// One statement of a script, see [`next_statement`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Statement<'t> {
+    /// Commands joined by `&&` and `||`.
+    Chain(&'t str),
+    Repeat { count: &'t str, body: &'t str },
+    /// `otherwise` is empty without an `else`.
+    If { cond: &'t str, then: &'t str, otherwise: &'t str },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Link {
+    And,
+    Or,
+}
+
+/// Position of the first byte of `text` outside quotes for which `stop`
+/// holds. With `braces`, only bytes outside `{ }` count.
+fn find(text: &str, braces: bool, stop: impl Fn(&[u8]) -> bool) -> Result<Option<usize>, ScriptError> {
+    let bytes = text.as_bytes();
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        match (quote, byte) {
+            (Some(b'\''), b'\'') | (Some(b'"'), b'"') => quote = None,
+            (Some(b'\''), _) => {}
+            (_, b'\\') => idx += 1,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(byte),
+            (None, _) => {
+                if depth == 0 && stop(&bytes[idx..]) {
+                    return Ok(Some(idx));
+                }
+                if braces {
+                    match byte {
+                        b'{' => depth += 1,
+                        b'}' if depth == 0 => return Err(ScriptError::Unbalanced),
+                        b'}' => depth -= 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        idx += 1;
+    }
+    Ok(None)
+}
+
+fn is_blank(ch: char) -> bool {
+    ch == ' ' || ch == '\t'
+}
+
+/// The first word of `text`, and what follows it.
+fn split_word(text: &str) -> (&str, &str) {
+    let end = text.find(|ch: char| ch.is_ascii_whitespace()).unwrap_or(text.len());
+    text.split_at(end)
+}
+
+/// `{ body }` at the start of `text`: the body, and what follows the `}`.
+fn block(text: &str) -> Result<(&str, &str), ScriptError> {
+    let inner = text.trim_start_matches(is_blank).strip_prefix('{').ok_or(ScriptError::Syntax)?;
+    let close = find(inner, true, |rest| rest[0] == b'}')?.ok_or(ScriptError::Unbalanced)?;
+    Ok((inner[..close].trim(), &inner[close + 1..]))
+}
+
+/// Only a separator or the end may follow a block.
+fn end_of_statement(rest: &str) -> Result<&str, ScriptError> {
+    let trimmed = rest.trim_start_matches(is_blank);
+    match trimmed.bytes().next() {
+        None | Some(b';' | b'\r' | b'\n') => Ok(trimmed),
+        Some(_) => Err(ScriptError::Syntax),
+    }
+}
+
+/// The first statement of `text`, and the text after it, or `None` when
+/// only separators are left.
+pub(crate) fn next_statement(text: &str) -> Result<Option<(Statement<'_>, &str)>, ScriptError> {
+    let text = text.trim_start_matches(|ch: char| ch.is_ascii_whitespace() || ch == ';');
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let (word, after) = split_word(text);
+    match word {
+        "repeat" => {
+            let (count, after) = split_word(after.trim_start_matches(is_blank));
+            let (body, rest) = block(after)?;
+            Ok(Some((Statement::Repeat { count, body }, end_of_statement(rest)?)))
+        }
+        "if" => {
+            let open = find(after, true, |rest| rest[0] == b'{')?.ok_or(ScriptError::Syntax)?;
+            let cond = after[..open].trim();
+            if cond.is_empty() {
+                return Err(ScriptError::Syntax);
+            }
+            let (then, rest) = block(&after[open..])?;
+            let (word, after_else) = split_word(rest.trim_start_matches(is_blank));
+            if word != "else" {
+                return Ok(Some((Statement::If { cond, then, otherwise: "" }, end_of_statement(rest)?)));
+            }
+            let after_else = after_else.trim_start_matches(is_blank);
+            if split_word(after_else).0 == "if" {
+                //`else if` is an `if` in the else block
+                let (_, rest) = next_statement(after_else)?.ok_or(ScriptError::Syntax)?;
+                let otherwise = after_else[..after_else.len() - rest.len()].trim();
+                return Ok(Some((Statement::If { cond, then, otherwise }, rest)));
+            }
+            let (otherwise, rest) = block(after_else)?;
+            Ok(Some((Statement::If { cond, then, otherwise }, end_of_statement(rest)?)))
+        }
+        "else" | "{" | "}" => Err(ScriptError::Syntax),
+        _ => {
+            let end = find(text, false, |rest| rest[0] == b';' || rest[0] == b'\n')?.unwrap_or(text.len());
+            Ok(Some((Statement::Chain(text[..end].trim()), &text[end..])))
+        }
+    }
+}
+
+/// The first command of a chain, and the `&&` or `||` with the rest.
+pub(crate) fn next_link(chain: &str) -> (&str, Option<(Link, &str)>) {
+    let at = find(chain, false, |rest| rest.starts_with(b"&&") || rest.starts_with(b"||"));
+    match at {
+        Ok(Some(idx)) => {
+            let link = if chain.as_bytes()[idx] == b'&' { Link::And } else { Link::Or };
+            (chain[..idx].trim(), Some((link, &chain[idx + 2..])))
+        }
+        _ => (chain.trim(), None),
+    }
+}
+
+/// `a op b` of an `if`, `None` when `argv` is a command to run instead.
+pub(crate) fn compare(argv: &[&str]) -> Option<Result<bool, ScriptError>> {
+    let [a, op, b] = argv else {
+        return None;
+    };
+    let test: fn(Ordering) -> bool = match *op {
+        "==" => Ordering::is_eq,
+        "!=" => Ordering::is_ne,
+        "<" => Ordering::is_lt,
+        ">" => Ordering::is_gt,
+        "<=" => Ordering::is_le,
+        ">=" => Ordering::is_ge,
+        _ => return None,
+    };
+    let order = match (a.parse::<i32>(), b.parse::<i32>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ if matches!(*op, "==" | "!=") => a.cmp(b),
+        _ => return Some(Err(ScriptError::NotANumber)),
+    };
+    Some(Ok(test(order)))
+}
+
+/// Named scripts, shared by the shells and the places they are edited
+/// from, so every method takes `&self`. Aliases are kept in one too.
+pub trait ScriptStore {
+    /// Copy script `name` to the start of `buf`, returns its length.
+    /// `None` when there is no such script or it does not fit.
+    fn load(&self, name: &str, buf: &mut [u8]) -> Option<usize>;
+
+    fn contains(&self, name: &str) -> bool;
+
+    /// Add script `name`, or replace it.
+    fn save(&self, name: &str, text: &str) -> Result<(), ScriptError>;
+
+    fn remove(&self, name: &str) -> Result<(), ScriptError>;
+
+    /// Call `f` with the name and text of every script, oldest first.
+    fn each(&self, f: &mut dyn FnMut(&str, &str));
+}
+
+/// Up to `N` scripts of at most `LEN` bytes.
+pub(crate) struct ScriptTable<const N: usize, const LEN: usize> {
+    pub(crate) scripts: Vec<(String<NAME_LEN>, String<LEN>), N>,
+}
+
+impl<const N: usize, const LEN: usize> ScriptTable<N, LEN> {
+    pub(crate) const fn new() -> Self {
+        Self { scripts: Vec::new() }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.scripts.iter().position(|(n, _)| n == name)
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    pub(crate) fn load(&self, name: &str, buf: &mut [u8]) -> Option<usize> {
+        let text = &self.scripts[self.find(name)?].1;
+        let dest = buf.get_mut(..text.len())?;
+        dest.copy_from_slice(text.as_bytes());
+        Some(text.len())
+    }
+
+    /// Returns the slot the script went to.
+    pub(crate) fn save(&mut self, name: &str, text: &str) -> Result<usize, ScriptError> {
+        let mut chars = name.chars();
+        let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            && !matches!(name, "repeat" | "if" | "else");
+        if !valid {
+            return Err(ScriptError::BadName);
+        }
+        let name = String::from_str(name).map_err(|_| ScriptError::BadName)?;
+        let text = String::from_str(text).map_err(|_| ScriptError::TooLong)?;
+        match self.find(&name) {
+            Some(idx) => {
+                self.scripts[idx].1 = text;
+                Ok(idx)
+            }
+            None => {
+                self.scripts.push((name, text)).map_err(|_| ScriptError::Full)?;
+                Ok(self.scripts.len() - 1)
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Result<(), ScriptError> {
+        let idx = self.find(name).ok_or(ScriptError::NoSuchScript)?;
+        self.scripts.remove(idx);
+        Ok(())
+    }
+
+    pub(crate) fn each(&self, f: &mut dyn FnMut(&str, &str)) {
+        for (name, text) in self.scripts.iter() {
+            f(name, text);
+        }
+    }
+}
+
+/// Scripts in RAM, lost on reset, see
+/// [`FlashScripts`](crate::flash_script::FlashScripts) to keep them.
+pub struct Scripts<M: RawMutex, const N: usize, const LEN: usize> {
+    table: Mutex<M, RefCell<ScriptTable<N, LEN>>>,
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> Scripts<M, N, LEN> {
+    pub const fn new() -> Self {
+        Self {
+            table: Mutex::new(RefCell::new(ScriptTable::new())),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut ScriptTable<N, LEN>) -> R) -> R {
+        self.table.lock(|table| f(&mut table.borrow_mut()))
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> Default for Scripts<M, N, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize> ScriptStore for Scripts<M, N, LEN> {
+    fn load(&self, name: &str, buf: &mut [u8]) -> Option<usize> {
+        self.with(|table| table.load(name, buf))
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.with(|table| table.contains(name))
+    }
+
+    fn save(&self, name: &str, text: &str) -> Result<(), ScriptError> {
+        self.with(|table| table.save(name, text).map(|_| ()))
+    }
+
+    fn remove(&self, name: &str) -> Result<(), ScriptError> {
+        self.with(|table| table.remove(name))
+    }
+
+    fn each(&self, f: &mut dyn FnMut(&str, &str)) {
+        self.with(|table| table.each(f))
+    }
+}
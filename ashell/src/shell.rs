@@ -1,59 +1,263 @@
-use core::{fmt::Write, str::from_utf8};
+use core::{cell::RefCell, fmt::Write, future::poll_fn, str::from_utf8};
 // use hal::serial;
 use embedded_io::asynch::{Read as AsyncRead, Write as AsyncWrite};
 // use nb::block;
-use heapless::Vec;
+use heapless::{String, Vec};
+use core::str::FromStr;
 
 use log::{Metadata, Record};
-use crate::autocomplete::Autocomplete;
-use crate::history::History;
+use crate::alias::{substitute, ALIAS_LEN};
+use crate::autocomplete::{common_prefix_len, last_word, Autocomplete};
+use crate::command::MAX_ARGS;
+use crate::filter::{split_pipeline, Pipeline};
+use crate::history::{expand, History};
+use crate::jobs::{JobControl, JobError};
+use crate::machine::{self, Framer};
+use crate::output::{CommandOutput, Output, OutputWriter};
+use crate::script::{compare, next_link, next_statement, Link, ScriptError, ScriptStore, Statement, MAX_DEPTH, SCRIPT_LEN};
+use crate::tokenizer::{tokenize_vars, TokenizeError};
+use crate::vars::{VarError, Vars, VALUE_LEN};
 use crate::*;
-use embassy_sync::pipe::{Pipe, Reader, Writer};
+use embassy_futures::select::{select, Either};
+use embassy_sync::pipe::Writer;
 
 pub type SpinResult = Result<(), ShellError>;
 // pub type PollResult<'a, S> = Result<Option<Input<'a>>, ShellError>;
 type CS = embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 
 const SHELL_PROMPT:&str = "\r\n#>";
+const PROMPT:&str = "#>";
+/// Assumed terminal width when listing completions.
+const TERM_WIDTH: usize = 80;
+/// Buffer handed to [`Source::next`], half of it holds a 64 byte USB packet.
+const SOURCE_BUF_LEN: usize = 128;
+/// Longest line [`AShell::print_above`] keeps while waiting for its end.
+const ABOVE_LINE_LEN: usize = 128;
+/// A command line with its `$NAME`s replaced has to fit this.
+const ARGV_BUF_LEN: usize = 256;
 
-pub struct AShell<A, H, const CMD_LEN: usize, const LOG_LEN:usize> 
+/// What the caller of [`AShell::edit`] has to do after a byte was handled.
+enum Action {
+    None,
+    Control(u8),
+    Line(usize),
+}
+
+/// A key press, decoded from a plain byte or a VT100/xterm escape sequence.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Key {
+    Byte(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+    WordLeft,
+    WordRight,
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KeyState {
+    Ground,
+    /// after `ESC`
+    Escape,
+    /// after `ESC [`, collecting parameters
+    Csi,
+    /// after `ESC O`
+    Ss3,
+}
+
+/// A block of a script being run, see [`AShell::run_script`].
+struct Frame<'t> {
+    /// statements not run yet
+    rest: &'t str,
+    body: &'t str,
+    /// times `body` runs again after this
+    left: u32,
+}
+
+/// State of an incremental history search (Ctrl-R / Ctrl-S).
+struct Search<const CMD_LEN: usize> {
+    query: String<CMD_LEN>,
+    /// history entry number and text of the current match
+    found: Option<(usize, String<CMD_LEN>)>,
+    backward: bool,
+    failed: bool,
+}
+
+/// Decodes `ESC [ params final`, `ESC O final` and `ESC x` (Alt-x).
+struct KeyParser {
+    state: KeyState,
+    params: [u16; 2],
+    param_idx: usize,
+}
+
+impl KeyParser {
+    const fn new() -> Self {
+        Self {
+            state: KeyState::Ground,
+            params: [0; 2],
+            param_idx: 0,
+        }
+    }
+
+    /// Returns a key once `byte` completes one.
+    fn feed(&mut self, byte: u8) -> Option<Key> {
+        match self.state {
+            KeyState::Ground => {
+                if byte == control::ESC {
+                    self.state = KeyState::Escape;
+                    None
+                } else {
+                    Some(Key::Byte(byte))
+                }
+            }
+            KeyState::Escape => {
+                self.state = KeyState::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = KeyState::Csi;
+                        self.params = [0; 2];
+                        self.param_idx = 0;
+                        None
+                    }
+                    b'O' => {
+                        self.state = KeyState::Ss3;
+                        None
+                    }
+                    b'b' | b'B' => Some(Key::WordLeft),
+                    b'f' | b'F' => Some(Key::WordRight),
+                    control::ESC => {
+                        self.state = KeyState::Escape;
+                        None
+                    }
+                    _ => Some(Key::Unknown),
+                }
+            }
+            KeyState::Csi => match byte {
+                b'0'..=b'9' => {
+                    if let Some(param) = self.params.get_mut(self.param_idx) {
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    }
+                    None
+                }
+                b';' => {
+                    self.param_idx += 1;
+                    None
+                }
+                // final byte
+                0x40..=0x7e => {
+                    self.state = KeyState::Ground;
+                    // xterm sends `ESC [ 1 ; 5 D` for Ctrl-Left
+                    let ctrl = self.params[1] == 5;
+                    Some(match (byte, self.params[0]) {
+                        (b'A', _) => Key::Up,
+                        (b'B', _) => Key::Down,
+                        (b'C', _) if ctrl => Key::WordRight,
+                        (b'D', _) if ctrl => Key::WordLeft,
+                        (b'C', _) => Key::Right,
+                        (b'D', _) => Key::Left,
+                        (b'H', _) => Key::Home,
+                        (b'F', _) => Key::End,
+                        (b'~', 1) | (b'~', 7) => Key::Home,
+                        (b'~', 4) | (b'~', 8) => Key::End,
+                        (b'~', 3) => Key::Delete,
+                        _ => Key::Unknown,
+                    })
+                }
+                // intermediate bytes, ignored
+                _ => None,
+            },
+            KeyState::Ss3 => {
+                self.state = KeyState::Ground;
+                Some(match byte {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    b'C' => Key::Right,
+                    b'D' => Key::Left,
+                    b'H' => Key::Home,
+                    b'F' => Key::End,
+                    _ => Key::Unknown,
+                })
+            }
+        }
+    }
+}
+
+pub struct AShell<A, H, O, const CMD_LEN: usize> 
 where 
     // S: AsyncRead + AsyncWrite,
     A: Autocomplete<CMD_LEN>,
-    H: History<CMD_LEN>
+    H: History<CMD_LEN>,
+    O: Output,
 {
     autocomplete: A,
     history: H,
     // editor_buf: Vec<u8, CMD_LEN>,
     editor_buf: [u8; CMD_LEN],
-    log_buffer: &'static Pipe<CS,LOG_LEN>,
+    output: Framer<O>,
     editor_len: usize,
     cursor: usize,
-    control: bool,
-    escape: bool,
+    keys: KeyParser,
+    search: Option<Search<CMD_LEN>>,
     autocomplete_on: bool,
     history_on: bool,
+    last_tab: bool,
+    /// start of a line for [`AShell::print_above`], waiting for its `\n`
+    above_line: Vec<u8, ABOVE_LINE_LEN>,
+    /// where `line &` goes, see [`AShell::set_jobs`]
+    jobs: Option<&'static dyn JobControl>,
+    /// `set` variables and `$?`
+    vars: Vars,
+    /// where the `script` built-in keeps scripts, see [`AShell::set_scripts`]
+    scripts: Option<&'static dyn ScriptStore>,
+    /// see [`AShell::set_aliases`]
+    aliases: Option<&'static dyn ScriptStore>,
+    /// the output of the last command did not end its line
+    mid_line: bool,
+    /// in machine mode, see [`crate::machine`]
+    machine: Option<Machine>,
 }
 
-impl<A, H, const CMD_LEN: usize, const LOG_LEN: usize> AShell<A, H, CMD_LEN, LOG_LEN>
+struct Machine {
+    /// sequence number of the last request
+    seq: u32,
+    /// the line read is longer than the editor buffer
+    overlong: bool,
+}
+
+impl<A, H, O, const CMD_LEN: usize> AShell<A, H, O, CMD_LEN>
 where
     A: Autocomplete<CMD_LEN>,
     H: History<CMD_LEN>,
+    O: Output,
 {
-    pub async fn new(autocomplete: A, history: H, log_buffer: &'static Pipe<CS, LOG_LEN>) -> Self {
-        log_buffer.write(SHELL_PROMPT.as_bytes()).await;
+    pub async fn new(autocomplete: A, history: H, mut output: O) -> Self {
+        output.write_all(SHELL_PROMPT.as_bytes()).await;
+        output.flush().await;
         Self {
             autocomplete,
             history,
             // env,
             cursor: 0,
             editor_buf: [0;CMD_LEN],
-            log_buffer,
+            output: Framer::new(output),
             editor_len: 0,
             autocomplete_on: true,
             history_on: true,
-            control: false,
-            escape: false,
+            keys: KeyParser::new(),
+            search: None,
+            last_tab: false,
+            above_line: Vec::new(),
+            jobs: None,
+            vars: Vars::default(),
+            scripts: None,
+            aliases: None,
+            mid_line: false,
+            machine: None,
         }
     }
 
@@ -65,6 +269,25 @@ where
         self.history_on = history_on;
     }
 
+    /// Book `line &` in `jobs` and enable the `jobs`, `fg` and `kill`
+    /// built-ins. Something has to run [`Jobs::run`](crate::jobs::Jobs::run)
+    /// on the same table.
+    pub fn set_jobs(&mut self, jobs: &'static dyn JobControl) {
+        self.jobs = Some(jobs);
+    }
+
+    /// Run the scripts in `scripts` by their name and enable the `script`
+    /// built-in to edit them.
+    pub fn set_scripts(&mut self, scripts: &'static dyn ScriptStore) {
+        self.scripts = Some(scripts);
+    }
+
+    /// Keep the aliases of the `alias` built-in in `aliases`, expand them
+    /// before commands are looked up, and complete their names.
+    pub fn set_aliases(&mut self, aliases: &'static dyn ScriptStore) {
+        self.aliases = Some(aliases);
+    }
+
     pub fn get_autocomplete_mut(&mut self) -> &mut A {
         &mut self.autocomplete
     }
@@ -77,16 +300,17 @@ where
         // &mut self.serial
     // }
 
-    pub fn reader(&self) -> Reader<'_, CS, LOG_LEN> {
-        self.log_buffer.reader()
+    pub fn get_output_mut(&mut self) -> &mut O {
+        self.output.inner_mut()
     }
-    pub fn writer(&self) -> Writer<'_, CS, LOG_LEN> {
-        self.log_buffer.writer()
+
+    pub fn get_vars_mut(&mut self) -> &mut Vars {
+        &mut self.vars
     }
 
     pub fn reset(&mut self) {
-        self.control = false;
-        self.escape = false;
+        self.keys = KeyParser::new();
+        self.search = None;
         self.cursor = 0;
         self.editor_len = 0;
     }
@@ -128,88 +352,773 @@ where
     // pub async fn feed(&mut self, env: &mut impl Environment<A, H, CMD_LEN, LOG_LEN>, byte:u8) -> ShellResult
     pub async fn feed(&mut self, env: &mut impl Environment, byte:u8) -> ShellResult
     {
-        const ANSI_ESCAPE: u8 = b'[';
+        let ret = self.feed_line(env, byte).await;
+        self.output.flush().await;
+        ret
+    }
 
-        // let mut buf:[u8;32] = [0; 32];
-        // loop {
-            // let rx_len = self.serial.read(&mut buf).await.unwrap();
+    async fn feed_line(&mut self, env: &mut impl Environment, byte:u8) -> ShellResult
+    {
+        let mut line_buf = [0; CMD_LEN];
+        match self.edit(byte, &mut line_buf).await? {
+            Action::None => Ok(()),
+            Action::Control(code) => env.control(code).await,
+            Action::Line(0) => {
+                self.output.write_all(SHELL_PROMPT.as_bytes()).await;
+                Ok(())
+            }
+            Action::Line(len) => {
+                let line_str = from_utf8(&line_buf[..len])?;
+                let (cmd, args) = line_str.split_once(" ").unwrap_or((line_str, &""));
+                let argv: Vec<&str, MAX_ARGS> = line_str.split_ascii_whitespace().take(MAX_ARGS).collect();
+                // env.command(self, cmd, args).await
+                let ret = match self.builtin(&argv, &mut NoSource).await {
+                    Some(ret) => ret,
+                    None => env.command(cmd, args, &mut OutputWriter(&mut self.output)).await,
+                };
+                self.prompt().await;
+                ret
+            }
+        }
+    }
 
-            // for byte in &buf[..rx_len] {
-                match byte {
-                    ANSI_ESCAPE if self.escape => {
-                        self.control = true;
-                        Ok(())
-                    }
-                    control::ESC => {
-                        self.escape = true;
-                        Ok(())
+    /// Same as [`AShell::feed`], but the line is split into at most `ARGC`
+    /// arguments with [`tokenize_vars`] before it is handed to `env`.
+    /// Commands run to completion, see [`AShell::feed_from`] to cancel them.
+    pub async fn feed_argv<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, byte:u8) -> ShellResult
+    {
+        self.feed_from::<ARGC>(env, &mut NoSource, byte).await
+    }
+
+    /// Same as [`AShell::feed_argv`], and `source` is read while a command
+    /// runs: Ctrl-C cancels the command, other input is kept as the start
+    /// of the next line, text to print goes straight out.
+    pub async fn feed_from<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, byte:u8) -> ShellResult
+    {
+        let ret = self.feed_argv_line::<ARGC>(env, source, byte).await;
+        self.output.flush().await;
+        ret
+    }
+
+    /// Serve `source` forever: input goes to the line editor and commands
+    /// run in `env`, see [`AShell::feed_from`], text to print goes above
+    /// the prompt.
+    pub async fn run<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source) -> !
+    {
+        let mut buf = [0u8; SOURCE_BUF_LEN];
+        loop {
+            match source.next(&mut buf).await {
+                Event::Input(n) => {
+                    for byte in &buf[..n] {
+                        let _ = self.feed_from::<ARGC>(env, source, *byte).await;
                     }
-                    control_byte if self.control => {
-                        self.escape = false;
-                        self.control = false;
-
-                        const UP: u8 = 0x41;
-                        const DOWN: u8 = 0x42;
-                        const RIGHT: u8 = 0x43;
-                        const LEFT: u8 = 0x44;
-                        match control_byte {
-                            LEFT => self.dpad_left().await,
-                            RIGHT => self.dpad_right().await,
-                            UP => self.dpad_up().await,
-                            DOWN => self.dpad_down().await,
-                            _ => {Ok(())}
+                }
+                Event::Print(n) => {
+                    let _ = self.print_above(&buf[..n]).await;
+                }
+            }
+        }
+    }
+
+    async fn feed_argv_line<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, byte:u8) -> ShellResult
+    {
+        if self.machine.is_some() {
+            return self.feed_request::<ARGC>(env, source, byte).await;
+        }
+        let mut line_buf = [0; CMD_LEN];
+        match self.edit(byte, &mut line_buf).await? {
+            Action::None => Ok(()),
+            Action::Control(code) => env.control(code).await,
+            Action::Line(0) => {
+                self.output.write_all(SHELL_PROMPT.as_bytes()).await;
+                Ok(())
+            }
+            Action::Line(len) if &line_buf[..len] == machine::ENTER.as_bytes() => {
+                self.machine = Some(Machine { seq: 0, overlong: false });
+                self.output.begin(0);
+                self.output.end(0).await;
+                Ok(())
+            }
+            Action::Line(len) => {
+                let ret = self.run_line::<ARGC>(env, source, &line_buf[..len]).await;
+                self.prompt().await;
+                ret
+            }
+        }
+    }
+
+    /// Machine mode: collect a request line without echo and run it, its
+    /// output goes into one response frame.
+    async fn feed_request<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, byte:u8) -> ShellResult
+    {
+        let state = match self.machine.as_mut() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        match byte {
+            b'\r' | b'\n' => {}
+            control::CTRL_C => {
+                self.editor_len = 0;
+                state.overlong = false;
+                return Ok(());
+            }
+            byte @ (b'\t' | 0x20..=0x7e) => {
+                if self.editor_len < CMD_LEN {
+                    self.editor_buf[self.editor_len] = byte;
+                    self.editor_len += 1;
+                } else {
+                    state.overlong = true;
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+        let len = core::mem::take(&mut self.editor_len);
+        let overlong = core::mem::take(&mut state.overlong);
+        if len == 0 && !overlong {
+            //the `\n` of `\r\n`
+            return Ok(());
+        }
+        let mut line_buf = [0; CMD_LEN];
+        line_buf[..len].copy_from_slice(&self.editor_buf[..len]);
+        let line = from_utf8(&line_buf[..len])?;
+        let (seq, line) = match line.trim_start().split_once(' ') {
+            Some((number, rest)) if number.parse::<u32>().is_ok() => (number.parse().unwrap_or(0), rest),
+            _ => (state.seq.wrapping_add(1), line),
+        };
+        state.seq = seq;
+        self.output.begin(seq);
+        self.mid_line = false;
+        let ret = if overlong {
+            self.report("", Err(TokenizeError::TooLong.into()))
+        } else if line.trim() == machine::LEAVE {
+            self.machine = None;
+            Ok(())
+        } else {
+            self.run_line::<ARGC>(env, source, line.as_bytes()).await
+        };
+        self.output.end(exit_status(&ret)).await;
+        if self.machine.is_none() {
+            self.prompt().await;
+        }
+        ret
+    }
+
+    /// Run a line read, as a job if it ends with `&`, and set `$?`.
+    async fn run_line<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, line: &[u8]) -> ShellResult
+    {
+        let ret = match background(line) {
+            Some(line) => {
+                let ret = self.start_job(from_utf8(line)?);
+                self.report("", ret)
+            }
+            None => {
+                let line = from_utf8(line)?;
+                let mut script_buf = [0; SCRIPT_LEN];
+                let text = match self.stored_script(line, &mut script_buf) {
+                    Some(len) => from_utf8(&script_buf[..len])?,
+                    None => line,
+                };
+                self.run_script::<ARGC>(env, source, text).await
+            }
+        };
+        self.vars.set_status(exit_status(&ret));
+        self.mid_line = false;
+        ret
+    }
+
+    /// Run the line editor on one input byte. A finished line is copied
+    /// into `line_buf` and recorded in history.
+    async fn edit(&mut self, byte:u8, line_buf: &mut [u8; CMD_LEN]) -> Result<Action, ShellError>
+    {
+        let last_tab = core::mem::replace(&mut self.last_tab, byte == control::TAB);
+        let mut key = match self.keys.feed(byte) {
+            None => return Ok(Action::None),
+            Some(key) => key,
+        };
+        if self.search.is_some() {
+            key = match self.search_key(key).await? {
+                None => return Ok(Action::None),
+                Some(key) => key,
+            };
+        }
+
+        match key {
+            Key::Left => self.dpad_left().await?,
+            Key::Right => self.dpad_right().await?,
+            Key::Up => self.dpad_up().await?,
+            Key::Down => self.dpad_down().await?,
+            Key::Home => self.move_cursor(0)?,
+            Key::End => self.move_cursor(self.editor_len)?,
+            Key::WordLeft => self.move_cursor(self.word_start())?,
+            Key::WordRight => self.move_cursor(self.word_end())?,
+            Key::Delete => self.delete_forward().await?,
+            Key::Unknown => {}
+            Key::Byte(control::TAB) => {
+                if self.autocomplete_on {
+                    self.suggest(last_tab).await?
+                } else {
+                    self.bell().await?
+                }
+            }
+            Key::Byte(control::DEL) | Key::Byte(control::BS) => self.delete_at_cursor().await?,
+            Key::Byte(control::CTRL_A) => self.move_cursor(0)?,
+            Key::Byte(control::CTRL_E) => self.move_cursor(self.editor_len)?,
+            Key::Byte(control::CTRL_B) => self.dpad_left().await?,
+            Key::Byte(control::CTRL_F) => self.dpad_right().await?,
+            Key::Byte(control::CTRL_P) => self.dpad_up().await?,
+            Key::Byte(control::CTRL_N) => self.dpad_down().await?,
+            Key::Byte(control::CTRL_D) if self.editor_len > 0 => self.delete_forward().await?,
+            Key::Byte(control::CTRL_K) => self.delete_range(self.cursor, self.editor_len).await?,
+            Key::Byte(control::CTRL_U) => self.delete_range(0, self.cursor).await?,
+            Key::Byte(control::CTRL_W) => self.delete_range(self.word_start(), self.cursor).await?,
+            Key::Byte(control::CTRL_R) | Key::Byte(control::CTRL_S) if self.history_on => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    found: None,
+                    backward: key == Key::Byte(control::CTRL_R),
+                    failed: false,
+                });
+                self.draw_search()?;
+            }
+            Key::Byte(control::CTRL_C) => {
+                //drop the line, like sh
+                self.editor_len = 0;
+                self.cursor = 0;
+                self.output.write_all(b"^C").await;
+                return Ok(Action::Line(0));
+            }
+            Key::Byte(control::CTRL_L) => {
+                self.write_str("\x1b[H\x1b[2J")?;
+                self.redraw_line().await?;
+            }
+            Key::Byte(control::CR) => {
+                let line = self.editor_buf[..self.editor_len].trim_ascii();
+                // log::info!("\r\n\t{}-{:?}", line.len(), from_utf8(line));
+                let mut len = line.len();
+                if len > 0  {
+                    line_buf[..len].copy_from_slice(line);
+                    self.editor_len = 0;
+                    self.cursor = 0;
+                    self.output.write_all("\r\n".as_bytes()).await;
+                    if self.history_on {
+                        match expand::<CMD_LEN>(from_utf8(&line_buf[..len])?, &self.history) {
+                            Ok(None) => {}
+                            Ok(Some(expanded)) => {
+                                //echo what is going to run, like sh
+                                self.output.write_all(expanded.as_bytes()).await;
+                                self.output.write_all("\r\n".as_bytes()).await;
+                                len = expanded.len();
+                                line_buf[..len].copy_from_slice(expanded.as_bytes());
+                            }
+                            Err(err) => {
+                                write!(self, "{}", err)?;
+                                return Ok(Action::Line(0));
+                            }
                         }
                     }
-                    _ if self.escape => {
-                        self.escape = false;
-                        self.control = false;
-                        Ok(())
+                    let line_str = from_utf8(&line_buf[..len])?;
+                    self.history
+                        .push(line_str)
+                        .map_err(|_| ShellError::HistoryError)?;
+                }
+                return Ok(Action::Line(len));
+            }
+            Key::Byte(byte) => {
+                let ch = byte as char;
+                if ch.is_ascii_control() {
+                    // env.control(self, byte).await
+                    return Ok(Action::Control(byte));
+                }
+                self.write_at_cursor(byte).await?;
+            }
+        }
+        Ok(Action::None)
+    }
+
+    /// A line that is only the name of a stored script: copy the script
+    /// into `buf`, returns its length.
+    fn stored_script(&self, line: &str, buf: &mut [u8]) -> Option<usize> {
+        if line.contains(|ch: char| ch.is_ascii_whitespace()) {
+            return None;
+        }
+        self.scripts?.load(line, buf)
+    }
+
+    /// Run the statements of `text`, see [`crate::script`]. Returns how the
+    /// last command ended, or the syntax error that stopped the script.
+    async fn run_script<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, text: &str) -> ShellResult {
+        let mut frames: Vec<Frame<'_>, MAX_DEPTH> = Vec::new();
+        let _ = frames.push(Frame { rest: text, body: text, left: 0 });
+        let mut ret = Ok(());
+        let mut started = false;
+        while let Some(frame) = frames.last_mut() {
+            let (statement, rest) = match next_statement(frame.rest) {
+                Ok(Some(next)) => next,
+                Ok(None) => {
+                    //end of a block, once more for a `repeat`
+                    if frame.left > 0 {
+                        frame.left -= 1;
+                        frame.rest = frame.body;
+                    } else {
+                        frames.pop();
                     }
-                    control::TAB => {
-                        if self.autocomplete_on {
-                            self.suggest().await
-                        } else {
-                            self.bell().await
-                        }
+                    continue;
+                }
+                Err(err) => return self.report("", Err(err.into())),
+            };
+            frame.rest = rest;
+            //commands that never wait do not see Ctrl-C themselves
+            if core::mem::replace(&mut started, true) && self.ctrl_c_pending(source).await {
+                self.output.write_all(b"^C").await;
+                return Err(ShellError::Interrupted);
+            }
+            let block = match statement {
+                Statement::Chain(chain) => {
+                    ret = self.run_chain::<ARGC>(env, source, chain).await;
+                    if let Err(ShellError::Interrupted) = ret {
+                        return ret;
                     }
-                    control::DEL | control::BS => self.delete_at_cursor().await,
-                    control::CR => {
-                        let line = self.editor_buf[..self.editor_len].trim_ascii();
-                        // log::info!("\r\n\t{}-{:?}", line.len(), from_utf8(line));
-                        let ret = if line.len() > 0  {
-                            let mut cmd_buf = [0; CMD_LEN];
-                            cmd_buf[..line.len()].copy_from_slice(line);
-                            let line_str = from_utf8(&cmd_buf[..line.len()])?;
-                            self.history
-                                .push(line_str)
-                                .map_err(|_| ShellError::HistoryError)?;
-                            self.editor_len = 0;
-                            self.cursor = 0;
-                            let (cmd, args) = line_str.split_once(" ").unwrap_or((line_str, &""));
-                            //
-                            self.log_buffer.write("\r\n".as_bytes()).await;
-                            // env.command(self, cmd, args).await
-                            env.command(cmd, args).await
-                        } else
-                        {
-                            Ok(())
-                        };
-                        //write prompt
-                        self.log_buffer.write(SHELL_PROMPT.as_bytes()).await;
-                        ret
+                    None
+                }
+                Statement::Repeat { count, body } => match self.repeat_count::<ARGC>(count) {
+                    Ok(0) => None,
+                    Ok(times) => Some((body, times - 1)),
+                    Err(err) => return self.report("repeat", Err(err)),
+                },
+                Statement::If { cond, then, otherwise } => match self.test::<ARGC>(env, source, cond).await {
+                    Ok(true) => Some((then, 0)),
+                    Ok(false) => Some((otherwise, 0)),
+                    Err(err) => return self.report("if", Err(err)),
+                },
+            };
+            if let Some((body, left)) = block {
+                if frames.push(Frame { rest: body, body, left }).is_err() {
+                    return self.report("", Err(ScriptError::TooDeep.into()));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Run the commands of `chain` joined by `&&` and `||`.
+    async fn run_chain<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, chain: &str) -> ShellResult {
+        let mut rest = chain;
+        let mut run = true;
+        let mut ret = Ok(());
+        loop {
+            let (command, link) = next_link(rest);
+            if command.is_empty() {
+                return self.report("", Err(ScriptError::Syntax.into()));
+            }
+            if run {
+                ret = self.run_simple::<ARGC>(env, source, command).await;
+                if let Err(ShellError::Interrupted) = ret {
+                    return ret;
+                }
+            }
+            match link {
+                None => return ret,
+                Some((link, next)) => {
+                    //a skipped command leaves `ret` as it was, like sh
+                    run = (link == Link::And) == ret.is_ok();
+                    rest = next;
+                }
+            }
+        }
+    }
+
+    /// Expand and run one command, built-ins first, and set `$?`.
+    async fn run_simple<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, command: &str) -> ShellResult {
+        if core::mem::take(&mut self.mid_line) {
+            //each command starts on a line of its own
+            self.write_str("\r\n")?;
+        }
+        let len = command.len();
+        let mut argv_buf = [0; ARGV_BUF_LEN];
+        let mut alias_buf = [0; ARGV_BUF_LEN];
+        let ret = if len > ARGV_BUF_LEN {
+            self.report("", Err(TokenizeError::TooLong.into()))
+        } else {
+            argv_buf[..len].copy_from_slice(command.as_bytes());
+            match tokenize_vars::<ARGC>(&mut argv_buf, len, &self.vars) {
+                Ok(argv) if argv.is_empty() => Ok(()),
+                Ok(argv) => match self.expand_alias::<ARGC>(&argv, &mut alias_buf) {
+                    Ok(Some(expanded)) if expanded.is_empty() => Ok(()),
+                    Ok(Some(expanded)) => self.dispatch(env, source, &expanded).await,
+                    Ok(None) => self.dispatch(env, source, &argv).await,
+                    Err(err) => self.report(argv[0], Err(err)),
+                },
+                Err(err) => self.report("", Err(err.into())),
+            }
+        };
+        self.vars.set_status(exit_status(&ret));
+        ret
+    }
+
+    /// Run `argv` as a built-in, or in `env`.
+    async fn dispatch(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, argv: &[&str]) -> ShellResult {
+        if self.scripts.map_or(false, |scripts| scripts.contains(argv[0])) {
+            return self.report(argv[0], Err(ScriptError::Nested.into()));
+        }
+        match self.builtin(argv, source).await {
+            Some(ret) => self.report(argv[0], ret),
+            None => self.run_command(env, source, argv).await,
+        }
+    }
+
+    /// `argv` with its alias filled in, split in `buf`, see [`crate::alias`].
+    /// `None` when `argv[0]` is no alias.
+    fn expand_alias<'b, const ARGC: usize>(&self, argv: &[&str], buf: &'b mut [u8]) -> Result<Option<Vec<&'b str, ARGC>>, ShellError> {
+        let mut text = [0; ALIAS_LEN];
+        let len = match self.aliases.and_then(|aliases| aliases.load(argv[0], &mut text)) {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut line: String<ARGV_BUF_LEN> = String::new();
+        substitute(from_utf8(&text[..len])?, &argv[1..], &mut line).map_err(|_| TokenizeError::TooLong)?;
+        buf[..line.len()].copy_from_slice(line.as_bytes());
+        Ok(Some(tokenize_vars::<ARGC>(buf, line.len(), &self.vars)?))
+    }
+
+    /// Condition of an `if`: a comparison, or a command that has to succeed.
+    async fn test<const ARGC: usize>(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, cond: &str) -> Result<bool, ShellError> {
+        let len = cond.len();
+        let mut argv_buf = [0; ARGV_BUF_LEN];
+        if len <= ARGV_BUF_LEN {
+            argv_buf[..len].copy_from_slice(cond.as_bytes());
+            if let Ok(argv) = tokenize_vars::<ARGC>(&mut argv_buf, len, &self.vars) {
+                if let Some(result) = compare(&argv) {
+                    return result.map_err(Into::into);
+                }
+            }
+        }
+        match self.run_simple::<ARGC>(env, source, cond).await {
+            Ok(()) => Ok(true),
+            Err(ShellError::Interrupted) => Err(ShellError::Interrupted),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Times a `repeat` runs, `count` may be a `$NAME`.
+    fn repeat_count<const ARGC: usize>(&self, count: &str) -> Result<u32, ShellError> {
+        let len = count.len();
+        let mut argv_buf = [0; ARGV_BUF_LEN];
+        if len > ARGV_BUF_LEN {
+            return Err(ScriptError::BadCount.into());
+        }
+        argv_buf[..len].copy_from_slice(count.as_bytes());
+        let argv = tokenize_vars::<ARGC>(&mut argv_buf, len, &self.vars)?;
+        match argv[..] {
+            [count] => count.parse().map_err(|_| ScriptError::BadCount.into()),
+            _ => Err(ScriptError::BadCount.into()),
+        }
+    }
+
+    /// Whether Ctrl-C is waiting in `source`, for scripts of commands that
+    /// return without waiting, where [`AShell::run_command`] never looks.
+    async fn ctrl_c_pending(&mut self, source: &mut impl Source) -> bool {
+        let output = RefCell::new(&mut self.output);
+        let interrupt = wait_ctrl_c(&output, &mut self.editor_buf, &mut self.editor_len, source);
+        let pending = matches!(select(interrupt, core::future::ready(())).await, Either::First(()));
+        self.cursor = self.editor_len;
+        pending
+    }
+
+    /// Run `argv` in `env` until it returns or Ctrl-C arrives from `source`.
+    /// `argv` may end with filters, see [`crate::filter`].
+    async fn run_command(&mut self, env: &mut impl ArgvEnvironment, source: &mut impl Source, argv: &[&str]) -> ShellResult {
+        let name = argv[0];
+        let (argv, filters) = match split_pipeline(argv) {
+            Ok(split) => split,
+            Err(err) => return self.report(name, Err(err.into())),
+        };
+        // the command and the source loop both print, never across an await
+        let output = RefCell::new(&mut self.output);
+        let mut writer = SharedWriter(&output);
+        let mut pipeline = Pipeline::new(filters, &mut writer);
+        let command = async {
+            env.command(argv, &mut pipeline).await
+        };
+        let interrupt = wait_ctrl_c(&output, &mut self.editor_buf, &mut self.editor_len, source);
+        let ended = select(command, interrupt).await;
+        let ret = match ended {
+            //a full `head` stopped the command
+            Either::First(Err(ShellError::FormatError(_))) if pipeline.done() => Ok(()),
+            Either::First(ret) => ret,
+            Either::Second(()) => {
+                let _ = pipeline.interrupted();
+                Err(ShellError::Interrupted)
+            }
+        };
+        let _ = pipeline.finish();
+        let mut mid_line = pipeline.mid_line();
+        if let Err(err) = &ret {
+            if reportable(err) {
+                let sep = if mid_line { "\r\n" } else { "" };
+                let _ = write!(writer, "{}{}: {}", sep, name, err);
+                mid_line = true;
+            }
+        }
+        self.mid_line = mid_line;
+        self.cursor = self.editor_len;
+        ret
+    }
+
+    /// Print what went wrong, `name: error`, and pass `ret` on.
+    fn report(&mut self, name: &str, ret: ShellResult) -> ShellResult {
+        if let Err(err) = &ret {
+            if reportable(err) {
+                let sep = if core::mem::replace(&mut self.mid_line, true) { "\r\n" } else { "" };
+                let _ = match name {
+                    "" => write!(self, "{}{}", sep, err),
+                    name => write!(self, "{}{}: {}", sep, name, err),
+                };
+            }
+        }
+        ret
+    }
+
+    /// Write the prompt, and the type-ahead a command left in the editor.
+    async fn prompt(&mut self) {
+        self.output.write_all(SHELL_PROMPT.as_bytes()).await;
+        self.output.write_all(&self.editor_buf[..self.editor_len]).await;
+    }
+
+    /// Commands the shell handles itself, before the environment sees them.
+    /// Returns `None` when `argv` is not one of them.
+    async fn builtin(&mut self, argv: &[&str], source: &mut impl Source) -> Option<ShellResult> {
+        match argv {
+            ["history"] => Some(self.list_history().await),
+            ["history", "-c"] => {
+                self.history.reset();
+                Some(Ok(()))
+            }
+            ["jobs"] | ["fg", ..] | ["kill", ..] if self.jobs.is_some() => {
+                Some(self.job_builtin(argv, source).await)
+            }
+            ["set"] | ["env"] => Some(self.list_vars()),
+            ["set", name, words @ ..] => {
+                let mut value: String<VALUE_LEN> = String::new();
+                for (i, word) in words.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    if write!(value, "{}{}", sep, word).is_err() {
+                        return Some(Err(VarError::TooLong.into()));
                     }
-                    _ => {
-                        let ch = byte as char;
-                        if ch.is_ascii_control() {
-                            // env.control(self, byte).await
-                            env.control(byte).await
-                        } else {
-                            self.write_at_cursor(byte).await
-                        }
+                }
+                Some(self.vars.set(name, &value).map_err(Into::into))
+            }
+            ["unset", names @ ..] => {
+                for name in names {
+                    self.vars.unset(name);
+                }
+                Some(Ok(()))
+            }
+            ["script", ..] if self.scripts.is_some() => Some(self.script_builtin(argv)),
+            ["alias", ..] | ["unalias", ..] if self.aliases.is_some() => Some(self.alias_builtin(argv)),
+            _ => None,
+        }
+    }
+
+    /// Book `line` in the job table, `[n]` tells its number.
+    fn start_job(&mut self, line: &str) -> ShellResult {
+        if line.is_empty() {
+            return Ok(());
+        }
+        let started = match self.jobs {
+            Some(jobs) => jobs.start(line),
+            None => Err(JobError::NoJobControl),
+        };
+        write!(self, "[{}]", started?)?;
+        Ok(())
+    }
+
+    /// `jobs`, `fg [n]` and `kill <n>`, a job number may start with `%`.
+    async fn job_builtin(&mut self, argv: &[&str], source: &mut impl Source) -> ShellResult {
+        let jobs = self.jobs.ok_or(JobError::NoJobControl)?;
+        match argv {
+            ["jobs"] => {
+                jobs.list(&mut OutputWriter(&mut self.output))?;
+                self.mid_line = jobs.last().is_some();
+            }
+            ["kill", number] => jobs.kill(job_number(number)?)?,
+            ["fg"] | ["fg", _] => {
+                let number = match argv.get(1) {
+                    Some(number) => job_number(number)?,
+                    None => jobs.last().ok_or(JobError::NoSuchJob)?,
+                };
+                //echo the line, like sh
+                jobs.describe(number, &mut OutputWriter(&mut self.output))?;
+                self.write_str("\r\n")?;
+                let output = RefCell::new(&mut self.output);
+                let gone = poll_fn(|cx| jobs.poll_gone(number, cx));
+                let interrupt = wait_ctrl_c(&output, &mut self.editor_buf, &mut self.editor_len, source);
+                if let Either::Second(()) = select(gone, interrupt).await {
+                    let _ = jobs.kill(number);
+                    self.output.write_all(b"^C").await;
+                    self.cursor = self.editor_len;
+                    return Err(ShellError::Interrupted);
+                }
+                self.cursor = self.editor_len;
+            }
+            _ => return Err(JobError::NoSuchJob.into()),
+        }
+        Ok(())
+    }
+
+    /// `script [list]`, `script show|rm <name>`, and `script set|add <name>
+    /// <line..>` to replace a script or add a line to it.
+    fn script_builtin(&mut self, argv: &[&str]) -> ShellResult {
+        let scripts = self.scripts.ok_or(ScriptError::NoSuchScript)?;
+        let mut out = OutputWriter(&mut self.output);
+        match argv {
+            ["script"] | ["script", "list"] => {
+                let mut first = true;
+                scripts.each(&mut |name, _| {
+                    let sep = if core::mem::replace(&mut first, false) { "" } else { "\r\n" };
+                    let _ = write!(out, "{}{}", sep, name);
+                });
+                self.mid_line = !first;
+            }
+            ["script", "show", name] => {
+                let mut buf = [0; SCRIPT_LEN];
+                let len = scripts.load(name, &mut buf).ok_or(ScriptError::NoSuchScript)?;
+                for (i, line) in from_utf8(&buf[..len])?.split('\n').enumerate() {
+                    let sep = if i == 0 { "" } else { "\r\n" };
+                    write!(out, "{}{}", sep, line)?;
+                }
+                self.mid_line = true;
+            }
+            ["script", "rm", name] => scripts.remove(name)?,
+            ["script", verb @ ("set" | "add"), name, words @ ..] if !words.is_empty() => {
+                let mut text: String<SCRIPT_LEN> = String::new();
+                let mut buf = [0; SCRIPT_LEN];
+                if let (&"add", Some(len)) = (verb, scripts.load(name, &mut buf)) {
+                    write!(text, "{}\n", from_utf8(&buf[..len])?).map_err(|_| ScriptError::TooLong)?;
+                }
+                for (i, word) in words.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(text, "{}{}", sep, word).map_err(|_| ScriptError::TooLong)?;
+                }
+                scripts.save(name, &text)?;
+            }
+            _ => return Err(ScriptError::Syntax.into()),
+        }
+        Ok(())
+    }
+
+    /// `alias` lists, `alias name` shows one, `alias name=text..` sets one,
+    /// `unalias names..` removes them.
+    fn alias_builtin(&mut self, argv: &[&str]) -> ShellResult {
+        let aliases = self.aliases.ok_or(ScriptError::NoSuchAlias)?;
+        let mut out = OutputWriter(&mut self.output);
+        match argv {
+            ["alias"] => {
+                let mut first = true;
+                aliases.each(&mut |name, text| {
+                    let sep = if core::mem::replace(&mut first, false) { "" } else { "\r\n" };
+                    let _ = write!(out, "{}{}='{}'", sep, name, text);
+                });
+                self.mid_line = !first;
+            }
+            ["alias", name] if !name.contains('=') => {
+                let mut text = [0; ALIAS_LEN];
+                let len = aliases.load(name, &mut text).ok_or(ScriptError::NoSuchAlias)?;
+                write!(out, "{}='{}'", name, from_utf8(&text[..len])?)?;
+                self.mid_line = true;
+            }
+            ["alias", definition, words @ ..] => {
+                let (name, first) = definition.split_once('=').ok_or(ScriptError::Syntax)?;
+                let mut text: String<ALIAS_LEN> = String::new();
+                for (i, word) in core::iter::once(first).chain(words.iter().copied()).enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    write!(text, "{}{}", sep, word).map_err(|_| ScriptError::TooLong)?;
+                }
+                aliases.save(name, &text)?;
+            }
+            ["unalias", names @ ..] => {
+                for name in names {
+                    aliases.remove(name).map_err(|err| match err {
+                        ScriptError::NoSuchScript => ScriptError::NoSuchAlias,
+                        err => err,
+                    })?;
+                }
+            }
+            _ => return Err(ScriptError::Syntax.into()),
+        }
+        Ok(())
+    }
+
+    /// `NAME=value` for every variable, oldest first.
+    fn list_vars(&mut self) -> ShellResult {
+        let mut out = OutputWriter(&mut self.output);
+        for (i, (name, value)) in self.vars.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "\r\n" };
+            write!(out, "{}{}={}", sep, name, value)?;
+        }
+        self.mid_line = self.vars.iter().next().is_some();
+        Ok(())
+    }
+
+    /// Print the history oldest first, with the numbers `!n` refers to.
+    async fn list_history(&mut self) -> ShellResult {
+        self.mid_line = self.history.len() > 0;
+        for idx in (0..self.history.len()).rev() {
+            if let Some((number, line)) = self.history.nth(idx) {
+                //the prompt starts with a new line, so only separate entries
+                let mut head: String<12> = String::new();
+                let sep = if idx + 1 == self.history.len() { "" } else { "\r\n" };
+                write!(head, "{}{:>5}  ", sep, number)?;
+                self.output.write_all(head.as_bytes()).await;
+                self.output.write_all(line.as_bytes()).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print `text` above the prompt, for log lines and other output that
+    /// does not come from a command. The prompt line is erased, the complete
+    /// lines of `text` are written, then the prompt and the line being edited
+    /// are drawn again with the cursor where it was. A trailing partial line
+    /// is held back until the rest of it arrives.
+    pub async fn print_above(&mut self, text: &[u8]) -> ShellResult {
+        let mut erased = false;
+        for byte in text {
+            match *byte {
+                b'\r' => {}
+                b'\n' => self.print_above_line(&mut erased).await,
+                byte => {
+                    if self.above_line.is_full() {
+                        self.print_above_line(&mut erased).await;
                     }
+                    let _ = self.above_line.push(byte);
                 }
-            // }
-        // }
+            }
+        }
+        if erased {
+            if self.search.is_some() {
+                self.draw_search()?;
+            } else {
+                self.redraw_current_line()?;
+            }
+        }
+        self.output.flush().await;
+        Ok(())
+    }
+
+    async fn print_above_line(&mut self, erased: &mut bool) {
+        if self.machine.is_some() {
+            self.output.log(&self.above_line).await;
+            self.above_line.clear();
+            return;
+        }
+        if !*erased {
+            self.output.write_all(b"\r\x1b[K").await;
+            *erased = true;
+        }
+        self.output.write_all(&self.above_line).await;
+        self.output.write_all(b"\r\n").await;
+        self.above_line.clear();
     }
 
     pub fn clear(&mut self) -> ShellResult {
@@ -221,7 +1130,7 @@ where
 
     pub async fn bell(&mut self) -> ShellResult {
         // block!(self.serial.write(control::BELL)).map_err(ShellError::WriteError)
-        self.log_buffer.write(&[control::BELL as u8]).await;
+        self.output.write_all(&[control::BELL as u8]).await;
         Ok(())
     }
 
@@ -232,10 +1141,10 @@ where
     }
 
     async fn write_at_cursor(&mut self, byte: u8) -> ShellResult {
-        if self.cursor == self.editor_buf.len() {
+        if self.editor_len == self.editor_buf.len() {
             self.bell().await?;
         } else if self.cursor < self.editor_len {
-            self.log_buffer.write(&[byte]).await;
+            self.output.write_all(&[byte]).await;
 
             self.editor_buf
                 .copy_within(self.cursor..self.editor_len, self.cursor + 1);
@@ -247,14 +1156,14 @@ where
             // for b in &self.editor_buf[self.cursor..self.editor_len] {
                 // self.serial.write(*b).await.map_err(ShellError::WriteError)?;
             // }
-            self.log_buffer.write(&self.editor_buf[self.cursor..self.editor_len]).await;
+            self.output.write_all(&self.editor_buf[self.cursor..self.editor_len]).await;
             self.write_str("\x1b[u")?;
         } else {
             self.editor_buf[self.cursor] = byte;
             // self.editor_buf.push(byte);
             self.cursor += 1;
             self.editor_len += 1;
-            self.log_buffer.write(&[byte]).await;
+            self.output.write_all(&[byte]).await;
         }
         Ok(())
     }
@@ -271,7 +1180,7 @@ where
             // for b in &self.editor_buf[self.cursor..self.editor_len] {
                 // self.serial.write(*b).await.map_err(|_| ShellError::WriteError)?;
             // }
-            self.log_buffer.write(&self.editor_buf[self.cursor..self.editor_len]).await;
+            self.output.write_all(&self.editor_buf[self.cursor..self.editor_len]).await;
             self.write_str("\x1b[u")?;
         } else {
             self.cursor -= 1;
@@ -281,6 +1190,145 @@ where
         Ok(())
     }
 
+    /// Handle `key` while searching. Keys that end the search are returned
+    /// to be handled as usual, after the match was put in the editor buffer.
+    async fn search_key(&mut self, key: Key) -> Result<Option<Key>, ShellError> {
+        let search = match self.search.as_mut() {
+            Some(search) => search,
+            None => return Ok(Some(key)),
+        };
+        // where to look for the next match
+        let from = match key {
+            Key::Byte(control::CTRL_R) | Key::Byte(control::CTRL_S) => {
+                search.backward = key == Key::Byte(control::CTRL_R);
+                let idx = search.found.as_ref().map(|(idx, _)| *idx);
+                match (idx, search.backward) {
+                    (None, _) => 0,
+                    (Some(idx), true) => idx + 1,
+                    (Some(0), false) => {
+                        search.failed = true;
+                        return self.draw_search().map(|_| None);
+                    }
+                    (Some(idx), false) => idx - 1,
+                }
+            }
+            Key::Byte(control::DEL) | Key::Byte(control::BS) => {
+                search.query.pop();
+                0
+            }
+            Key::Byte(control::CTRL_G) | Key::Byte(control::CTRL_C) => {
+                // back to the line as it was before the search
+                self.search = None;
+                self.redraw_current_line()?;
+                return Ok(None);
+            }
+            Key::Byte(byte) if !(byte as char).is_ascii_control() => {
+                if search.query.push(byte as char).is_err() {
+                    return self.bell().await.map(|_| None);
+                }
+                // the current match may still match the longer query
+                search.found.as_ref().map_or(0, |(idx, _)| *idx)
+            }
+            _ => {
+                // accept the match and handle the key as usual
+                if let Some((_, line)) = self.search.take().and_then(|search| search.found) {
+                    let bytes = line.as_bytes();
+                    self.editor_buf[..bytes.len()].copy_from_slice(bytes);
+                    self.editor_len = bytes.len();
+                    self.cursor = bytes.len();
+                }
+                self.redraw_current_line()?;
+                return Ok(Some(key));
+            }
+        };
+
+        let found = self.history.search(&search.query, from, search.backward);
+        let failed = found.is_none();
+        search.failed = failed;
+        if found.is_some() {
+            search.found = found;
+        }
+        self.draw_search()?;
+        if failed {
+            self.bell().await?;
+        }
+        Ok(None)
+    }
+
+    /// Replace the current terminal line with the search prompt and match.
+    fn draw_search(&mut self) -> ShellResult {
+        let search = match self.search.as_ref() {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        let mut out = OutputWriter(&mut self.output);
+        let failed = if search.failed { "failed " } else { "" };
+        let direction = if search.backward { "reverse-" } else { "" };
+        let found = search.found.as_ref().map_or("", |(_, line)| line.as_str());
+        write!(out, "\r\x1b[K({}{}i-search)`{}': {}", failed, direction, search.query.as_str(), found)?;
+        Ok(())
+    }
+
+    /// Replace the current terminal line with the prompt and editor buffer.
+    fn redraw_current_line(&mut self) -> ShellResult {
+        let mut out = OutputWriter(&mut self.output);
+        let line = from_utf8(&self.editor_buf[..self.editor_len])?;
+        write!(out, "\r\x1b[K{}{}", PROMPT, line)?;
+        let back = self.editor_len - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        Ok(())
+    }
+
+    /// Move the cursor to `pos` in the editor buffer.
+    fn move_cursor(&mut self, pos: usize) -> ShellResult {
+        if pos < self.cursor {
+            write!(self, "\x1b[{}D", self.cursor - pos)?;
+        } else if pos > self.cursor {
+            write!(self, "\x1b[{}C", pos - self.cursor)?;
+        }
+        self.cursor = pos;
+        Ok(())
+    }
+
+    /// Start of the word before the cursor, blanks in between are skipped.
+    fn word_start(&self) -> usize {
+        let line = &self.editor_buf[..self.cursor];
+        let end = line.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+        line[..end].iter().rposition(|b| *b == b' ').map_or(0, |i| i + 1)
+    }
+
+    /// End of the word after the cursor, blanks in between are skipped.
+    fn word_end(&self) -> usize {
+        let line = &self.editor_buf[self.cursor..self.editor_len];
+        let start = line.iter().position(|b| *b != b' ').unwrap_or(line.len());
+        let end = line[start..].iter().position(|b| *b == b' ').map_or(line.len(), |i| start + i);
+        self.cursor + end
+    }
+
+    /// Remove `start..end` from the editor buffer and leave the cursor at `start`.
+    async fn delete_range(&mut self, start: usize, end: usize) -> ShellResult {
+        if start >= end {
+            return self.bell().await;
+        }
+        self.move_cursor(start)?;
+        self.editor_buf.copy_within(end..self.editor_len, start);
+        self.editor_len -= end - start;
+        self.write_str("\x1b[s\x1b[K")?;
+        self.output.write_all(&self.editor_buf[self.cursor..self.editor_len]).await;
+        self.write_str("\x1b[u")?;
+        Ok(())
+    }
+
+    async fn delete_forward(&mut self) -> ShellResult {
+        if self.cursor < self.editor_len {
+            self.delete_range(self.cursor, self.cursor + 1).await
+        } else {
+            self.bell().await
+        }
+    }
+
     async fn dpad_left(&mut self) -> ShellResult {
         if self.cursor > 0 {
             self.cursor -= 1;
@@ -321,17 +1369,67 @@ where
         }
     }
 
-    async fn suggest(&mut self) -> ShellResult {
+    /// Complete the word before the cursor up to the longest common prefix
+    /// of all candidates. With nothing left to fill, a second TAB lists them.
+    async fn suggest(&mut self, last_tab: bool) -> ShellResult {
         let prefix = from_utf8(&self.editor_buf[..self.cursor])?;
-        match self.autocomplete.suggest(prefix) {
-            None => self.bell().await?,
-            Some(suffix) => {
-                let bytes = suffix.as_bytes();
-                self.editor_buf[self.cursor..(self.cursor + bytes.len())].copy_from_slice(bytes);
-                self.cursor += bytes.len();
-                self.editor_len = self.cursor;
-                write!(self, "\x1b[K{}", suffix.as_str())?;
+        let partial_len = last_word(prefix).len();
+        let mut common: String<CMD_LEN> = String::new();
+        let mut count = 0;
+        let mut width = 0;
+        complete(&self.autocomplete, [self.aliases, self.scripts], prefix, &mut |candidate| {
+            if count == 0 {
+                common = String::from_str(candidate).unwrap_or_default();
+            } else {
+                common.truncate(common_prefix_len(&common, candidate));
             }
+            count += 1;
+            width = width.max(candidate.len());
+        });
+
+        if count == 0 || common.len() < partial_len {
+            return self.bell().await;
+        }
+        let fill = &common.as_bytes()[partial_len..];
+        if !fill.is_empty() || count == 1 {
+            for byte in fill {
+                self.write_at_cursor(*byte).await?;
+            }
+            if count == 1 && self.cursor == self.editor_len {
+                self.write_at_cursor(b' ').await?;
+            }
+            return Ok(());
+        }
+        if !last_tab {
+            return self.bell().await;
+        }
+        self.list_candidates(width + 2).await
+    }
+
+    /// Print all candidates in columns of `width`, then redraw the prompt
+    /// and the line with the cursor where it was.
+    async fn list_candidates(&mut self, width: usize) -> ShellResult {
+        let columns = (TERM_WIDTH / width).max(1);
+        let mut out = OutputWriter(&mut self.output);
+        let mut column = 0;
+        let prefix = from_utf8(&self.editor_buf[..self.cursor])?;
+        complete(&self.autocomplete, [self.aliases, self.scripts], prefix, &mut |candidate| {
+            if column % columns == 0 {
+                let _ = out.write_str("\r\n");
+            }
+            let _ = write!(out, "{:<1$}", candidate, width);
+            column += 1;
+        });
+        self.redraw_line().await
+    }
+
+    /// Write the prompt and the whole editor buffer on a new line.
+    async fn redraw_line(&mut self) -> ShellResult {
+        self.output.write_all(SHELL_PROMPT.as_bytes()).await;
+        self.output.write_all(&self.editor_buf[..self.editor_len]).await;
+        let back = self.editor_len - self.cursor;
+        if back > 0 {
+            write!(self, "\x1b[{}D", back)?;
         }
         Ok(())
     }
@@ -352,14 +1450,15 @@ where
     }
 }
 
-impl<A, H, const CMD_LEN: usize, const LOG_LEN: usize> core::fmt::Write for AShell<A, H, CMD_LEN, LOG_LEN>
+impl<A, H, O, const CMD_LEN: usize> core::fmt::Write for AShell<A, H, O, CMD_LEN>
 where
     // S: AsyncRead + AsyncWrite,
     A: Autocomplete<CMD_LEN>,
     H: History<CMD_LEN>,
+    O: Output,
 {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.log_buffer.try_write(s.as_bytes());
+        self.output.try_write(s.as_bytes());
         Ok(())
     }
 }
@@ -384,6 +1483,120 @@ where
 //     fn flush(&self) {}
 // }
 
+/// `cmd &` asks to run `cmd` in the background: the line without its
+/// trailing `&`, `None` for a line to run in the foreground.
+fn background(line: &[u8]) -> Option<&[u8]> {
+    let rest = line.strip_suffix(b"&")?;
+    match rest.last() {
+        Some(b'\\') | Some(b'&') => None,
+        _ => Some(rest.trim_ascii()),
+    }
+}
+
+/// Completions of the word before the cursor: those of `autocomplete`, and
+/// the names in `stores` for the first word of a line.
+fn complete<const CMD_LEN: usize>(
+    autocomplete: &impl Autocomplete<CMD_LEN>,
+    stores: [Option<&'static dyn ScriptStore>; 2],
+    prefix: &str,
+    add: &mut dyn FnMut(&str),
+) {
+    autocomplete.candidates(prefix, add);
+    let word = prefix.trim_start();
+    if word.contains(' ') {
+        return;
+    }
+    for store in stores.into_iter().flatten() {
+        store.each(&mut |name, _| {
+            if name.starts_with(word) {
+                add(name);
+            }
+        });
+    }
+}
+
+/// `$?` after `ret`.
+fn exit_status(ret: &ShellResult) -> i32 {
+    match ret {
+        Ok(()) => 0,
+        Err(err) => err.status(),
+    }
+}
+
+fn job_number(arg: &str) -> Result<u16, JobError> {
+    arg.strip_prefix('%')
+        .unwrap_or(arg)
+        .parse()
+        .map_err(|_| JobError::NoSuchJob)
+}
+
+/// Read `source` until Ctrl-C, while a command or a job runs in the
+/// foreground. Other input is kept in the editor as the start of the next
+/// line, text to print goes straight out.
+async fn wait_ctrl_c<O: Output, const CMD_LEN: usize>(
+    output: &RefCell<&mut O>,
+    editor_buf: &mut [u8; CMD_LEN],
+    editor_len: &mut usize,
+    source: &mut impl Source,
+) {
+    let mut buf = [0u8; SOURCE_BUF_LEN];
+    loop {
+        match source.next(&mut buf).await {
+            Event::Input(n) => {
+                for byte in &buf[..n] {
+                    match *byte {
+                        control::CTRL_C => return,
+                        //type-ahead, shown with the next prompt
+                        byte @ 0x20..=0x7e if *editor_len < CMD_LEN => {
+                            editor_buf[*editor_len] = byte;
+                            *editor_len += 1;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::Print(n) => {
+                output.borrow_mut().print(&buf[..n]);
+            }
+        }
+    }
+}
+
+/// Errors the shell prints. Argument errors come with the usage from
+/// [`Registry::dispatch`](crate::command::Registry::dispatch), and `^C` is
+/// echoed already.
+fn reportable(err: &ShellError) -> bool {
+    !matches!(err, ShellError::ArgError(_) | ShellError::Interrupted)
+}
+
+/// Source of [`AShell::feed_argv`], which has nothing to read.
+struct NoSource;
+
+impl Source for NoSource {
+    async fn next(&mut self, _buf: &mut [u8]) -> Event {
+        core::future::pending().await
+    }
+}
+
+/// Output of a running command, see [`AShell::run_command`].
+struct SharedWriter<'a, 'o, O: Output>(&'a RefCell<&'o mut O>);
+
+impl<'a, 'o, O: Output> core::fmt::Write for SharedWriter<'a, 'o, O> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        //output that does not fit fails the command, it is not lost without a word
+        match self.0.borrow_mut().try_write(s.as_bytes()) == s.len() {
+            true => Ok(()),
+            false => Err(core::fmt::Error),
+        }
+    }
+}
+
+impl<'a, 'o, O: Output> CommandOutput for SharedWriter<'a, 'o, O> {
+    fn poll_room(&mut self, cx: &mut core::task::Context<'_>, len: usize) -> core::task::Poll<()> {
+        self.0.borrow_mut().poll_room(cx, len)
+    }
+}
+
 pub struct LogWriter<'d, const N: usize>(pub Writer<'d, CS, N>);
 
 impl<'d, const N: usize> core::fmt::Write for LogWriter<'d, N> {
@@ -0,0 +1,184 @@
+use core::fmt;
+use core::str::from_utf8;
+
+use crate::heapless::Vec;
+
+/// Errors produced while splitting a command line into arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenizeError {
+    /// More arguments than the argv capacity.
+    TooManyArgs,
+    /// A `'` or `"` was opened but never closed.
+    UnterminatedQuote,
+    /// The line ends with a lone `\`.
+    TrailingEscape,
+    /// The resulting argument is not valid UTF-8.
+    BadInput,
+    /// Expanded variables do not fit the buffer.
+    TooLong,
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizeError::TooManyArgs => write!(f, "too many arguments"),
+            TokenizeError::UnterminatedQuote => write!(f, "unterminated quote"),
+            TokenizeError::TrailingEscape => write!(f, "line ends with \\"),
+            TokenizeError::BadInput => write!(f, "invalid UTF-8"),
+            TokenizeError::TooLong => write!(f, "line too long after expansion"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// Values for `$NAME` in [`tokenize_vars`].
+pub trait Variables {
+    fn get(&self, name: &str) -> Option<&str>;
+}
+
+/// Split `line` into an argv-style list of arguments, sh style.
+///
+/// - blanks (space, tab) separate arguments outside of quotes
+/// - `'...'` is taken literally
+/// - `"..."` is taken literally except `\"`, `\\` and `\$`
+/// - `\x` outside of quotes is `x`
+/// - quoted and unquoted parts next to each other form one argument,
+///   `a"b c"d` is `ab cd`, and `""` is an empty argument
+///
+/// Quotes and escapes are removed in place, so `line` is clobbered and the
+/// returned arguments borrow from it. No allocation happens; at most `ARGC`
+/// arguments are accepted.
+pub fn tokenize<const ARGC: usize>(line: &mut [u8]) -> Result<Vec<&str, ARGC>, TokenizeError> {
+    let len = line.len();
+    split::<ARGC>(line, len, None)
+}
+
+/// Same as [`tokenize`], and `$NAME`, `${NAME}` and `$?` outside of single
+/// quotes are replaced by their value in `vars`, or by nothing. A value is
+/// not split into more arguments.
+///
+/// The line is the first `len` bytes of `buf`, the rest is room for values
+/// longer than their `$NAME`.
+pub fn tokenize_vars<'b, const ARGC: usize>(
+    buf: &'b mut [u8],
+    len: usize,
+    vars: &dyn Variables,
+) -> Result<Vec<&'b str, ARGC>, TokenizeError> {
+    split::<ARGC>(buf, len, Some(vars))
+}
+
+/// Length of the variable name at the start of `rest`, and of what refers
+/// to it, `NAME` or `{NAME}`.
+fn var_name(rest: &[u8]) -> Option<(core::ops::Range<usize>, usize)> {
+    let is_name = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+    match rest {
+        [b'?', ..] => Some((0..1, 1)),
+        [b'{', name @ ..] => {
+            let end = name.iter().position(|b| *b == b'}')?;
+            Some((1..end + 1, end + 2))
+        }
+        _ => match rest.iter().take_while(|b| is_name(b)).count() {
+            0 => None,
+            n => Some((0..n, n)),
+        },
+    }
+}
+
+fn split<'b, const ARGC: usize>(
+    buf: &'b mut [u8],
+    len: usize,
+    vars: Option<&dyn Variables>,
+) -> Result<Vec<&'b str, ARGC>, TokenizeError> {
+    let mut spans: Vec<(usize, usize), ARGC> = Vec::new();
+    let mut quote = Quote::None;
+    let mut escape = false;
+    // start of the argument being built, if any
+    let mut start: Option<usize> = None;
+    let mut w = 0;
+
+    // the line is read from the end of `buf` while arguments are written
+    // from its start, so expanded values have room to grow
+    let end = buf.len();
+    buf.copy_within(0..len, end - len);
+    let mut r = end - len;
+    while r < end {
+        let byte = buf[r];
+        r += 1;
+        if escape {
+            escape = false;
+            start.get_or_insert(w);
+            buf[w] = byte;
+            w += 1;
+            continue;
+        }
+        match (quote, byte, vars) {
+            (Quote::None | Quote::Double, b'$', Some(vars)) if var_name(&buf[r..end]).is_some() => {
+                let (name, skip) = var_name(&buf[r..end]).unwrap_or((0..0, 0));
+                let name = from_utf8(&buf[r + name.start..r + name.end]).map_err(|_| TokenizeError::BadInput)?;
+                let value = vars.get(name).unwrap_or("").as_bytes();
+                r += skip;
+                if w + value.len() > r {
+                    return Err(TokenizeError::TooLong);
+                }
+                //an empty value outside of quotes is no argument, like sh
+                if !value.is_empty() || quote == Quote::Double {
+                    start.get_or_insert(w);
+                }
+                buf[w..w + value.len()].copy_from_slice(value);
+                w += value.len();
+            }
+            (Quote::None, b' ', _) | (Quote::None, b'\t', _) => {
+                if let Some(s) = start.take() {
+                    spans.push((s, w)).map_err(|_| TokenizeError::TooManyArgs)?;
+                }
+            }
+            (Quote::None, b'\\', _) => {
+                escape = true;
+            }
+            (Quote::None, b'\'', _) => {
+                start.get_or_insert(w);
+                quote = Quote::Single;
+            }
+            (Quote::None, b'"', _) => {
+                start.get_or_insert(w);
+                quote = Quote::Double;
+            }
+            (Quote::Single, b'\'', _) | (Quote::Double, b'"', _) => {
+                quote = Quote::None;
+            }
+            (Quote::Double, b'\\', _) if r < end && matches!(buf[r], b'"' | b'\\' | b'$') => {
+                escape = true;
+            }
+            _ => {
+                start.get_or_insert(w);
+                buf[w] = byte;
+                w += 1;
+            }
+        }
+    }
+
+    if escape {
+        return Err(TokenizeError::TrailingEscape);
+    }
+    if quote != Quote::None {
+        return Err(TokenizeError::UnterminatedQuote);
+    }
+    if let Some(s) = start {
+        spans.push((s, w)).map_err(|_| TokenizeError::TooManyArgs)?;
+    }
+
+    let buf: &[u8] = buf;
+    let mut argv = Vec::new();
+    for (s, e) in spans {
+        let arg = from_utf8(&buf[s..e]).map_err(|_| TokenizeError::BadInput)?;
+        // spans and argv share the same capacity
+        let _ = argv.push(arg);
+    }
+    Ok(argv)
+}
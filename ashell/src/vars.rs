@@ -0,0 +1,95 @@
+//! Shell variables, set with `set NAME value` and used as `$NAME`.
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::heapless::{String, Vec};
+use crate::tokenizer::Variables;
+use crate::ShellError;
+
+/// Most variables a shell keeps.
+pub const MAX_VARS: usize = 16;
+pub const NAME_LEN: usize = 16;
+pub const VALUE_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VarError {
+    /// Not letters, digits and `_`, or starts with a digit.
+    BadName,
+    TooLong,
+    Full,
+}
+
+impl fmt::Display for VarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarError::BadName => write!(f, "bad variable name"),
+            VarError::TooLong => write!(f, "value longer than {} bytes", VALUE_LEN),
+            VarError::Full => write!(f, "too many variables"),
+        }
+    }
+}
+
+impl From<VarError> for ShellError {
+    fn from(err: VarError) -> Self {
+        ShellError::VarError(err)
+    }
+}
+
+/// Fixed-size variable store, and the exit status of the last command
+/// for `$?`.
+#[derive(Default)]
+pub struct Vars {
+    vars: Vec<(String<NAME_LEN>, String<VALUE_LEN>), MAX_VARS>,
+    status: String<12>,
+}
+
+impl Vars {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), VarError> {
+        let mut chars = name.chars();
+        let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || name.len() > NAME_LEN {
+            return Err(VarError::BadName);
+        }
+        let value = String::from_str(value).map_err(|_| VarError::TooLong)?;
+        match self.vars.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => {
+                let name = String::from_str(name).map_err(|_| VarError::BadName)?;
+                self.vars.push((name, value)).map_err(|_| VarError::Full)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns false when `name` was not set.
+    pub fn unset(&mut self, name: &str) -> bool {
+        let len = self.vars.len();
+        self.vars.retain(|(n, _)| n != name);
+        self.vars.len() != len
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Exit status of the last command, see [`ShellError::status`].
+    pub fn status(&self) -> i32 {
+        self.status.parse().unwrap_or(0)
+    }
+
+    pub fn set_status(&mut self, status: i32) {
+        self.status.clear();
+        let _ = fmt::write(&mut self.status, format_args!("{}", status));
+    }
+}
+
+impl Variables for Vars {
+    fn get(&self, name: &str) -> Option<&str> {
+        if name == "?" {
+            return Some(if self.status.is_empty() { "0" } else { &self.status });
+        }
+        self.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+}
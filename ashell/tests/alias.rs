@@ -0,0 +1,64 @@
+mod common;
+
+use ashell::ShellError;
+use common::*;
+
+#[test]
+fn arguments_are_filled_in() {
+    run(|| {
+        let mut term = Terminal::new().with_aliases();
+        term.reply("pwm", "ok");
+        term.enter("alias p='pwm start $1 x'");
+        term.enter(r#"p "a b" c"#);
+        assert_eq!(term.last_command(), Some(vec!["pwm", "start", "a b", "x", "c"]));
+        term.enter(r#"alias say='pwm "$1" $2'"#);
+        term.enter(r#"say 'a"b' "it's""#);
+        assert_eq!(term.last_command(), Some(vec!["pwm", "a\"b", "it's"]));
+        term.enter("alias pin='pwm $PIN'");
+        term.enter("set PIN 3");
+        term.enter("pin");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "3"]));
+    });
+}
+
+#[test]
+fn alias_named_like_its_command() {
+    run(|| {
+        let mut term = Terminal::new().with_aliases();
+        term.reply("pwm", "ok");
+        term.enter("alias pwm='pwm -v'");
+        term.enter("pwm 1");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "-v", "1"]));
+    });
+}
+
+#[test]
+fn list_show_and_unalias() {
+    run(|| {
+        let mut term = Terminal::new().with_aliases();
+        term.reply("pwm", "ok");
+        term.enter("alias p=pwm");
+        term.enter("alias q='pwm 2 | head 1'");
+        term.enter("alias");
+        assert!(term.screen.text().ends_with("#>alias\np='pwm'\nq='pwm 2 | head 1'\n#>"));
+        term.enter("alias p");
+        assert!(term.screen.text().ends_with("#>alias p\np='pwm'\n#>"));
+        term.enter("unalias p");
+        term.enter("p");
+        assert!(matches!(term.result, Err(ShellError::CommandNotFound)));
+        term.enter("unalias p");
+        assert!(term.screen.text().ends_with("#>unalias p\nunalias: no such alias\n#>"));
+    });
+}
+
+#[test]
+fn names_are_completed() {
+    run(|| {
+        let mut term = Terminal::new().with_aliases();
+        term.enter("alias pwstat='pwmin status'");
+        term.type_str("pws\t");
+        assert_eq!(term.screen.current_line(), "#>pwstat");
+        term.type_str("1 pws\t");
+        assert_eq!(term.screen.bells, 1);
+    });
+}
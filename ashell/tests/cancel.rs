@@ -0,0 +1,53 @@
+mod common;
+
+use ashell::ShellError;
+use common::*;
+
+#[test]
+fn ctrl_c_cancels_a_running_command() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.queue_input(&[ctrl(b'c')]);
+        term.enter("block");
+        assert!(matches!(term.result, Err(ShellError::Interrupted)));
+        assert_eq!(term.screen.text(), "\n#>block\nstarted\n^C\n#>");
+    });
+}
+
+#[test]
+fn command_runs_to_completion() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("spin");
+        assert!(term.result.is_ok());
+        assert_eq!(term.screen.text(), "\n#>spin\ndone\n#>");
+    });
+}
+
+#[test]
+fn printed_text_and_type_ahead_while_running() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.queue_print("[pwmin] ch0 1000Hz\r\n");
+        term.queue_input(b"pwm");
+        term.queue_input(&[b' ', ctrl(b'c')]);
+        term.enter("block");
+        assert_eq!(term.screen.text(), "\n#>block\nstarted\n[pwmin] ch0 1000Hz\n^C\n#>pwm");
+        term.type_str("1");
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["pwm", "1"]));
+    });
+}
+
+#[test]
+fn ctrl_c_drops_the_line() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwm 1");
+        term.key(ctrl(b'c'));
+        assert_eq!(term.screen.text(), "\n#>pwm 1^C\n#>");
+        assert!(term.env.controls.is_empty());
+        term.key(b'\r');
+        assert!(term.env.commands.is_empty());
+    });
+}
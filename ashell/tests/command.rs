@@ -0,0 +1,344 @@
+use std::fmt::Write;
+
+use ashell::autocomplete::Autocomplete;
+use ashell::command::{Arg, ArgError, ArgKind, Args, Command, Handler, Registry, Value};
+use ashell::ShellError;
+
+const CH: Arg = Arg {
+    name: "ch",
+    kind: ArgKind::Int { min: 0, max: 3 },
+    required: true,
+    help: "channel",
+    complete: None,
+};
+
+static PWM_ARGS: &[Arg] = &[
+    CH,
+    Arg {
+        name: "duty",
+        kind: ArgKind::Int { min: -100, max: 100 },
+        required: false,
+        help: "percent",
+        complete: None,
+    },
+    Arg {
+        name: "-m",
+        kind: ArgKind::Choice(&["fast", "slow"]),
+        required: false,
+        help: "mode",
+        complete: None,
+    },
+    Arg {
+        name: "-q",
+        kind: ArgKind::Switch,
+        required: false,
+        help: "quiet",
+        complete: None,
+    },
+];
+
+static PINS_ARGS: &[Arg] = &[
+    Arg {
+        name: "-r",
+        kind: ArgKind::Switch,
+        required: false,
+        help: "reset",
+        complete: None,
+    },
+    Arg {
+        name: "-w",
+        kind: ArgKind::Int { min: 1, max: 9 },
+        required: false,
+        help: "width",
+        complete: None,
+    },
+    Arg {
+        name: "pins",
+        kind: ArgKind::Pins { max: 7 },
+        required: true,
+        help: "pin numbers",
+        complete: None,
+    },
+];
+
+fn print_args(args: &Args, out: &mut dyn Write) -> ashell::ShellResult {
+    write!(out, "{:?} {:?}", args.get("ch"), args.get("duty"))?;
+    Ok(())
+}
+
+static PWM: Command = Command {
+    name: "pwm",
+    summary: "set a pwm output",
+    usage: "pwm <ch> [duty] [-m fast|slow] [-q]",
+    args: PWM_ARGS,
+    subcommands: &[],
+    handler: Some(Handler::Sync(print_args)),
+};
+
+/// only pin 3 runs
+fn running(add: &mut dyn FnMut(&str)) {
+    add("all");
+    add("3");
+}
+
+static GROUP: Command = Command {
+    name: "pwmin",
+    summary: "measure pwm input",
+    usage: "pwmin <start> ..",
+    args: &[],
+    subcommands: &[
+        Command {
+            name: "start",
+            summary: "start capture",
+            usage: "pwmin start <pins>",
+            args: PINS_ARGS,
+            subcommands: &[],
+            handler: Some(Handler::Async(1)),
+        },
+        Command {
+            name: "stop",
+            summary: "stop capture",
+            usage: "pwmin stop <pins>",
+            args: &[Arg {
+                complete: Some(running),
+                ..PINS_ARGS[2]
+            }],
+            subcommands: &[],
+            handler: Some(Handler::Sync(print_args)),
+        },
+        Command {
+            name: "filter",
+            summary: "input filter",
+            usage: "pwmin filter <on|off>",
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "on",
+                    summary: "filter on",
+                    usage: "pwmin filter on [-n <samples>]",
+                    args: &[Arg {
+                        name: "-n",
+                        kind: ArgKind::Choice(&["4", "8"]),
+                        required: false,
+                        help: "samples",
+                        complete: None,
+                    }],
+                    subcommands: &[],
+                    handler: Some(Handler::Sync(print_args)),
+                },
+                Command {
+                    name: "off",
+                    summary: "filter off",
+                    usage: "pwmin filter off",
+                    args: &[],
+                    subcommands: &[],
+                    handler: Some(Handler::Sync(print_args)),
+                },
+            ],
+            handler: None,
+        },
+    ],
+    handler: None,
+};
+
+fn registry() -> Registry<4> {
+    let mut registry = Registry::new();
+    registry.register(&PWM).unwrap();
+    registry.register(&GROUP).unwrap();
+    registry
+}
+
+fn parse(spec: &'static [Arg], line: &str) -> Result<Vec<Value<'static>>, ArgError> {
+    let argv: Vec<&'static str> = Box::leak(line.to_string().into_boxed_str()).split_whitespace().collect();
+    Args::parse(spec, &argv).map(|args| spec.iter().map(|arg| args.get(arg.name)).collect())
+}
+
+fn complete(line: &str) -> Vec<String> {
+    let mut candidates = Vec::new();
+    registry().complete(line, &mut |candidate| candidates.push(candidate.to_string()));
+    candidates
+}
+
+/// What `dispatch` of `line` writes, and how it ends.
+fn dispatch(line: &str) -> (String, Result<(), ShellError>) {
+    let argv: Vec<&str> = line.split_whitespace().collect();
+    let mut out = String::new();
+    let ret = registry().dispatch(&argv, &mut out).map(|_| ());
+    (out, ret)
+}
+
+#[test]
+fn typed_values() {
+    use Value::*;
+    let cases: &[(&str, [Value; 4])] = &[
+        ("1", [Int(1), None, None, None]),
+        ("0x3 -0x10", [Int(3), Int(-16), None, None]),
+        ("3 -100 -m slow -q", [Int(3), Int(-100), Choice("slow"), Switch]),
+        // flags go anywhere
+        ("-q 2 -m fast 0X0a", [Int(2), Int(10), Choice("fast"), Switch]),
+    ];
+    for (line, values) in cases {
+        assert_eq!(parse(PWM_ARGS, line).unwrap(), values, "{:?}", line);
+    }
+}
+
+#[test]
+fn bad_values() {
+    let range = ArgError::OutOfRange { name: "ch", min: 0, max: 3 };
+    let cases: &[(&str, ArgError)] = &[
+        ("", ArgError::Missing("ch")),
+        ("-q", ArgError::Missing("ch")),
+        ("4", range),
+        ("-1", range),
+        ("1 101", ArgError::OutOfRange { name: "duty", min: -100, max: 100 }),
+        ("x", ArgError::NotANumber("ch")),
+        ("0x", ArgError::NotANumber("ch")),
+        ("1x", ArgError::NotANumber("ch")),
+        // one sign, and before 0x
+        ("+1", ArgError::NotANumber("ch")),
+        ("0x-1", ArgError::NotANumber("ch")),
+        ("0x+1", ArgError::NotANumber("ch")),
+        ("1 --5", ArgError::UnknownFlag),
+        ("1 -+5", ArgError::UnknownFlag),
+        ("1 2 3", ArgError::TooManyArgs),
+        ("1 -m", ArgError::NoValue("-m")),
+        ("1 -m medium", ArgError::BadChoice("-m")),
+        ("1 -x", ArgError::UnknownFlag),
+    ];
+    for (line, err) in cases {
+        assert_eq!(parse(PWM_ARGS, line).err(), Some(*err), "{:?}", line);
+    }
+}
+
+#[test]
+fn pin_lists() {
+    use Value::*;
+    let cases: &[(&str, [Value; 3])] = &[
+        ("0", [None, None, Pins(0b1)]),
+        ("1,3 5", [None, None, Pins(0b10_1010)]),
+        ("all", [None, None, Pins(0xff)]),
+        ("7,,0x2", [None, None, Pins(0b1000_0100)]),
+        ("-r -w 3 1 2", [Switch, Int(3), Pins(0b110)]),
+        // a pin list ends at the next flag
+        ("1 2 -w 3", [None, Int(3), Pins(0b110)]),
+        ("4,5 -r", [Switch, None, Pins(0b11_0000)]),
+    ];
+    for (line, values) in cases {
+        assert_eq!(parse(PINS_ARGS, line).unwrap(), values, "{:?}", line);
+    }
+    assert_eq!(
+        parse(PINS_ARGS, "1 8").err(),
+        Some(ArgError::OutOfRange { name: "pins", min: 0, max: 7 })
+    );
+    assert_eq!(parse(PINS_ARGS, "1 two").err(), Some(ArgError::NotANumber("pins")));
+    assert_eq!(parse(PINS_ARGS, "1 -w").err(), Some(ArgError::NoValue("-w")));
+    assert_eq!(parse(PINS_ARGS, "1 -x").err(), Some(ArgError::UnknownFlag));
+    assert_eq!(parse(PINS_ARGS, "1 -r 2").err(), Some(ArgError::TooManyArgs));
+}
+
+#[test]
+fn dispatch_and_errors() {
+    let (out, ret) = dispatch("pwm 2 -0x5");
+    assert_eq!(out, "Int(2) Int(-5)");
+    assert!(ret.is_ok());
+    let (out, ret) = dispatch("pwm 9");
+    assert_eq!(out, "pwm: <ch> out of range 0..3\r\nusage: pwm <ch> [duty] [-m fast|slow] [-q]\r\n");
+    assert!(matches!(ret, Err(ShellError::ArgError(ArgError::OutOfRange { .. }))));
+    let (out, ret) = dispatch("pwm 1 -m medium");
+    assert!(out.starts_with("pwm: invalid value for <-m>\r\n"));
+    assert!(matches!(ret, Err(ShellError::ArgError(ArgError::BadChoice("-m")))));
+    let (out, ret) = dispatch("pwmin run");
+    assert_eq!(out, "pwmin: missing or unknown subcommand\r\nusage: pwmin <start> ..\r\n");
+    assert!(matches!(ret, Err(ShellError::ArgError(ArgError::NoSubcommand))));
+    assert!(matches!(dispatch("nosuch").1, Err(ShellError::CommandNotFound)));
+    let (out, ret) = dispatch("pwmin filter on -n 4");
+    assert_eq!(out, "None None");
+    assert!(ret.is_ok());
+
+    // async commands come back to be awaited
+    let argv = ["pwmin", "start", "1"];
+    let task = registry().dispatch(&argv, &mut String::new()).unwrap().expect("a task");
+    assert_eq!((task.id, task.args.pins("pins")), (1, 0b10));
+}
+
+#[test]
+fn help() {
+    assert_eq!(
+        dispatch("help").0,
+        "  help      list commands, or usage of one\r\n  \
+         pwm       set a pwm output\r\n  \
+         pwmin     measure pwm input\r\n"
+    );
+    assert_eq!(
+        dispatch("help pwm").0,
+        "pwm - set a pwm output\r\n\
+         usage: pwm <ch> [duty] [-m fast|slow] [-q]\r\n  \
+         ch        0..3  channel\r\n  \
+         duty      -100..100  percent\r\n  \
+         -m        fast|slow  mode\r\n  \
+         -q        quiet\r\n"
+    );
+    assert_eq!(
+        dispatch("help pwmin").0,
+        "pwmin - measure pwm input\r\n\
+         usage: pwmin <start> ..\r\n  \
+         start     start capture\r\n  \
+         stop      stop capture\r\n  \
+         filter    input filter\r\n"
+    );
+    assert_eq!(
+        dispatch("help pwmin start").0,
+        "start - start capture\r\n\
+         usage: pwmin start <pins>\r\n  \
+         -r        reset\r\n  \
+         -w        1..9  width\r\n  \
+         pins      0..7|all  pin numbers\r\n"
+    );
+    assert!(matches!(dispatch("help nosuch").1, Err(ShellError::CommandNotFound)));
+}
+
+#[test]
+fn completion_at_every_level() {
+    let pins = ["all", "0", "1", "2", "3", "4", "5", "6", "7"];
+    let cases: &[(&str, &[&str])] = &[
+        ("", &["help", "pwm", "pwmin"]),
+        ("pw", &["pwm", "pwmin"]),
+        ("pwmin ", &["start", "stop", "filter"]),
+        ("pwmin s", &["start", "stop"]),
+        ("pwmin filter ", &["on", "off"]),
+        ("pwmin filter o", &["on", "off"]),
+        ("pwmin filter on -", &["-n"]),
+        ("pwmin filter on -n ", &["4", "8"]),
+        ("pwmin filter off ", &[]),
+        ("pwmin start ", &pins),
+        ("pwmin start -", &["-r", "-w"]),
+        ("pwmin start 1 -w ", &[]),
+        ("pwmin start 1 -r ", &pins),
+        ("pwmin start 1 2 ", &pins),
+        // the hook of the argument, not its pins
+        ("pwmin stop ", &["all", "3"]),
+        ("pwmin stop a", &["all"]),
+        ("pwm 1 -m ", &["fast", "slow"]),
+        ("pwm 1 -m s", &["slow"]),
+        ("pwm 1 -", &["-m", "-q"]),
+        ("pwm 1 ", &[]),
+        ("nosuch ", &[]),
+        ("help ", &["pwm", "pwmin"]),
+        ("help pwmin ", &["start", "stop", "filter"]),
+        ("help pwmin filter ", &["on", "off"]),
+    ];
+    for (line, candidates) in cases {
+        assert_eq!(complete(line), *candidates, "{:?}", line);
+    }
+}
+
+#[test]
+fn suggestion_is_the_common_prefix() {
+    let suggest = |line: &str| Autocomplete::<16>::suggest(&registry(), line).map(|s| s.to_string());
+    assert_eq!(suggest("pw").as_deref(), Some("m"));
+    assert_eq!(suggest("pwmin s").as_deref(), Some("t"));
+    assert_eq!(suggest("pwmin fi").as_deref(), Some("lter"));
+    // nothing to fill, or nothing at all
+    assert_eq!(suggest("pwmin st"), None);
+    assert_eq!(suggest("x"), None);
+}
@@ -0,0 +1,539 @@
+//! Host test harness: an in-memory terminal around [`AShell`].
+//!
+//! Bytes typed into a [`Terminal`] go through `AShell::feed_argv`, whatever
+//! the shell writes to its pipe is drained and run through a small VT100
+//! interpreter, so tests can assert what the user sees on the [`Screen`].
+//! The shell writes into a [`Pipe`], like the firmware does.
+//! With [`Terminal::with_jobs`] a job runner is polled next to the shell,
+//! and what the jobs print shows up above the prompt. [`RamFlash`] stands
+//! in for the flash of the stores that keep things across resets.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use ashell::autocomplete::{Autocomplete, StaticAutocomplete};
+use ashell::command::MAX_ARGS;
+use ashell::history::LRUHistory;
+use ashell::jobs::Jobs;
+use ashell::output::{self, CommandOutput};
+use ashell::script::Scripts;
+use ashell::{AShell, ArgvEnvironment, Event, ShellError, ShellResult, Source};
+use embassy_futures::select::{select, Either};
+use embassy_futures::{block_on, yield_now};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+pub const CMD_LEN: usize = 64;
+pub const HISTORY_CAP: usize = 16;
+pub const LOG_LEN: usize = 4096;
+pub const COLS: usize = 80;
+pub const ROWS: usize = 24;
+pub const JOBS_CAP: usize = 2;
+pub const SCRIPTS_CAP: usize = 4;
+
+pub const UP: &[u8] = b"\x1b[A";
+pub const DOWN: &[u8] = b"\x1b[B";
+pub const RIGHT: &[u8] = b"\x1b[C";
+pub const LEFT: &[u8] = b"\x1b[D";
+pub const HOME: &[u8] = b"\x1b[H";
+pub const END: &[u8] = b"\x1b[F";
+pub const DELETE: &[u8] = b"\x1b[3~";
+pub const WORD_LEFT: &[u8] = b"\x1bb";
+pub const WORD_RIGHT: &[u8] = b"\x1bf";
+
+pub const fn ctrl(key: u8) -> u8 {
+    key & 0x1f
+}
+
+/// Run `test` on a thread named `main`.
+///
+/// The shell pipe uses `ThreadModeRawMutex`, which with the `std` feature of
+/// embassy-sync only works on the main thread, while libtest runs every test
+/// on a thread of its own.
+pub fn run(test: impl FnOnce() + Send + 'static) {
+    let thread = std::thread::Builder::new()
+        .name("main".into())
+        .spawn(test)
+        .unwrap();
+    if let Err(panic) = thread.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+pub type PipeOutput = &'static Pipe<ThreadModeRawMutex, LOG_LEN>;
+pub type TestShell<A> = AShell<A, LRUHistory<CMD_LEN, HISTORY_CAP>, PipeOutput, CMD_LEN>;
+pub type TestJobs = Jobs<ThreadModeRawMutex, JOBS_CAP, CMD_LEN>;
+pub type TestScripts = Scripts<ThreadModeRawMutex, SCRIPTS_CAP, 128>;
+
+/// Events read by the shell while a command runs. Pending forever once
+/// empty, so a command that never returns must be given a Ctrl-C.
+#[derive(Default)]
+pub struct Script(pub VecDeque<(bool, Vec<u8>)>);
+
+impl Source for Script {
+    async fn next(&mut self, buf: &mut [u8]) -> Event {
+        match self.0.pop_front() {
+            Some((input, bytes)) => {
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                if input {
+                    Event::Input(bytes.len())
+                } else {
+                    Event::Print(bytes.len())
+                }
+            }
+            None => core::future::pending().await,
+        }
+    }
+}
+
+/// Records every command line and answers with canned output.
+///
+/// Three commands are async: `block` prints `started` and never returns,
+/// `spin` yields a few times, then prints `done`, and `stream` prints
+/// `[PwmIn]:pin:n:1:500:500` lines for pins 0 and 1 until a write fails.
+/// `exit <n>` fails with status `n`.
+pub struct MockEnv {
+    /// argv of every command, in order
+    pub commands: Vec<Vec<String>>,
+    /// control codes passed through by the shell
+    pub controls: Vec<u8>,
+    /// text written for a command name
+    pub replies: HashMap<String, String>,
+}
+
+impl MockEnv {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+            controls: Vec::new(),
+            replies: HashMap::new(),
+        }
+    }
+}
+
+impl ArgvEnvironment for MockEnv {
+    async fn command(&mut self, argv: &[&str], out: &mut dyn CommandOutput) -> ShellResult {
+        self.commands.push(argv.iter().map(|arg| arg.to_string()).collect());
+        match argv[0] {
+            "block" => {
+                out.write_str("started\r\n")?;
+                core::future::pending::<()>().await;
+            }
+            "spin" => {
+                for _ in 0..3 {
+                    yield_now().await;
+                }
+                return Ok(out.write_str("done")?);
+            }
+            "exit" => {
+                return match argv.get(1).and_then(|n| n.parse().ok()) {
+                    Some(0) | None => Ok(()),
+                    Some(status) => Err(ShellError::ExecuteError(status)),
+                };
+            }
+            "stream" => {
+                for n in 0.. {
+                    output::write_all(out, &format!("[PwmIn]:{}:{}:1:500:500\r\n", n % 2, n)).await?;
+                    yield_now().await;
+                }
+            }
+            _ => {}
+        }
+        match self.replies.get(argv[0]) {
+            Some(reply) => Ok(out.write_str(reply)?),
+            None => Err(ShellError::CommandNotFound),
+        }
+    }
+
+    async fn control(&mut self, code: u8) -> ShellResult {
+        self.controls.push(code);
+        Ok(())
+    }
+}
+
+/// Output of the job runner, printed above the prompt between keys.
+#[derive(Clone, Default)]
+struct JobOutput(Rc<RefCell<String>>);
+
+impl Write for JobOutput {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.borrow_mut().push_str(s);
+        Ok(())
+    }
+}
+
+impl CommandOutput for JobOutput {
+    fn poll_room(&mut self, _cx: &mut Context<'_>, _len: usize) -> Poll<()> {
+        Poll::Ready(())
+    }
+}
+
+pub struct Terminal<A: Autocomplete<CMD_LEN> = StaticAutocomplete<4>> {
+    shell: TestShell<A>,
+    pipe: &'static Pipe<ThreadModeRawMutex, LOG_LEN>,
+    runner: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    job_output: JobOutput,
+    pub env: MockEnv,
+    pub script: Script,
+    pub screen: Screen,
+    /// every byte the shell wrote, for tests that read frames
+    pub raw: Vec<u8>,
+    /// result of the last byte fed to the shell
+    pub result: ShellResult,
+}
+
+impl Terminal {
+    /// A terminal completing a few fixed command names.
+    pub fn new() -> Self {
+        Self::with_autocomplete(StaticAutocomplete(["help", "history", "pwmin", "pwm"]))
+    }
+}
+
+impl<A: Autocomplete<CMD_LEN>> Terminal<A> {
+    pub fn with_autocomplete(autocomplete: A) -> Self {
+        let pipe: &'static Pipe<ThreadModeRawMutex, LOG_LEN> = Box::leak(Box::new(Pipe::new()));
+        let shell = block_on(AShell::new(autocomplete, LRUHistory::default(), pipe));
+        let mut term = Self {
+            shell,
+            pipe,
+            runner: None,
+            job_output: JobOutput::default(),
+            env: MockEnv::new(),
+            script: Script::default(),
+            screen: Screen::new(),
+            raw: Vec::new(),
+            result: Ok(()),
+        };
+        term.drain();
+        term
+    }
+
+    /// Give the shell a job table, served by one runner with a
+    /// [`MockEnv`] of its own.
+    pub fn with_jobs(mut self) -> Self {
+        let jobs: &'static TestJobs = Box::leak(Box::new(Jobs::new()));
+        self.shell.set_jobs(jobs);
+        let mut out = self.job_output.clone();
+        self.runner = Some(Box::pin(async move {
+            jobs.run::<MAX_ARGS>(&mut MockEnv::new(), &mut out).await;
+        }));
+        self
+    }
+
+    /// Give the shell an empty script store.
+    pub fn with_scripts(mut self) -> Self {
+        let scripts: &'static TestScripts = Box::leak(Box::new(Scripts::new()));
+        self.shell.set_scripts(scripts);
+        self
+    }
+
+    /// Give the shell an empty alias store.
+    pub fn with_aliases(mut self) -> Self {
+        let aliases: &'static TestScripts = Box::leak(Box::new(Scripts::new()));
+        self.shell.set_aliases(aliases);
+        self
+    }
+
+    pub fn shell(&mut self) -> &mut TestShell<A> {
+        &mut self.shell
+    }
+
+    /// Let `name` succeed and print `reply`.
+    pub fn reply(&mut self, name: &str, reply: &str) {
+        self.env.replies.insert(name.into(), reply.into());
+    }
+
+    /// Feed `bytes` one at a time, updating the screen after each one.
+    pub fn keys(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            let feed = self.shell.feed_from::<MAX_ARGS>(&mut self.env, &mut self.script, *byte);
+            self.result = match &mut self.runner {
+                Some(runner) => match block_on(select(feed, runner.as_mut())) {
+                    Either::First(ret) => ret,
+                    Either::Second(()) => unreachable!("the job runner returned"),
+                },
+                None => block_on(feed),
+            };
+            self.drain();
+            self.print_job_output();
+        }
+    }
+
+    pub fn key(&mut self, byte: u8) {
+        self.keys(&[byte]);
+    }
+
+    pub fn type_str(&mut self, text: &str) {
+        self.keys(text.as_bytes());
+    }
+
+    /// Type `line` and press Enter.
+    pub fn enter(&mut self, line: &str) {
+        self.type_str(line);
+        self.key(b'\r');
+    }
+
+    /// Input the shell reads while the next command runs.
+    pub fn queue_input(&mut self, bytes: &[u8]) {
+        self.script.0.push_back((true, bytes.into()));
+    }
+
+    /// Text to print that arrives while the next command runs.
+    pub fn queue_print(&mut self, text: &str) {
+        self.script.0.push_back((false, text.into()));
+    }
+
+    /// Log `text` through `AShell::print_above`.
+    pub fn log(&mut self, text: &str) {
+        self.result = block_on(self.shell.print_above(text.as_bytes()));
+        self.drain();
+    }
+
+    /// Let the job runner make some progress while no key is pressed.
+    pub fn poll_jobs(&mut self) {
+        if let Some(runner) = &mut self.runner {
+            block_on(select(runner.as_mut(), async {
+                for _ in 0..8 {
+                    yield_now().await;
+                }
+            }));
+        }
+        self.print_job_output();
+    }
+
+    fn print_job_output(&mut self) {
+        let text = self.job_output.0.take();
+        if !text.is_empty() {
+            self.log(&text);
+        }
+    }
+
+    /// argv of the last command the environment saw.
+    pub fn last_command(&self) -> Option<Vec<&str>> {
+        self.env
+            .commands
+            .last()
+            .map(|argv| argv.iter().map(String::as_str).collect())
+    }
+
+    fn drain(&mut self) {
+        let mut buf = [0; LOG_LEN];
+        while let Ok(n) = self.pipe.try_read(&mut buf) {
+            self.screen.feed(&buf[..n]);
+            self.raw.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+pub const SECTOR: usize = 256;
+pub const SIZE: usize = 4 * SECTOR;
+
+/// NOR flash in RAM: writes can only clear bits, erase sets a sector to 0xff.
+pub struct RamFlash(pub Vec<u8>);
+
+#[derive(Debug)]
+pub struct RamFlashError;
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = RamFlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        assert_eq!(from as usize % SECTOR, 0);
+        assert_eq!(to as usize % SECTOR, 0);
+        self.0[from as usize..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+        for (cell, byte) in self.0[offset as usize..].iter_mut().zip(bytes) {
+            assert_eq!(*cell, 0xff, "write to flash that is not erased");
+            *cell = *byte;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// A `ROWS` x `COLS` VT100 screen. Only what the shell emits is understood:
+/// CR, LF, BS, BEL, cursor movement, `K`, `J`, `H` and save/restore cursor.
+pub struct Screen {
+    cells: Vec<Vec<char>>,
+    row: usize,
+    col: usize,
+    saved: (usize, usize),
+    state: State,
+    params: Vec<usize>,
+    /// number of BEL received
+    pub bells: usize,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            cells: vec![vec![' '; COLS]; ROWS],
+            row: 0,
+            col: 0,
+            saved: (0, 0),
+            state: State::Ground,
+            params: Vec::new(),
+            bells: 0,
+        }
+    }
+
+    /// Row and column of the cursor.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    /// Text of `row`, without trailing blanks.
+    pub fn line(&self, row: usize) -> String {
+        self.cells[row].iter().collect::<String>().trim_end().to_string()
+    }
+
+    /// The line the cursor is on.
+    pub fn current_line(&self) -> String {
+        self.line(self.row)
+    }
+
+    /// All lines down to the last non-empty one, joined with `\n`.
+    pub fn text(&self) -> String {
+        let lines: Vec<String> = (0..ROWS).map(|row| self.line(row)).collect();
+        let used = lines.iter().rposition(|line| !line.is_empty()).map_or(0, |i| i + 1);
+        lines[..used].join("\n")
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.feed_byte(*byte);
+        }
+    }
+
+    fn feed_byte(&mut self, byte: u8) {
+        match self.state {
+            State::Ground => match byte {
+                0x1b => self.state = State::Escape,
+                b'\r' => self.col = 0,
+                b'\n' => self.line_feed(),
+                0x07 => self.bells += 1,
+                0x08 => self.col = self.col.saturating_sub(1),
+                0x20..=0x7e => self.print(byte as char),
+                _ => {}
+            },
+            State::Escape => match byte {
+                b'[' => {
+                    self.params.clear();
+                    self.state = State::Csi;
+                }
+                _ => self.state = State::Ground,
+            },
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    let last = self.params.last_mut().unwrap();
+                    *last = *last * 10 + (byte - b'0') as usize;
+                }
+                b';' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    self.params.push(0);
+                }
+                _ => {
+                    self.csi(byte);
+                    self.state = State::Ground;
+                }
+            },
+        }
+    }
+
+    fn param(&self, idx: usize, default: usize) -> usize {
+        match self.params.get(idx) {
+            Some(0) | None => default,
+            Some(value) => *value,
+        }
+    }
+
+    fn csi(&mut self, code: u8) {
+        let n = self.param(0, 1);
+        match code {
+            b'A' => self.row = self.row.saturating_sub(n),
+            b'B' => self.row = (self.row + n).min(ROWS - 1),
+            b'C' => self.col = (self.col + n).min(COLS - 1),
+            b'D' => self.col = self.col.saturating_sub(n),
+            b'H' => {
+                self.row = self.param(0, 1).min(ROWS) - 1;
+                self.col = self.param(1, 1).min(COLS) - 1;
+            }
+            b'K' => self.cells[self.row][self.col..].fill(' '),
+            b'J' => match self.params.first() {
+                Some(2) => self.cells.iter_mut().for_each(|row| row.fill(' ')),
+                _ => {
+                    self.cells[self.row][self.col..].fill(' ');
+                    self.cells[self.row + 1..].iter_mut().for_each(|row| row.fill(' '));
+                }
+            },
+            b's' => self.saved = (self.row, self.col),
+            b'u' => (self.row, self.col) = self.saved,
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.col == COLS {
+            self.col = 0;
+            self.line_feed();
+        }
+        self.cells[self.row][self.col] = ch;
+        self.col += 1;
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 == ROWS {
+            self.cells.remove(0);
+            self.cells.push(vec![' '; COLS]);
+        } else {
+            self.row += 1;
+        }
+    }
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn unique_match_is_completed() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("his");
+        term.key(b'\t');
+        assert_eq!(term.screen.current_line(), "#>history");
+        assert_eq!(term.screen.cursor(), (1, 10));
+        assert_eq!(term.screen.bells, 0);
+    });
+}
+
+#[test]
+fn common_prefix_then_list() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pw");
+        term.key(b'\t');
+        assert_eq!(term.screen.current_line(), "#>pwm");
+        term.key(b'\t');
+        assert_eq!(term.screen.text(), "\n#>pwm\npwmin  pwm\n#>pwm");
+        assert_eq!(term.screen.cursor(), (3, 5));
+    });
+}
+
+#[test]
+fn ambiguous_prefix_rings_first() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwm");
+        term.key(b'\t');
+        assert_eq!(term.screen.bells, 1);
+        term.key(b'\t');
+        assert_eq!(term.screen.line(2), "pwmin  pwm");
+    });
+}
+
+#[test]
+fn no_match_rings() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("x");
+        term.key(b'\t');
+        assert_eq!(term.screen.bells, 1);
+        assert_eq!(term.screen.current_line(), "#>x");
+    });
+}
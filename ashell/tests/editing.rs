@@ -0,0 +1,159 @@
+mod common;
+
+use ashell::ShellError;
+use common::*;
+
+#[test]
+fn typing_is_echoed() {
+    run(|| {
+        let mut term = Terminal::new();
+        assert_eq!(term.screen.current_line(), "#>");
+        term.type_str("hello");
+        assert_eq!(term.screen.current_line(), "#>hello");
+        assert_eq!(term.screen.cursor(), (1, 7));
+    });
+}
+
+#[test]
+fn enter_runs_tokenized_line() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("pwm  set 'a b' 3");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "set", "a b", "3"]));
+        assert_eq!(term.screen.text(), "\n#>pwm  set 'a b' 3\nok\n#>");
+        assert_eq!(term.screen.cursor(), (3, 2));
+    });
+}
+
+#[test]
+fn empty_line_only_prints_prompt() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("   ");
+        assert!(term.env.commands.is_empty());
+        assert_eq!(term.screen.text(), "\n#>\n#>");
+    });
+}
+
+#[test]
+fn unknown_command_is_reported() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("nope");
+        assert!(matches!(term.result, Err(ShellError::CommandNotFound)));
+        assert_eq!(term.screen.text(), "\n#>nope\nnope: command not found\n#>");
+    });
+}
+
+#[test]
+fn insert_and_backspace_in_the_middle() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwm1");
+        term.keys(LEFT);
+        term.type_str(" ");
+        assert_eq!(term.screen.current_line(), "#>pwm 1");
+        assert_eq!(term.screen.cursor(), (1, 6));
+
+        term.keys(LEFT);
+        term.key(0x7f);
+        assert_eq!(term.screen.current_line(), "#>pw 1");
+        assert_eq!(term.screen.cursor(), (1, 4));
+
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["pw", "1"]));
+    });
+}
+
+#[test]
+fn cursor_moves() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwmin start 0");
+        term.keys(HOME);
+        assert_eq!(term.screen.cursor(), (1, 2));
+        term.keys(WORD_RIGHT);
+        assert_eq!(term.screen.cursor(), (1, 7));
+        term.keys(END);
+        assert_eq!(term.screen.cursor(), (1, 15));
+        term.keys(WORD_LEFT);
+        assert_eq!(term.screen.cursor(), (1, 14));
+        term.key(ctrl(b'a'));
+        assert_eq!(term.screen.cursor(), (1, 2));
+        term.key(ctrl(b'f'));
+        term.key(ctrl(b'e'));
+        term.key(ctrl(b'b'));
+        assert_eq!(term.screen.cursor(), (1, 14));
+        assert_eq!(term.screen.bells, 0);
+    });
+}
+
+#[test]
+fn bell_at_line_edges() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.keys(LEFT);
+        term.key(0x7f);
+        term.type_str("x");
+        term.keys(RIGHT);
+        assert_eq!(term.screen.bells, 3);
+        assert_eq!(term.screen.current_line(), "#>x");
+    });
+}
+
+#[test]
+fn kill_keys() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwmin start 0,1");
+        term.key(ctrl(b'w'));
+        assert_eq!(term.screen.current_line(), "#>pwmin start");
+        term.keys(WORD_LEFT);
+        term.key(ctrl(b'k'));
+        assert_eq!(term.screen.current_line(), "#>pwmin");
+        term.type_str(" stop");
+        term.keys(WORD_LEFT);
+        term.key(ctrl(b'u'));
+        assert_eq!(term.screen.current_line(), "#>stop");
+        assert_eq!(term.screen.cursor(), (1, 2));
+        term.keys(DELETE);
+        term.key(ctrl(b'd'));
+        assert_eq!(term.screen.current_line(), "#>op");
+    });
+}
+
+#[test]
+fn line_is_limited_to_buffer() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str(&"x".repeat(CMD_LEN + 2));
+        assert_eq!(term.screen.bells, 2);
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["x".repeat(CMD_LEN).as_str()]));
+    });
+}
+
+#[test]
+fn ctrl_l_clears_the_screen() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("pwm");
+        term.type_str("abc");
+        term.keys(LEFT);
+        term.key(ctrl(b'l'));
+        assert_eq!(term.screen.text(), "\n#>abc");
+        assert_eq!(term.screen.cursor(), (1, 4));
+    });
+}
+
+#[test]
+fn other_control_codes_go_to_the_environment() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.key(ctrl(b't'));
+        term.key(ctrl(b'd'));
+        assert_eq!(term.env.controls, vec![ctrl(b't'), ctrl(b'd')]);
+    });
+}
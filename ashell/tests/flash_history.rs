@@ -0,0 +1,152 @@
+use ashell::flash_history::FlashHistory;
+use ashell::history::History;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+const SECTOR: usize = 256;
+const SIZE: usize = 4 * SECTOR;
+
+/// NOR flash in RAM: writes can only clear bits, erase sets a sector to 0xff.
+struct RamFlash(Vec<u8>);
+
+#[derive(Debug)]
+struct RamFlashError;
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = RamFlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        assert_eq!(from as usize % SECTOR, 0);
+        assert_eq!(to as usize % SECTOR, 0);
+        self.0[from as usize..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+        for (cell, byte) in self.0[offset as usize..].iter_mut().zip(bytes) {
+            assert_eq!(*cell, 0xff, "write to flash that is not erased");
+            *cell = *byte;
+        }
+        Ok(())
+    }
+}
+
+type Hist = FlashHistory<RamFlash, 64, 8>;
+
+/// All entries, newest first.
+fn lines(history: &mut Hist) -> Vec<String> {
+    std::iter::from_fn(|| history.go_back()).map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn survives_reload() {
+    let mut history = Hist::new(RamFlash(vec![0; SIZE]), 0, SIZE as u32);
+    history.push("pwm 1").unwrap();
+    history.push("pwm 2").unwrap();
+    history.push("pwm 1").unwrap();
+    let mut history = Hist::new(history.into_inner(), 0, SIZE as u32);
+    assert_eq!(lines(&mut history), ["pwm 1", "pwm 2"]);
+}
+
+#[test]
+fn ring_wraps_and_keeps_newest() {
+    let mut history = Hist::new(RamFlash(vec![0xff; SIZE]), 0, SIZE as u32);
+    for i in 0..200 {
+        history.push(&format!("command number {}", i)).unwrap();
+    }
+    let mut history = Hist::new(history.into_inner(), 0, SIZE as u32);
+    let expected: Vec<String> = (192..200).rev().map(|i| format!("command number {}", i)).collect();
+    assert_eq!(lines(&mut history), expected);
+}
+
+#[test]
+fn corrupt_record_is_skipped() {
+    let mut history = Hist::new(RamFlash(vec![0xff; SIZE]), 0, SIZE as u32);
+    history.push("first").unwrap();
+    history.push("second").unwrap();
+    let mut flash = history.into_inner();
+    // flip a bit in the first record's command
+    flash.0[8] ^= 0x01;
+    let mut history = Hist::new(flash, 0, SIZE as u32);
+    history.push("third").unwrap();
+    let mut history = Hist::new(history.into_inner(), 0, SIZE as u32);
+    assert_eq!(lines(&mut history), ["third"]);
+}
+
+#[test]
+fn reset_erases() {
+    let mut history = Hist::new(RamFlash(vec![0xff; SIZE]), 0, SIZE as u32);
+    history.push("first").unwrap();
+    history.reset();
+    let mut history = Hist::new(history.into_inner(), 0, SIZE as u32);
+    assert!(lines(&mut history).is_empty());
+}
+
+/// CRC-16/CCITT-FALSE, as the records use
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Add `offset` to the sequence number of every record in `flash`.
+fn shift_seqs(flash: &mut RamFlash, offset: u32) {
+    for sector in flash.0.chunks_mut(SECTOR) {
+        let mut pos = 0;
+        while pos < SECTOR && sector[pos] == 0xa5 {
+            let len = sector[pos + 1] as usize;
+            let seq = u32::from_le_bytes(sector[pos + 4..pos + 8].try_into().unwrap());
+            sector[pos + 4..pos + 8].copy_from_slice(&seq.wrapping_add(offset).to_le_bytes());
+            let crc = crc16(&sector[pos + 4..pos + 8 + len]);
+            sector[pos + 2..pos + 4].copy_from_slice(&crc.to_le_bytes());
+            pos += (8 + len).div_ceil(4) * 4;
+        }
+    }
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let mut history = Hist::new(RamFlash(vec![0xff; SIZE]), 0, SIZE as u32);
+    for i in 0..200 {
+        history.push(&format!("command number {}", i)).unwrap();
+    }
+    // command 180 gets the last number before 0
+    let mut flash = history.into_inner();
+    shift_seqs(&mut flash, u32::MAX - 180);
+    let mut history = Hist::new(flash, 0, SIZE as u32);
+    history.push("after the wrap").unwrap();
+    let mut history = Hist::new(history.into_inner(), 0, SIZE as u32);
+    let mut expected: Vec<String> = (193..200).rev().map(|i| format!("command number {}", i)).collect();
+    expected.insert(0, "after the wrap".into());
+    assert_eq!(lines(&mut history), expected);
+}
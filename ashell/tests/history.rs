@@ -0,0 +1,148 @@
+mod common;
+
+use ashell::history::{History, LRUHistory, NoHistory};
+use common::*;
+
+#[test]
+fn arrows_walk_history() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwm 1");
+        term.enter("pwm 2");
+        term.keys(UP);
+        assert_eq!(term.screen.current_line(), "#>pwm 2");
+        term.keys(UP);
+        assert_eq!(term.screen.current_line(), "#>pwm 1");
+        term.keys(UP);
+        assert_eq!(term.screen.bells, 1);
+        term.keys(DOWN);
+        assert_eq!(term.screen.current_line(), "#>pwm 2");
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["pwm", "2"]));
+    });
+}
+
+#[test]
+fn reverse_search() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwmin start 0");
+        term.enter("pwmin stop 0");
+        term.enter("help");
+        term.key(ctrl(b'r'));
+        term.type_str("st");
+        assert_eq!(term.screen.current_line(), "(reverse-i-search)`st': pwmin stop 0");
+        term.key(ctrl(b'r'));
+        assert_eq!(term.screen.current_line(), "(reverse-i-search)`st': pwmin start 0");
+        term.key(ctrl(b'r'));
+        assert_eq!(term.screen.current_line(), "(failed reverse-i-search)`st': pwmin start 0");
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["pwmin", "start", "0"]));
+    });
+}
+
+#[test]
+fn search_is_left_for_editing() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwmin start 0");
+        term.key(ctrl(b'r'));
+        term.type_str("start");
+        term.keys(END);
+        term.type_str(",1");
+        assert_eq!(term.screen.current_line(), "#>pwmin start 0,1");
+    });
+}
+
+#[test]
+fn search_walks_both_ways() {
+    let mut history = LRUHistory::<CMD_LEN, HISTORY_CAP>::default();
+    for line in ["pwmin start 0", "pwm 1", "pwmin stop 0"] {
+        history.push(line).unwrap();
+    }
+    let search = |pattern: &str, from: usize, backward: bool| {
+        history.search(pattern, from, backward).map(|(idx, line)| (idx, line.to_string()))
+    };
+    // entry 0 is the newest, backward goes to older ones
+    assert_eq!(search("pwmin", 0, true), Some((0, "pwmin stop 0".into())));
+    assert_eq!(search("pwmin", 1, true), Some((2, "pwmin start 0".into())));
+    assert_eq!(search("pwmin", 1, false), Some((0, "pwmin stop 0".into())));
+    assert_eq!(search("pwm 1", 5, false), Some((1, "pwm 1".into())));
+    assert_eq!(search("pwmin", 3, true), None);
+    assert_eq!(search("nope", 0, true), None);
+    assert_eq!(History::<CMD_LEN>::search(&NoHistory, "pwm", 0, true), None);
+}
+
+#[test]
+fn bang_expansion() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwmin start 0");
+        term.enter("pwm 50");
+        term.enter("!!");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "50"]));
+        assert!(term.screen.text().contains("#>!!\npwm 50\n"));
+
+        term.enter("!pwmin");
+        assert_eq!(term.last_command(), Some(vec!["pwmin", "start", "0"]));
+        term.enter("^start^stop");
+        assert_eq!(term.last_command(), Some(vec!["pwmin", "stop", "0"]));
+        term.enter("!3 9");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "50", "9"]));
+        term.enter("!-2");
+        assert_eq!(term.last_command(), Some(vec!["pwmin", "stop", "0"]));
+        term.enter("echo '!!'");
+        assert_eq!(term.last_command(), Some(vec!["echo", "!!"]));
+    });
+}
+
+#[test]
+fn missing_event_runs_nothing() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("!nope");
+        assert!(term.env.commands.is_empty());
+        assert_eq!(term.screen.text(), "\n#>!nope\nevent not found\n#>");
+
+        term.enter("!-9223372036854775808");
+        assert!(term.env.commands.is_empty());
+        assert!(term.screen.text().ends_with("\nevent not found\n#>"));
+    });
+}
+
+#[test]
+fn history_builtin_lists_entries() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwm 1");
+        term.enter("pwm 2");
+        term.enter("history");
+        assert!(term.env.commands.iter().all(|argv| argv[0] != "history"));
+        assert!(term.screen.text().ends_with("    1  pwm 1\n    2  pwm 2\n    3  history\n#>"));
+
+        term.enter("history -c");
+        term.keys(UP);
+        assert_eq!(term.screen.current_line(), "#>");
+        assert_eq!(term.screen.bells, 1);
+    });
+}
+
+#[test]
+fn entries_keep_their_numbers() {
+    let mut history = LRUHistory::<CMD_LEN, 2>::default();
+    let entries = |history: &LRUHistory<CMD_LEN, 2>| {
+        (0..history.len())
+            .filter_map(|idx| history.nth(idx))
+            .map(|(number, line)| (number, line.to_string()))
+            .collect::<Vec<_>>()
+    };
+    history.push("pwm 1").unwrap();
+    history.push("pwm 2").unwrap();
+    assert_eq!(entries(&history), [(2, "pwm 2".into()), (1, "pwm 1".into())]);
+    // a repeat moves to the front with a new number
+    history.push("pwm 1").unwrap();
+    assert_eq!(entries(&history), [(3, "pwm 1".into()), (2, "pwm 2".into())]);
+    // the oldest goes, numbers keep growing
+    history.push("pwm 3").unwrap();
+    assert_eq!(entries(&history), [(4, "pwm 3".into()), (3, "pwm 1".into())]);
+}
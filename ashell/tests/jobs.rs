@@ -0,0 +1,85 @@
+mod common;
+
+use ashell::jobs::JobError;
+use ashell::ShellError;
+use common::*;
+
+#[test]
+fn job_runs_while_the_prompt_is_free() {
+    run(|| {
+        let mut term = Terminal::new().with_jobs();
+        term.enter("block &");
+        assert!(term.env.commands.is_empty());
+        term.poll_jobs();
+        assert_eq!(term.screen.text(), "\n#>block &\n[1]\nstarted\n#>");
+        term.enter("jobs");
+        assert!(term.screen.text().ends_with("#>jobs\n[1] Running  block\n#>"));
+    });
+}
+
+#[test]
+fn kill_cancels_a_job() {
+    run(|| {
+        let mut term = Terminal::new().with_jobs();
+        term.enter("block &");
+        term.poll_jobs();
+        term.enter("kill %1");
+        term.poll_jobs();
+        assert!(term.screen.text().ends_with("#>kill %1\n\n[1] Killed  block\n#>"));
+        term.enter("kill 1");
+        assert!(matches!(term.result, Err(ShellError::JobError(JobError::NoSuchJob))));
+        assert!(term.screen.text().ends_with("#>kill 1\nkill: no such job\n#>"));
+    });
+}
+
+#[test]
+fn fg_waits_for_the_job() {
+    run(|| {
+        let mut term = Terminal::new().with_jobs();
+        term.enter("spin &");
+        term.enter("fg");
+        assert!(term.result.is_ok());
+        assert!(term.screen.text().ends_with("#>fg\nspin\n\ndone\n[1] Done  spin\n#>"));
+        term.enter("jobs");
+        assert!(term.screen.text().ends_with("#>jobs\n\n#>"));
+    });
+}
+
+#[test]
+fn ctrl_c_kills_the_foreground_job() {
+    run(|| {
+        let mut term = Terminal::new().with_jobs();
+        term.enter("block &");
+        term.poll_jobs();
+        term.queue_input(&[ctrl(b'c')]);
+        term.enter("fg 1");
+        assert!(matches!(term.result, Err(ShellError::Interrupted)));
+        term.poll_jobs();
+        assert!(term.screen.text().ends_with("#>fg 1\nblock\n^C\n[1] Killed  block\n#>"));
+    });
+}
+
+#[test]
+fn table_full() {
+    run(|| {
+        let mut term = Terminal::new().with_jobs();
+        for _ in 0..JOBS_CAP {
+            term.enter("block &");
+        }
+        term.enter("block &");
+        assert!(matches!(term.result, Err(ShellError::JobError(JobError::TableFull))));
+        assert!(term.screen.text().ends_with("#>block &\ntoo many jobs\n#>"));
+    });
+}
+
+#[test]
+fn no_job_control_without_a_table() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("block &");
+        assert!(matches!(term.result, Err(ShellError::JobError(JobError::NoJobControl))));
+        assert!(term.env.commands.is_empty());
+        term.enter("jobs");
+        assert_eq!(term.last_command(), Some(vec!["jobs"]));
+    });
+}
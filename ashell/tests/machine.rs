@@ -0,0 +1,153 @@
+mod common;
+
+use ashell::machine::{Framer, Header, Kind};
+use ashell::output::Output;
+use ashell::ShellError;
+use common::*;
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+
+/// The frames in `raw`, `(header, payload)`, skipping what comes before
+/// the first one.
+fn frames(raw: &[u8]) -> Vec<(Header, String)> {
+    let mut frames = Vec::new();
+    let mut rest = match raw.iter().position(|&byte| byte == b'@') {
+        Some(start) => &raw[start..],
+        None => return frames,
+    };
+    while !rest.is_empty() {
+        let end = rest.windows(2).position(|pair| pair == b"\r\n").expect("header line end");
+        let header = Header::parse(&rest[..end]).expect("frame header");
+        let payload = &rest[end + 2..end + 2 + header.len];
+        assert_eq!(&rest[end + 2 + header.len..end + 4 + header.len], b"\r\n");
+        frames.push((header, String::from_utf8(payload.to_vec()).unwrap()));
+        rest = &rest[end + 4 + header.len..];
+    }
+    frames
+}
+
+fn machine_term() -> Terminal {
+    let mut term = Terminal::new();
+    term.reply("pwm", "ok");
+    term.enter("@machine");
+    assert!(term.raw.ends_with(b"#>@machine\r\n@R 0 0 0\r\n\r\n"));
+    term.raw.clear();
+    term
+}
+
+fn response(seq: u32, status: i32, payload: &str) -> (Header, String) {
+    let header = Header {
+        kind: Kind::Response,
+        seq,
+        status,
+        len: payload.len(),
+    };
+    (header, payload.into())
+}
+
+#[test]
+fn requests_are_answered_in_frames() {
+    run(|| {
+        let mut term = machine_term();
+        term.keys(b"7 pwm 1 | count\r\n");
+        term.keys(b"pwm 2; exit 3\n");
+        term.keys(b"nosuch\r");
+        assert_eq!(
+            frames(&term.raw),
+            [
+                response(7, 0, "1"),
+                response(8, 3, "ok\r\nexit: failed with status 3"),
+                response(9, 127, "nosuch: command not found"),
+            ]
+        );
+        assert_eq!(term.env.commands.len(), 4);
+        // nothing was echoed or kept
+        term.raw.clear();
+        term.keys(b"12 @interactive\r");
+        let frame = term.raw.strip_suffix(b"\r\n#>").expect("a prompt");
+        assert_eq!(frames(frame), [response(12, 0, "")]);
+        term.key(ctrl(b'p'));
+        assert!(term.screen.text().ends_with("\n#>@machine"));
+    });
+}
+
+#[test]
+fn logs_come_in_frames_of_their_own() {
+    run(|| {
+        let mut term = machine_term();
+        term.log("boot done\r\nhalf");
+        term.log(" a line\r\n");
+        term.queue_print("while busy\r\n");
+        term.queue_input(&[ctrl(b'c')]);
+        term.keys(b"1 block\r");
+        assert!(matches!(term.result, Err(ShellError::Interrupted)));
+        let log = |text: &str| {
+            let header = Header {
+                kind: Kind::Log,
+                seq: 0,
+                status: 0,
+                len: text.len(),
+            };
+            (header, text.to_string())
+        };
+        assert_eq!(
+            frames(&term.raw),
+            [
+                log("boot done"),
+                log("half a line"),
+                log("while busy\r\n"),
+                response(1, 130, "started\r\n^C"),
+            ]
+        );
+    });
+}
+
+#[test]
+fn overlong_and_cancelled_lines() {
+    run(|| {
+        let mut term = machine_term();
+        term.keys(&[b'x'; CMD_LEN + 1]);
+        term.key(b'\r');
+        term.keys(b"pwm 1");
+        term.key(ctrl(b'c'));
+        term.keys(b"pwm 2\r");
+        assert_eq!(
+            frames(&term.raw),
+            [response(1, 2, "line too long after expansion"), response(2, 0, "ok")]
+        );
+        assert_eq!(term.last_command(), Some(vec!["pwm", "2"]));
+    });
+}
+
+#[test]
+fn frames_that_do_not_fit_are_dropped_whole() {
+    run(|| {
+        let pipe: &'static Pipe<ThreadModeRawMutex, 32> = Box::leak(Box::new(Pipe::new()));
+        let mut framer = Framer::new(pipe);
+        assert_eq!(pipe.try_write(&[b'x'; 20]), Ok(20));
+        framer.begin(1);
+        // a 23 byte frame, 12 free
+        assert_eq!(framer.print(b"a log line"), 0);
+        let mut buf = [0u8; 32];
+        assert_eq!(pipe.try_read(&mut buf).unwrap(), 20);
+        // across the end of the ring
+        assert_eq!(framer.print(b"a log line"), 10);
+        let mut raw = Vec::new();
+        let mut drain = |raw: &mut Vec<u8>| {
+            while let Ok(n) = pipe.try_read(&mut buf) {
+                raw.extend_from_slice(&buf[..n]);
+            }
+        };
+        drain(&mut raw);
+        block_on(framer.end(0));
+        drain(&mut raw);
+        let log = Header {
+            kind: Kind::Log,
+            seq: 0,
+            status: 0,
+            len: 10,
+        };
+        assert_eq!(frames(&raw), [(log, "a log line".to_string()), response(1, 0, "")]);
+    });
+}
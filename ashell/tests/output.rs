@@ -0,0 +1,71 @@
+mod common;
+
+use std::fmt::Write;
+
+use ashell::output::{self, Output, OutputWriter};
+use common::run;
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+
+#[test]
+fn pipe_writes_go_past_the_end_of_the_ring() {
+    run(|| {
+        let pipe: &'static Pipe<ThreadModeRawMutex, 16> = Box::leak(Box::new(Pipe::new()));
+        let mut out = pipe;
+        assert_eq!(out.room(), 16);
+        assert_eq!(Output::try_write(&mut out, b"0123456789"), 10);
+        let mut buf = [0u8; 16];
+        assert_eq!(pipe.try_read(&mut buf[..8]).unwrap(), 8);
+        // 6 bytes to the end of the ring, 8 after its start
+        assert_eq!(out.room(), 14);
+        assert_eq!(Output::try_write(&mut out, b"abcdefghijklmn"), 14);
+        assert_eq!(out.room(), 0);
+        assert_eq!(Output::try_write(&mut out, b"x"), 0);
+        let mut read = Vec::new();
+        while let Ok(n) = pipe.try_read(&mut buf) {
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(read, b"89abcdefghijklmn");
+        // more than fits is cut
+        assert_eq!(Output::try_write(&mut out, &[b'y'; 20]), 16);
+    });
+}
+
+#[test]
+fn short_writes_are_errors() {
+    run(|| {
+        let pipe: &'static Pipe<ThreadModeRawMutex, 8> = Box::leak(Box::new(Pipe::new()));
+        let mut out = pipe;
+        let mut writer = OutputWriter(&mut out);
+        assert_eq!(write!(writer, "{}", 12345), Ok(()));
+        assert!(write!(writer, "{}", 6789).is_err());
+        assert_eq!(pipe.len(), 8);
+    });
+}
+
+#[test]
+fn async_writes_wait_for_room() {
+    run(|| {
+        let pipe: &'static Pipe<ThreadModeRawMutex, 8> = Box::leak(Box::new(Pipe::new()));
+        let mut out = pipe;
+        let write = async {
+            let mut writer = OutputWriter(&mut out);
+            for _ in 0..3 {
+                output::write_all(&mut writer, "line\r\n").await.unwrap();
+            }
+        };
+        let read = async {
+            let mut read = Vec::new();
+            let mut buf = [0u8; 4];
+            while read.len() < 18 {
+                let n = pipe.read(&mut buf).await;
+                read.extend_from_slice(&buf[..n]);
+            }
+            read
+        };
+        let ((), read) = block_on(join(write, read));
+        assert_eq!(read, b"line\r\nline\r\nline\r\n");
+    });
+}
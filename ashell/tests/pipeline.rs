@@ -0,0 +1,71 @@
+mod common;
+
+use ashell::filter::FilterError;
+use ashell::ShellError;
+use common::*;
+
+const CAPTURES: &str = "[PwmIn]:0:1:1:500:500\r\n[PwmIn]:1:2:1:250:750\r\n[PwmIn]:0:3:1:500:500\r\n";
+
+#[test]
+fn grep_and_field() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwmin", CAPTURES);
+        term.enter("pwmin | grep :0: | field 3 5");
+        assert_eq!(term.last_command(), Some(vec!["pwmin"]));
+        assert!(term.screen.text().ends_with("field 3 5\n1:500\n3:500\n#>"));
+        term.enter("pwmin | grep -v :0:");
+        assert!(term.screen.text().ends_with("grep -v :0:\n[PwmIn]:1:2:1:250:750\n#>"));
+    });
+}
+
+#[test]
+fn tail_and_count_print_at_the_end() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwmin", CAPTURES);
+        term.enter("pwmin | tail 1");
+        assert!(term.screen.text().ends_with("tail 1\n[PwmIn]:0:3:1:500:500\n#>"));
+        term.enter("pwmin | count");
+        assert!(term.screen.text().ends_with("count\n3\n#>"));
+    });
+}
+
+#[test]
+fn head_stops_an_endless_command() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("stream | head 3 | field 3");
+        assert!(term.result.is_ok());
+        assert!(term.screen.text().ends_with("field 3\n0\n1\n2\n#>"));
+    });
+}
+
+#[test]
+fn ctrl_c_flushes_the_filters() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.queue_input(&[ctrl(b'c')]);
+        term.enter("stream | count");
+        assert!(matches!(term.result, Err(ShellError::Interrupted)));
+        let text = term.screen.text();
+        let count: usize = text.lines().nth_back(1).unwrap().parse().unwrap();
+        assert!(count > 0);
+        assert!(text.ends_with(&format!("count\n^C\n{}\n#>", count)));
+    });
+}
+
+#[test]
+fn bad_filters() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwmin | sort");
+        assert!(matches!(term.result, Err(ShellError::FilterError(FilterError::Unknown))));
+        term.enter("pwmin | head x");
+        assert!(matches!(term.result, Err(ShellError::FilterError(FilterError::BadArg))));
+        assert!(term.screen.text().ends_with("head x\npwmin: bad filter argument\n#>"));
+        term.enter("| count");
+        assert!(matches!(term.result, Err(ShellError::FilterError(FilterError::NoCommand))));
+        assert!(term.env.commands.is_empty());
+    });
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn log_line_goes_above_the_prompt() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("pwm 1");
+        term.keys(LEFT);
+        term.keys(LEFT);
+        term.log("[pwmin] ch0 1000Hz\r\n");
+        assert_eq!(term.screen.text(), "\n[pwmin] ch0 1000Hz\n#>pwm 1");
+        assert_eq!(term.screen.cursor(), (2, 5));
+
+        term.type_str("0");
+        assert_eq!(term.screen.current_line(), "#>pwm0 1");
+        term.key(b'\r');
+        assert_eq!(term.last_command(), Some(vec!["pwm0", "1"]));
+    });
+}
+
+#[test]
+fn partial_line_waits_for_its_end() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.type_str("x");
+        term.log("[pwmin] ch");
+        assert_eq!(term.screen.text(), "\n#>x");
+        term.log("1 2000Hz\r\n[pwmin] ch2");
+        assert_eq!(term.screen.text(), "\n[pwmin] ch1 2000Hz\n#>x");
+        term.log(" 10Hz\n");
+        assert_eq!(term.screen.text(), "\n[pwmin] ch1 2000Hz\n[pwmin] ch2 10Hz\n#>x");
+        assert_eq!(term.screen.cursor(), (3, 3));
+    });
+}
+
+#[test]
+fn long_line_is_split() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.log(&"a".repeat(130));
+        assert_eq!(term.screen.line(1), "a".repeat(80));
+        assert_eq!(term.screen.line(2), "a".repeat(48));
+        assert_eq!(term.screen.line(3), "#>");
+    });
+}
+
+#[test]
+fn search_is_redrawn() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("pwmin start 0");
+        term.key(ctrl(b'r'));
+        term.type_str("sta");
+        term.log("hello\r\n");
+        assert_eq!(term.screen.line(3), "hello");
+        assert_eq!(term.screen.current_line(), "(reverse-i-search)`sta': pwmin start 0");
+    });
+}
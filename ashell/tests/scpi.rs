@@ -0,0 +1,206 @@
+mod common;
+
+use std::fmt::Write;
+
+use ashell::autocomplete::StaticAutocomplete;
+use ashell::history::LRUHistory;
+use ashell::scpi::{header_matches, Instrument, Params, Scpi, ScpiCommand, ScpiError};
+use ashell::AShell;
+use common::*;
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+
+const FREQ: u16 = 1;
+const THRES: u16 = 2;
+const THRES_QUERY: u16 = 3;
+const STATE: u16 = 4;
+
+static COMMANDS: [ScpiCommand; 4] = [
+    ScpiCommand { pattern: "MEASure:PWM:FREQuency?", id: FREQ },
+    ScpiCommand { pattern: "CONFigure:PWMIN:THREShold", id: THRES },
+    ScpiCommand { pattern: "CONFigure:PWMIN:THREShold?", id: THRES_QUERY },
+    ScpiCommand { pattern: "CONFigure:PWMIN[:STATe]", id: STATE },
+];
+
+/// Channel `n` measures `1000 * (n + 1)` Hz.
+#[derive(Default)]
+struct Meter {
+    threshold: i32,
+    running: u32,
+    resets: usize,
+}
+
+impl Instrument for Meter {
+    fn identity(&self) -> &str {
+        "Seven,Meter,0,1.0"
+    }
+
+    fn reset(&mut self) {
+        *self = Meter { resets: self.resets + 1, ..Meter::default() };
+    }
+
+    fn commands(&self) -> &'static [ScpiCommand] {
+        &COMMANDS
+    }
+
+    async fn execute(&mut self, id: u16, params: &mut Params<'_>, out: &mut dyn Write) -> Result<(), ScpiError> {
+        match id {
+            FREQ => {
+                let channels = params.channels(4)?;
+                let freqs: Vec<String> = (0..5)
+                    .filter(|ch| channels & (1 << ch) != 0)
+                    .map(|ch| ((ch + 1) * 1000).to_string())
+                    .collect();
+                let _ = out.write_str(&freqs.join(","));
+            }
+            THRES => self.threshold = params.int(0, 1000)?,
+            THRES_QUERY => {
+                let _ = write!(out, "{}", self.threshold);
+            }
+            STATE => {
+                let on = params.boolean()?;
+                let channels = params.channels(4)?;
+                if on {
+                    self.running |= channels;
+                } else {
+                    self.running &= !channels;
+                }
+            }
+            _ => return Err(ScpiError::UndefinedHeader),
+        }
+        Ok(())
+    }
+}
+
+type TestScpi = Scpi<Meter, 4>;
+
+/// Run `line`, the answer.
+fn query(scpi: &mut TestScpi, line: &str) -> String {
+    let mut out = String::new();
+    block_on(scpi.execute(line, &mut out));
+    out
+}
+
+#[test]
+fn headers_match_short_and_long_forms() {
+    let pattern = "MEASure:PWM:FREQuency?";
+    assert!(header_matches(pattern, "MEAS:PWM:FREQ?"));
+    assert!(header_matches(pattern, "measure:pwm:frequency?"));
+    assert!(header_matches(pattern, "Meas:Pwm:Freq?"));
+    assert!(!header_matches(pattern, "MEAS:PWM:FREQ"));
+    assert!(!header_matches(pattern, "MEASU:PWM:FREQ?"));
+    assert!(!header_matches(pattern, "MEAS:PWM?"));
+    assert!(!header_matches(pattern, "MEAS:PWM:FREQ:EXTRA?"));
+
+    let optional = "SYSTem:ERRor[:NEXT]?";
+    assert!(header_matches(optional, "SYST:ERR?"));
+    assert!(header_matches(optional, "syst:err:next?"));
+    assert!(!header_matches(optional, "SYST:ERR:COUN?"));
+    assert!(header_matches("CONFigure:PWMIN[:STATe]", "CONF:PWMIN"));
+    assert!(header_matches("CONFigure:PWMIN[:STATe]", "CONF:PWMIN:STAT"));
+
+    assert!(header_matches("*IDN?", "*idn?"));
+    assert!(!header_matches("*IDN?", "*IDN"));
+    assert!(!header_matches("*RST", "RST"));
+}
+
+#[test]
+fn parameters() {
+    let mut params = Params::new(" 12 , MAX,on, (@0,2:4), \"a,b\" ");
+    assert_eq!(params.int(0, 100), Ok(12));
+    assert_eq!(params.int(0, 100), Ok(100));
+    assert_eq!(params.boolean(), Ok(true));
+    assert_eq!(params.channels(4), Ok(0b11101));
+    assert_eq!(params.next(), Some("\"a,b\""));
+    assert_eq!(params.done(), Ok(()));
+    assert_eq!(params.int(0, 1), Err(ScpiError::MissingParameter));
+
+    assert_eq!(Params::new("101").int(0, 100), Err(ScpiError::DataOutOfRange));
+    assert_eq!(Params::new("1x").int(0, 100), Err(ScpiError::NumericDataError));
+    assert_eq!(Params::new("abc").int(0, 100), Err(ScpiError::DataTypeError));
+    assert_eq!(Params::new("maybe").boolean(), Err(ScpiError::IllegalParameterValue));
+    assert_eq!(Params::new("(@5)").channels(4), Err(ScpiError::DataOutOfRange));
+    assert_eq!(Params::new("(@3:1)").channels(4), Err(ScpiError::DataOutOfRange));
+    assert_eq!(Params::new("3").channels(4), Err(ScpiError::DataTypeError));
+    assert_eq!(Params::new("1, 2").done(), Err(ScpiError::ParameterNotAllowed));
+}
+
+#[test]
+fn common_commands_and_queries() {
+    let mut scpi = TestScpi::new(Meter::default());
+    assert_eq!(query(&mut scpi, "*IDN?"), "Seven,Meter,0,1.0");
+    assert_eq!(query(&mut scpi, "*opt?;*TST?;SYST:VERS?"), "0;0;1999.0");
+    assert_eq!(query(&mut scpi, "MEAS:PWM:FREQ? (@0,2)"), "1000,3000");
+    // a relative header continues the node of the one before it
+    assert_eq!(query(&mut scpi, "CONF:PWMIN:THRES 20;THRES?"), "20");
+    assert_eq!(query(&mut scpi, "CONF:PWMIN:THRES 30;*OPC?;THRES?"), "1;30");
+    assert_eq!(query(&mut scpi, ":CONF:PWMIN ON,(@1:2);:CONF:PWMIN:STAT OFF,(@1)"), "");
+    assert_eq!(scpi.instrument_mut().running, 0b100);
+    assert_eq!(query(&mut scpi, "*RST;CONF:PWMIN:THRES?"), "0");
+    assert_eq!(scpi.instrument_mut().resets, 1);
+}
+
+#[test]
+fn errors_are_queued() {
+    let mut scpi = TestScpi::new(Meter::default());
+    assert_eq!(query(&mut scpi, "SYST:ERR?"), "0,\"No error\"");
+    // the rest of a line is skipped after an error
+    assert_eq!(query(&mut scpi, "MEAS:VOLT?;*IDN?"), "");
+    query(&mut scpi, "CONF:PWMIN:THRES 2000");
+    query(&mut scpi, "CONF:PWMIN:THRES");
+    query(&mut scpi, "*CLS 1");
+    assert_eq!(query(&mut scpi, "SYST:ERR:COUN?;*ESR?;*ESR?"), "4;48;0");
+    assert_eq!(query(&mut scpi, "SYST:ERR?"), "-113,\"Undefined header\"");
+    assert_eq!(query(&mut scpi, "SYST:ERR:NEXT?"), "-222,\"Data out of range\"");
+    assert_eq!(query(&mut scpi, "SYST:ERR?;ERR?"), "-109,\"Missing parameter\";-108,\"Parameter not allowed\"");
+
+    // a full queue ends with an overflow
+    for _ in 0..6 {
+        query(&mut scpi, "NOPE");
+    }
+    assert_eq!(query(&mut scpi, "SYST:ERR:COUN?"), "4");
+    for _ in 0..3 {
+        assert_eq!(scpi.next_error(), Some(ScpiError::UndefinedHeader));
+    }
+    assert_eq!(scpi.next_error(), Some(ScpiError::QueueOverflow));
+    query(&mut scpi, "NOPE;*CLS");
+    assert_eq!(query(&mut scpi, "*CLS;SYST:ERR?"), "0,\"No error\"");
+}
+
+#[test]
+fn a_port_is_fed_byte_by_byte() {
+    let mut scpi = TestScpi::new(Meter::default());
+    let mut out = String::new();
+    for byte in b"*IDN?\r\nCONF:PWMIN:THRES 5\n" {
+        block_on(scpi.feed(*byte, &mut out));
+    }
+    // only queries are answered, with a line each
+    assert_eq!(out, "Seven,Meter,0,1.0\n");
+    out.clear();
+    let long = format!("*IDN?;{}\n", "*OPC;".repeat(30));
+    for byte in long.bytes().chain(*b"SYST:ERR?\n") {
+        block_on(scpi.feed(byte, &mut out));
+    }
+    assert_eq!(out, "-363,\"Input buffer overrun\"\n");
+}
+
+#[test]
+fn scpi_in_a_shell() {
+    run(|| {
+        let pipe: &'static Pipe<ThreadModeRawMutex, LOG_LEN> = Box::leak(Box::new(Pipe::new()));
+        let autocomplete = StaticAutocomplete(["help", "history", "pwmin", "pwm"]);
+        let mut shell: AShell<_, LRUHistory<CMD_LEN, HISTORY_CAP>, _, CMD_LEN> =
+            block_on(AShell::new(autocomplete, LRUHistory::default(), pipe));
+        let mut scpi = TestScpi::new(Meter::default());
+        for byte in b"meas:pwm:freq? (@1);*ESR?\r" {
+            block_on(shell.feed(&mut scpi, *byte)).unwrap();
+        }
+        let mut screen = Screen::new();
+        let mut buf = [0; LOG_LEN];
+        while let Ok(n) = pipe.try_read(&mut buf) {
+            screen.feed(&buf[..n]);
+        }
+        assert!(screen.text().ends_with("#>meas:pwm:freq? (@1);*ESR?\n2000;0\n#>"));
+    });
+}
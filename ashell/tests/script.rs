@@ -0,0 +1,123 @@
+mod common;
+
+use ashell::flash_script::FlashScripts;
+use ashell::script::{ScriptError, ScriptStore};
+use ashell::ShellError;
+use common::*;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+#[test]
+fn statements_and_chains() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("pwm 1; exit 1 && pwm 2 || pwm 3");
+        let commands: Vec<&str> = term.env.commands.iter().map(|argv| argv[1].as_str()).collect();
+        assert_eq!(commands, ["1", "1", "3"]);
+        assert!(term.result.is_ok());
+        assert!(term
+            .screen
+            .text()
+            .ends_with("#>pwm 1; exit 1 && pwm 2 || pwm 3\nok\nexit: failed with status 1\nok\n#>"));
+    });
+}
+
+#[test]
+fn repeat_and_if() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("set N 3");
+        term.enter("repeat $N { pwm a; pwm b }");
+        assert_eq!(term.env.commands.len(), 6);
+        term.enter("exit 2; if $? == 0 { pwm 0 } else if $? == 2 { pwm 2 }");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "2"]));
+        term.enter("if exit 1 { pwm yes } else { pwm no }");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "no"]));
+        term.enter("if $N >= 3 { repeat 2 { if '}' != x { pwm nested } } }");
+        assert_eq!(term.env.commands.iter().filter(|argv| argv[1] == "nested").count(), 2);
+    });
+}
+
+#[test]
+fn syntax_errors() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("repeat 2 { pwm");
+        assert!(matches!(term.result, Err(ShellError::ScriptError(ScriptError::Unbalanced))));
+        assert!(term.screen.text().ends_with("#>repeat 2 { pwm\nunbalanced { }\n#>"));
+        term.enter("repeat x { pwm }");
+        assert!(term.screen.text().ends_with("\nrepeat: bad count\n#>"));
+        term.enter("if a < b { pwm }");
+        assert!(term.screen.text().ends_with("\nif: not a number\n#>"));
+        term.enter("pwm 1 &&");
+        assert!(term.screen.text().ends_with("\nok\nsyntax error\n#>"));
+        term.enter("pwm $?");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "2"]));
+    });
+}
+
+#[test]
+fn stored_scripts() {
+    run(|| {
+        let mut term = Terminal::new().with_scripts();
+        term.reply("pwm", "ok");
+        term.enter("script set blink 'pwm 1; pwm 0'");
+        term.enter("script add blink 'pwm $?'");
+        term.enter("script");
+        assert!(term.screen.text().ends_with("#>script\nblink\n#>"));
+        term.enter("script show blink");
+        assert!(term.screen.text().ends_with("#>script show blink\npwm 1; pwm 0\npwm $?\n#>"));
+        term.env.commands.clear();
+        term.enter("blink");
+        let commands: Vec<&str> = term.env.commands.iter().map(|argv| argv[1].as_str()).collect();
+        assert_eq!(commands, ["1", "0", "0"]);
+        term.enter("pwm 1 && blink");
+        assert!(term.screen.text().ends_with("\nok\nblink: scripts run on a line of their own\n#>"));
+        term.enter("script rm blink");
+        term.enter("blink");
+        assert!(matches!(term.result, Err(ShellError::CommandNotFound)));
+    });
+}
+
+#[test]
+fn ctrl_c_stops_a_script() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.queue_input(&[ctrl(b'c')]);
+        term.enter("pwm 0; repeat 100 { pwm 1 }");
+        assert!(matches!(term.result, Err(ShellError::Interrupted)));
+        assert_eq!(term.env.commands.len(), 1);
+        assert!(term.screen.text().ends_with("\nok^C\n#>"));
+    });
+}
+
+type Flash = FlashScripts<NoopRawMutex, RamFlash, 4, 64>;
+
+#[test]
+fn flash_scripts_survive_reload() {
+    let scripts = Flash::new(RamFlash(vec![0; SIZE]), 0);
+    scripts.save("a", "pwm 1").unwrap();
+    scripts.save("b", "repeat 2 { pwm 2 }").unwrap();
+    scripts.save("a", "pwm 3").unwrap();
+    scripts.remove("b").unwrap();
+    scripts.save("c", "pwm 4").unwrap();
+    assert_eq!(scripts.save("1x", "pwm"), Err(ScriptError::BadName));
+
+    let scripts = Flash::new(scripts.into_inner(), 0);
+    let mut names = Vec::new();
+    scripts.each(&mut |name, _| names.push(name.to_string()));
+    assert_eq!(names, ["a", "c"]);
+    let mut buf = [0; 64];
+    let len = scripts.load("a", &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"pwm 3");
+
+    // flip a bit in the text of the first slot
+    let mut flash = scripts.into_inner();
+    flash.0[8 + 16] ^= 0x01;
+    let scripts = Flash::new(flash, 0);
+    assert!(!scripts.contains("a"));
+    assert!(scripts.contains("c"));
+}
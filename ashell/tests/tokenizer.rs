@@ -0,0 +1,92 @@
+use ashell::tokenizer::{tokenize, tokenize_vars, TokenizeError, Variables};
+
+/// The arguments of `line`, at most 4.
+fn split(line: &str) -> Result<Vec<String>, TokenizeError> {
+    let mut buf = line.as_bytes().to_vec();
+    tokenize::<4>(&mut buf).map(|argv| argv.iter().map(|arg| arg.to_string()).collect())
+}
+
+#[test]
+fn quoting() {
+    let cases: &[(&str, &[&str])] = &[
+        ("", &[]),
+        (" \t ", &[]),
+        ("pwm  set\t1", &["pwm", "set", "1"]),
+        // empty arguments
+        ("a '' b", &["a", "", "b"]),
+        ("\"\"", &[""]),
+        // adjacent parts are one argument
+        ("a\"b c\"d", &["ab cd"]),
+        ("'a'\"b\"c", &["abc"]),
+        // single quotes are literal
+        (r"'a\b $x \'", &[r"a\b $x \"]),
+        (r"'\'", &[r"\"]),
+        // double quotes take \" \\ and \$ only
+        (r#""a\"b""#, &[r#"a"b"#]),
+        (r#""a\\b""#, &[r"a\b"]),
+        (r#""\$x""#, &["$x"]),
+        (r#""a\b\n""#, &[r"a\b\n"]),
+        // outside of quotes \ takes anything
+        (r"a\ b \' \\", &["a b", "'", r"\"]),
+        (r#"\""#, &["\""]),
+    ];
+    for (line, argv) in cases {
+        assert_eq!(split(line).unwrap(), *argv, "{:?}", line);
+    }
+}
+
+#[test]
+fn errors() {
+    let cases: &[(&str, TokenizeError)] = &[
+        ("a 'b", TokenizeError::UnterminatedQuote),
+        ("'", TokenizeError::UnterminatedQuote),
+        ("a \"b c", TokenizeError::UnterminatedQuote),
+        (r#""a\""#, TokenizeError::UnterminatedQuote),
+        (r"a b\", TokenizeError::TrailingEscape),
+        (r"\", TokenizeError::TrailingEscape),
+        // argv holds 4
+        ("1 2 3 4 5", TokenizeError::TooManyArgs),
+        ("1 2 3 4 ''", TokenizeError::TooManyArgs),
+    ];
+    for (line, err) in cases {
+        assert_eq!(split(line), Err(*err), "{:?}", line);
+    }
+    assert_eq!(split("1 2 3 4 ").unwrap().len(), 4);
+    let mut bad = *b"a \xff";
+    assert_eq!(tokenize::<4>(&mut bad), Err(TokenizeError::BadInput));
+}
+
+struct Vars;
+
+impl Variables for Vars {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "ch" => Some("1 2"),
+            "?" => Some("0"),
+            "long" => Some("0123456789"),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn variables() {
+    let cases: &[(&str, &[&str])] = &[
+        ("pwm $ch", &["pwm", "1 2"]),
+        ("${ch}x $?", &["1 2x", "0"]),
+        ("'$ch' \"$ch\"", &["$ch", "1 2"]),
+        // unset is nothing, or an empty argument in quotes
+        ("a $none b", &["a", "b"]),
+        ("a \"$none\"", &["a", ""]),
+        // not a name
+        ("$ $-", &["$", "$-"]),
+    ];
+    for (line, argv) in cases {
+        let mut buf = [0u8; 32];
+        buf[..line.len()].copy_from_slice(line.as_bytes());
+        assert_eq!(tokenize_vars::<4>(&mut buf, line.len(), &Vars).unwrap().as_slice(), *argv, "{:?}", line);
+    }
+    // values longer than their name need room after the line
+    let mut buf = *b"$long";
+    assert_eq!(tokenize_vars::<4>(&mut buf, 5, &Vars), Err(TokenizeError::TooLong));
+}
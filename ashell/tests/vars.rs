@@ -0,0 +1,70 @@
+mod common;
+
+use ashell::vars::VarError;
+use ashell::ShellError;
+use common::*;
+
+#[test]
+fn set_and_expand() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("set PIN 3");
+        term.enter(r#"pwm $PIN "${PIN}x" '$PIN' \$PIN"#);
+        assert_eq!(term.last_command(), Some(vec!["pwm", "3", "3x", "$PIN", "$PIN"]));
+    });
+}
+
+#[test]
+fn unset_variable_is_empty() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("set PIN 3");
+        term.enter("unset PIN");
+        term.enter(r#"pwm $PIN a "$PIN""#);
+        assert_eq!(term.last_command(), Some(vec!["pwm", "a", ""]));
+    });
+}
+
+#[test]
+fn env_lists_variables() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.enter("set A 1");
+        term.enter("set B 'x y' z");
+        term.enter("env");
+        assert!(term.screen.text().ends_with("#>env\nA=1\nB=x y z\n#>"));
+    });
+}
+
+#[test]
+fn exit_status() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("exit 3");
+        assert!(matches!(term.result, Err(ShellError::ExecuteError(3))));
+        assert!(term.screen.text().ends_with("#>exit 3\nexit: failed with status 3\n#>"));
+        term.enter("pwm $?");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "3"]));
+        term.enter("pwm $?");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "0"]));
+        term.enter("nope");
+        term.enter("pwm $?");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "127"]));
+    });
+}
+
+#[test]
+fn bad_variable_name() {
+    run(|| {
+        let mut term = Terminal::new();
+        term.reply("pwm", "ok");
+        term.enter("set 1X a");
+        assert!(matches!(term.result, Err(ShellError::VarError(VarError::BadName))));
+        assert!(term.screen.text().ends_with("#>set 1X a\nset: bad variable name\n#>"));
+        term.enter("pwm $?");
+        assert_eq!(term.last_command(), Some(vec!["pwm", "2"]));
+    });
+}
@@ -9,20 +9,28 @@ mod mylog;
 mod shell;
 mod usb_shell;
 mod pwmin_pio;
+mod rpc;
+#[cfg(scpi)]
+mod scpi;
 
 use embassy_executor::Spawner;
 use embassy_rp::interrupt;
+use embassy_rp::gpio::Pin;
 use embassy_rp::usb::Driver as USBDriver;
 use embassy_rp::peripherals::UART0;
 use embassy_rp::uart::{BufferedUart, BufferedUartRx, BufferedUartTx, Config};
 use embedded_io::asynch::{Read, Write};
-use embassy_rp::gpio::{AnyPin, Pin};
 use embassy_executor::_export::StaticCell;
 use embassy_rp::pio::PioPeripheral;
 use {defmt_rtt as _, panic_probe as _};
 use pwmin_pio::pwmin_init;
 use embassy_time::{Duration, Timer};
-use crate::shell::{SHELL_ENV, create_shell, SevenShell};
+use crate::shell::{SHELL_ENV, MAX_ARGC, JOB_RUNNERS, UART_JOBS, UART_SHELL_PIPE, UartSource, SharedFlash, SevenAliases, SevenScripts, create_aliases, create_history, create_scripts, create_shell, register_builtin_cmds, job_task, SevenShell};
+use core::cell::RefCell;
+use embassy_rp::flash::Flash;
+use embassy_sync::blocking_mutex::Mutex;
+use crate::mylog::LOG_PIPE;
+use embassy_futures::join::join;
 
 macro_rules! singleton {
     ($val:expr) => {{
@@ -59,26 +67,56 @@ async fn main(spawner: Spawner) {
     mylog::init_log();
     spawner.spawn(mylog::log_task(tx));
     log::info!("welcome to SevenTest");
-    pwmin_init(p.PIO0, p.PIO1, p.PIN_0.degrade(), p.PIN_1.degrade(), p.PIN_2.degrade(), p.PIN_3.degrade(), p.PIN_4.degrade()).await;
+    //every GPIO but the uart ones
+    let pwmin_pins = [
+        p.PIN_0.degrade(), p.PIN_1.degrade(), p.PIN_2.degrade(), p.PIN_3.degrade(),
+        p.PIN_4.degrade(), p.PIN_5.degrade(), p.PIN_6.degrade(), p.PIN_7.degrade(),
+        p.PIN_8.degrade(), p.PIN_9.degrade(), p.PIN_10.degrade(), p.PIN_11.degrade(),
+        p.PIN_12.degrade(), p.PIN_13.degrade(), p.PIN_14.degrade(), p.PIN_15.degrade(),
+        p.PIN_18.degrade(), p.PIN_19.degrade(), p.PIN_20.degrade(), p.PIN_21.degrade(),
+        p.PIN_22.degrade(), p.PIN_23.degrade(), p.PIN_24.degrade(), p.PIN_25.degrade(),
+        p.PIN_26.degrade(), p.PIN_27.degrade(), p.PIN_28.degrade(), p.PIN_29.degrade(),
+    ];
+    pwmin_init(p.PIO0, p.PIO1, pwmin_pins).await;
+
+    //the history, the scripts and the aliases share the flash
+    let flash = SharedFlash(singleton!(Mutex::new(RefCell::new(Flash::new(p.FLASH)))));
+    let history = create_history(flash);
+    let scripts: &'static SevenScripts = singleton!(create_scripts(flash));
+    let aliases: &'static SevenAliases = singleton!(create_aliases(flash));
+
+    register_builtin_cmds();
+
+    //each transport has its own shell and output
+    let mut shell: SevenShell = create_shell(history, &UART_SHELL_PIPE).await;
+    shell.set_jobs(&UART_JOBS);
+    shell.set_scripts(scripts);
+    shell.set_aliases(aliases);
+    for _ in 0..JOB_RUNNERS {
+        spawner.spawn(job_task(&UART_JOBS, &LOG_PIPE)).unwrap();
+    }
+    spawner.spawn(rpc::rpc_event_task(&rpc::UART_RPC)).unwrap();
+    let uart_fut = async {
+        let mut source = rpc::RpcSource::new(UartSource { rx }, &rpc::UART_RPC);
+        shell.run::<MAX_ARGC>(&mut &SHELL_ENV, &mut source).await
+    };
 
     //init usb shell
     #[cfg(usb_shell)]
     {
         let irq = interrupt::take!(USBCTRL_IRQ);
         let driver = USBDriver::new(p.USB, irq);
-        let usb_shell = usb_shell::UsbShell;
-        usb_shell.run(&mut usb_shell::LoggerState::new(), driver).await;
-    }
-    
-
-    let mut shell: SevenShell = create_shell().await;
-    let mut rx_buf:[u8;32] = [0;32];
-    loop {
-        let rx_len = rx.read(&mut rx_buf).await.unwrap();
-        for byte in &rx_buf[..rx_len] {
-            unsafe {shell.feed(&mut SHELL_ENV, *byte).await;}
+        for _ in 0..JOB_RUNNERS {
+            spawner.spawn(job_task(&shell::USB_JOBS, &mylog::USB_LOG_PIPE)).unwrap();
         }
+        #[cfg(not(scpi))]
+        spawner.spawn(rpc::rpc_event_task(&rpc::USB_RPC)).unwrap();
+        let usb_shell = usb_shell::UsbShell;
+        let mut usb_state = usb_shell::LoggerState::new();
+        join(usb_shell.run(&mut usb_state, driver, scripts, aliases), uart_fut).await;
     }
+    #[cfg(not(usb_shell))]
+    uart_fut.await;
 
     // join(tx_fut, rx_fut).await;
 }
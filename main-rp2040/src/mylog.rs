@@ -4,14 +4,19 @@ use embassy_rp::uart::BufferedUartTx;
 use embassy_sync::pipe::{Pipe};
 use embedded_io::asynch::{Read as AsyncRead, Write as AsyncWrite};
 // use embassy_rp::uart::{BufferedUart, BufferedUartRx, BufferedUartTx};
+use crate::shell::UART_SHELL_PIPE;
 
 type CS = embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 
 const LOG_BUFF_SIZE:usize = 1024;
 
+/// Log lines for the uart shell, printed above its prompt
 pub static LOG_PIPE: Pipe<CS, LOG_BUFF_SIZE> = Pipe::new();
+/// Log lines for the usb shell, dropped while nobody reads them
+pub static USB_LOG_PIPE: Pipe<CS, LOG_BUFF_SIZE> = Pipe::new();
 
-struct MyWriter<'d, const N: usize>(&'d Pipe<CS, N>);
+/// `core::fmt::Write` into a pipe, what does not fit is dropped
+pub(crate) struct MyWriter<'d, const N: usize>(pub &'d Pipe<CS, N>);
 
 impl<'d, const N: usize> core::fmt::Write for MyWriter<'d, N> {
     fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
@@ -35,6 +40,7 @@ impl log::Log for MyLogger {
     fn log(&self, record: &log::Record) {
        if self.enabled(record.metadata()) {
             let _ = write!(MyWriter(&LOG_PIPE), "{}\r\n", record.args());
+            let _ = write!(MyWriter(&USB_LOG_PIPE), "{}\r\n", record.args());
         } 
     }
 
@@ -53,11 +59,12 @@ pub fn init_log() {
 #[embassy_executor::task]
 pub async fn log_task(mut tx: BufferedUartTx<'static, UART0>)
 {
-    //read data from LOG_PIPE and write to uart    
+    //read data from the uart shell and write to uart, log lines get there
+    //through AShell::print_above
     let mut log_buf:[u8;32] = [0;32];
     // let reader = LOG_PIPE.reader();
     loop {
-        let len = LOG_PIPE.read(&mut log_buf).await;
+        let len = UART_SHELL_PIPE.read(&mut log_buf).await;
         tx.write_all(&log_buf[..len]).await.unwrap();
     }
 }
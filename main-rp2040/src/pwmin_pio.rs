@@ -1,7 +1,10 @@
 use {defmt_rtt as _, panic_probe as _};
-use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
+use core::fmt::Write;
 use ashell::ShellResult;
+use ashell::command::{Arg, ArgKind, Args, Command, Handler};
+use ashell::output::{self, CommandOutput};
 use embassy_rp::{gpio::{AnyPin, Pin}, Peripheral, Peripherals, peripherals::PIO1, peripherals::PIO0, PeripheralRef, pio::PioCommon};
 use embassy_rp::pio::{PioStateMachine, PioStateMachineInstance, Pio0, Pio1, Sm0, Sm1, Sm2, Sm3, PioPeripheral,
                       ShiftDirection,FifoJoin};
@@ -11,17 +14,27 @@ use {defmt_rtt as _, panic_probe as _};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::pubsub::WaitResult;
 use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::pubsub::Subscriber;
+use seventest_proto::{report, Measurement, PwmCapture, PwmStats, Report, ReportPolicy, Threshold, DEFAULT_REPORT_POLICY, DEFAULT_THRESHOLD, PWMIN_TIMEBASE};
+use seventest_proto::stats::DEFAULT_BIN_WIDTH;
 use heapless::Vec;
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant};
-use crate::shell::register_shell_cmd;
+use crate::shell::{register_shell_cmd, JOB_RUNNERS};
 
 pub type PwmInCommandSignal = Signal<ThreadModeRawMutex, PwmInCommand>;
 
-const SM_CLK:u32 = 125_000_000; //125MHz
-static PWM_PUBSUB_CHANNEL:PubSubChannel::<ThreadModeRawMutex, PwmInfo, 200, 1, 5> = PubSubChannel::new();
+//subscribers: `pwmin watch` on the 2 shells and in each of their job runners,
+//the rpc events of the 2 links
+const PWM_SUBS:usize = 2 + 2 * JOB_RUNNERS + 2;
+//publishers: the capture task of each state machine
+static PWM_PUBSUB_CHANNEL:PubSubChannel::<ThreadModeRawMutex, PwmInfo, 200, PWM_SUBS, PWMIN_SLOTS> = PubSubChannel::new();
+pub type PwmSubscriber = Subscriber<'static, ThreadModeRawMutex, PwmInfo, 200, PWM_SUBS, PWMIN_SLOTS>;
 static mut PWMIN: PwmInShellEnv = PwmInShellEnv::new();
+//the threshold last set for all channels, channels may have their own since
+static PWMIN_THRESHOLD: AtomicU32 = AtomicU32::new(DEFAULT_THRESHOLD);
 #[derive(Clone, Copy, defmt::Format)]
 pub struct PwmInfo {
     pin:u32,
@@ -32,12 +45,35 @@ pub struct PwmInfo {
     time:u64,
 }
 
+impl PwmInfo {
+    /// the capture in Hz, ns and percent
+    pub fn measure(&self) -> Measurement {
+        Measurement::new(&PWMIN_TIMEBASE, self.high_period, self.low_period)
+    }
+
+    /// the channel of this capture
+    pub fn channel(&self) -> usize {
+        self.pin as usize
+    }
+
+    pub fn capture(&self) -> PwmCapture {
+        PwmCapture {
+            channel: self.pin as u8,
+            clk: self.clk,
+            high_ticks: self.high_period,
+            low_ticks: self.low_period,
+            count: self.count,
+            time: self.time,
+        }
+    }
+}
+
 impl Default for PwmInfo
 {
     fn default() -> Self {
         Self {
             pin: 0,
-            clk: SM_CLK,
+            clk: PWMIN_TIMEBASE.tick_hz(),
             high_period: 0,
             low_period: 0,
             count: 0,
@@ -48,25 +84,28 @@ impl Default for PwmInfo
 
 #[derive(Clone, Copy, Debug)]
 enum PwmInCommand {
-    Start(usize),
+    /// capture this GPIO
+    Start(u8),
     Stop,
 }
+/// A state machine and what it captures.
 struct PwmIn {
-    // pin: AnyPin,
-    run: bool,
-    // sm_no: usize,
-    // pio_no: usize,
-    // cmd: Signal<ThreadModeRawMutex, PwmInCommand>,
+    /// the GPIO captured, `None` while the state machine is free
+    gpio: Option<u8>,
     cmd: PwmInCommandSignal,
+    /// the last capture sent
+    last: Option<PwmInfo>,
+    /// every period since the start or `pwmin stats -r`
+    stats: PwmStats,
 }
 
 impl PwmIn {
-    // pub const fn new(pin: Option<usize>, sm:usize, pio:usize) -> Self {
     pub const fn new() -> Self {
         Self {
-            // pin,
-            run:false,
-            cmd:Signal::new()
+            gpio: None,
+            cmd: Signal::new(),
+            last: None,
+            stats: PwmStats::new(DEFAULT_BIN_WIDTH),
         }
     }
 }
@@ -75,204 +114,505 @@ pub enum PwmInError {
     PinError,
     PinInUse,
     PinAllocFail,
+    /// the GPIO was not handed to pwmin at init, something else has it
+    PinNotFree,
 }
 pub struct PwmInShellEnv {
-    pwmin_state: [PwmIn;5],
+    /// PIO0 state machines, then PIO1 ones
+    pwmin_state: [PwmIn; PWMIN_SLOTS],
+    /// GPIOs handed to `pwmin_init`, the only ones a channel may take
+    owned: u32,
+    /// by GPIO, kept while the channel is stopped
+    policies: [ReportPolicy; PWMIN_CHANNELS as usize],
 }
 
 impl PwmInShellEnv {
     pub const fn new() -> Self {
+        const FREE: PwmIn = PwmIn::new();
         Self {
-            pwmin_state: [
-                            PwmIn::new(), 
-                            PwmIn::new(), 
-                            PwmIn::new(), 
-                            PwmIn::new(), 
-                            PwmIn::new(), 
-                            // PwmIn::new(None,1, 1), 
-                            // PwmIn::new(None,1, 2), 
-                            // PwmIn::new(None,1, 3), 
-                        ],
+            pwmin_state: [FREE; PWMIN_SLOTS],
+            owned: 0,
+            policies: [DEFAULT_REPORT_POLICY; PWMIN_CHANNELS as usize],
+        }
+    }
+
+    fn policy(&self, gpio:usize) -> ReportPolicy {
+        self.policies.get(gpio).copied().unwrap_or_default()
+    }
+
+    /// the state machine capturing `gpio`
+    fn slot_of(&self, gpio:usize) -> Option<usize> {
+        self.pwmin_state.iter().position(|state| state.gpio.map(usize::from) == Some(gpio))
+    }
+
+    fn pin_in_use(&self, gpio:usize) -> bool {
+        self.slot_of(gpio).is_some()
+    }
+
+    fn last_capture(&self, gpio:usize) -> Option<PwmInfo> {
+        self.slot_of(gpio).and_then(|slot| self.pwmin_state[slot].last)
+    }
+
+    fn set_last_capture(&mut self, slot:usize, msg:PwmInfo) {
+        if let Some(state) = self.pwmin_state.get_mut(slot) {
+            state.last = Some(msg);
         }
     }
 
-    fn pin_in_use(&self, idx:usize) -> bool {
-        if idx < self.pwmin_state.len() {
-            return self.pwmin_state[idx].run;
+    fn add_sample(&mut self, slot:usize, high:u32, low:u32) {
+        if let Some(state) = self.pwmin_state.get_mut(slot) {
+            state.stats.add(high, low);
         }
-        false
+    }
+
+    fn stats_mut(&mut self, gpio:usize) -> Option<&mut PwmStats> {
+        self.slot_of(gpio).map(|slot| &mut self.pwmin_state[slot].stats)
     }
 
     pub fn get_stop_signal(&self, no:usize) -> Option<&PwmInCommandSignal> {
-        if no < self.pwmin_state.len() {
-            Some(&self.pwmin_state[no].cmd)
-        } else {
-            None
+        self.pwmin_state.get(no).map(|state| &state.cmd)
+    }
+
+    pub fn stop(&mut self, gpio:usize) {
+        if let Some(slot) = self.slot_of(gpio) {
+            let state = &mut self.pwmin_state[slot];
+            state.gpio = None;
+            state.cmd.signal(PwmInCommand::Stop);
+        }
+    }
+
+    /// Capture `gpio` on the first free state machine.
+    pub fn start(&mut self, gpio:usize) -> Result<(), PwmInError> {
+        if gpio >= PWMIN_CHANNELS as usize {
+            return Err(PwmInError::PinError);
+        }
+        if self.owned & (1 << gpio) == 0 {
+            return Err(PwmInError::PinNotFree);
+        }
+        if self.pin_in_use(gpio) {
+            return Err(PwmInError::PinInUse);
+        }
+        let state = self.pwmin_state.iter_mut().find(|state| state.gpio.is_none()).ok_or(PwmInError::PinAllocFail)?;
+        state.gpio = Some(gpio as u8);
+        state.last = None;
+        state.stats.reset();
+        state.cmd.signal(PwmInCommand::Start(gpio as u8));
+        Ok(())
+    }
+}
+
+/// channels are GPIO numbers, 0 to 29
+pub const PWMIN_CHANNELS:u8 = 30;
+/// state machines of PIO0 and PIO1, channels that can run at once
+pub const PWMIN_SLOTS:usize = 8;
+/// what the `all` of a channel argument gives
+const ALL_CHANNELS:u32 = u32::MAX >> (32 - PWMIN_CHANNELS);
+pub const PWMIN_WATCH_TASK:u16 = 0x100;
+
+const PWMIN_CH_ARG: Arg = Arg {
+    name: "ch",
+    kind: ArgKind::Pins { max: PWMIN_CHANNELS - 1 },
+    required: true,
+    help: "gpio numbers, all: the ones pwmin owns",
+    complete: None,
+};
+
+static PWMIN_CMD: Command = Command {
+    name: "pwmin",
+    summary: "measure pwm input",
+    usage: "pwmin <start|stop|status|watch|measure|stats|config> ..",
+    args: &[],
+    subcommands: &[
+        Command {
+            name: "start",
+            summary: "start capture",
+            usage: "pwmin start <gpio..|all>",
+            args: &[PWMIN_CH_ARG],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_start_cmd)),
+        },
+        Command {
+            name: "stop",
+            summary: "stop capture",
+            usage: "pwmin stop <gpio..|all>",
+            args: &[Arg { complete: Some(pwmin_running_channels), ..PWMIN_CH_ARG }],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_stop_cmd)),
+        },
+        Command {
+            name: "status",
+            summary: "show running channels",
+            usage: "pwmin status",
+            args: &[],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_status_cmd)),
+        },
+        Command {
+            name: "watch",
+            summary: "print captures until Ctrl-C",
+            usage: "pwmin watch <gpio..|all>",
+            args: &[PWMIN_CH_ARG],
+            subcommands: &[],
+            handler: Some(Handler::Async(PWMIN_WATCH_TASK)),
+        },
+        Command {
+            name: "measure",
+            summary: "show the last captures in Hz, us and %",
+            usage: "pwmin measure <gpio..|all>",
+            args: &[PWMIN_CH_ARG],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_measure_cmd)),
+        },
+        Command {
+            name: "stats",
+            summary: "show period and duty statistics, and the jitter",
+            usage: "pwmin stats [-r] [-w <ticks>] <gpio..|all>",
+            args: &[
+                Arg {
+                    name: "-r",
+                    kind: ArgKind::Switch,
+                    required: false,
+                    help: "reset after showing",
+                    complete: None,
+                },
+                Arg {
+                    name: "-w",
+                    kind: ArgKind::Int { min: 1, max: i32::MAX },
+                    required: false,
+                    help: "jitter bin width in ticks, resets after showing",
+                    complete: None,
+                },
+                Arg { complete: Some(pwmin_running_channels), ..PWMIN_CH_ARG },
+            ],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_stats_cmd)),
+        },
+        Command {
+            name: "config",
+            summary: "set when captures are sent, show it",
+            usage: "pwmin config [-t <ticks>] [-p <ppm>] [-n <samples>] [-i <ms>] [-c <on|off>] <gpio..|all>",
+            args: &[
+                Arg {
+                    name: "-t",
+                    kind: ArgKind::Int { min: 0, max: i32::MAX },
+                    required: false,
+                    help: "change threshold in ticks",
+                    complete: None,
+                },
+                Arg {
+                    name: "-p",
+                    kind: ArgKind::Int { min: 0, max: 1_000_000 },
+                    required: false,
+                    help: "change threshold in ppm of the period",
+                    complete: None,
+                },
+                Arg {
+                    name: "-n",
+                    kind: ArgKind::Int { min: 0, max: i32::MAX },
+                    required: false,
+                    help: "send the same period every n samples, 0 never",
+                    complete: None,
+                },
+                Arg {
+                    name: "-i",
+                    kind: ArgKind::Int { min: 0, max: i32::MAX },
+                    required: false,
+                    help: "ms between captures at least",
+                    complete: None,
+                },
+                Arg {
+                    name: "-c",
+                    kind: ArgKind::Choice(&["on", "off"]),
+                    required: false,
+                    help: "only send changes",
+                    complete: None,
+                },
+                PWMIN_CH_ARG,
+            ],
+            subcommands: &[],
+            handler: Some(Handler::Sync(pwmin_config_cmd)),
+        },
+    ],
+    handler: None,
+};
+
+fn selected_channels(args:&Args) -> impl Iterator<Item = usize> {
+    channels_in(selected_mask(args))
+}
+
+/// the `ch` argument as a mask, `all` is the GPIOs handed to `pwmin_init`
+fn selected_mask(args:&Args) -> u32 {
+    match args.pins("ch") {
+        ALL_CHANNELS => unsafe {PWMIN.owned},
+        channels => channels,
+    }
+}
+
+/// the channels of a bit mask, channel 0 is bit 0
+pub fn channels_in(channels:u32) -> impl Iterator<Item = usize> {
+    (0..PWMIN_CHANNELS as usize).filter(move |ch| channels & (1 << ch) != 0)
+}
+
+pub fn start(ch:usize) -> Result<(), PwmInError> {
+    unsafe {PWMIN.start(ch)}
+}
+
+pub fn stop(ch:usize) {
+    unsafe {PWMIN.stop(ch)}
+}
+
+pub fn running(ch:usize) -> bool {
+    unsafe {PWMIN.pin_in_use(ch)}
+}
+
+/// the last capture of `ch`, `None` before the first one
+pub fn last_capture(ch:usize) -> Option<PwmInfo> {
+    unsafe {PWMIN.last_capture(ch)}
+}
+
+/// captures of all channels, `None` when there are subscribers enough
+pub fn subscribe() -> Option<PwmSubscriber> {
+    PWM_PUBSUB_CHANNEL.subscriber().ok()
+}
+
+/// the threshold last set with `set_threshold`, `pwmin config` may have
+/// changed the one of a channel since
+pub fn threshold() -> u32 {
+    PWMIN_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// set the threshold of all channels in ticks, over what `pwmin config`
+/// set, ppm thresholds too
+pub fn set_threshold(ticks:u32) {
+    PWMIN_THRESHOLD.store(ticks, Ordering::Relaxed);
+    for policy in unsafe {PWMIN.policies.iter_mut()} {
+        policy.threshold = Threshold::Ticks(ticks);
+    }
+}
+
+fn pwmin_start_cmd(args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    //one error for more channels than state machines, not one a channel
+    let startable = selected_mask(args) & unsafe {PWMIN.owned};
+    let stopped = channels_in(startable).filter(|ch| !running(*ch)).count();
+    let free = unsafe {PWMIN.pwmin_state.iter()}.filter(|state| state.gpio.is_none()).count();
+    if stopped > free {
+        write!(out, "[pwmin] {} channels to start, {} free state machines\r\n", stopped, free)?;
+        return Err(ashell::ShellError::ExecuteError(-1));
+    }
+    for ch in selected_channels(args) {
+        let ret = unsafe {PWMIN.start(ch)};
+        match ret {
+            Err(PwmInError::PinInUse) => write!(out, "[pwmin] {} already started\r\n", ch)?,
+            Err(PwmInError::PinError) => write!(out, "[pwmin] {} invalid\r\n", ch)?,
+            Err(PwmInError::PinAllocFail) => write!(out, "[pwmin] {} no free state machine\r\n", ch)?,
+            Err(PwmInError::PinNotFree) => write!(out, "[pwmin] {} used by something else\r\n", ch)?,
+            Ok(_) => write!(out, "[pwmin] {} start success\r\n", ch)?,
+        }
+    }
+    Ok(())
+}
+
+fn pwmin_stop_cmd(args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    for ch in selected_channels(args) {
+        unsafe {PWMIN.stop(ch)};
+        write!(out, "[pwmin] {} stop success\r\n", ch)?;
+    }
+    Ok(())
+}
+
+fn pwmin_status_cmd(_args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    for (slot, state) in unsafe {PWMIN.pwmin_state.iter()}.enumerate() {
+        match state.gpio {
+            Some(gpio) => write!(out, "[pwmin] sm {}: gpio {}\r\n", slot, gpio)?,
+            None => write!(out, "[pwmin] sm {}: free\r\n", slot)?,
         }
     }
+    Ok(())
+}
 
-    pub fn stop(&mut self, idx:usize) {
-        if idx < self.pwmin_state.len() {
-            self.pwmin_state[idx].run = false;
-            self.pwmin_state[idx].cmd.signal(PwmInCommand::Stop);
+fn pwmin_measure_cmd(args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    for ch in selected_channels(args) {
+        match last_capture(ch) {
+            Some(capture) => write!(out, "[pwmin] {}: {}\r\n", ch, capture.measure())?,
+            None => write!(out, "[pwmin] {}: no capture\r\n", ch)?,
         }
     }
+    Ok(())
+}
 
-    // fn create_pin(&self, pin:usize) -> Option<AnyPin> {
-    //     if pin > 29 {
-    //         return None;
-    //     }
-
-    // }
-
-    // fn create_sm(&self, pio_no:usize, sm_no:usize) -> impl PioStateMachine {
-    // }
-
-    pub fn start(&mut self, idx:usize) -> Result<(), PwmInError> {
-        if self.pin_in_use(idx) {
-            Err(PwmInError::PinInUse)
-        } else {
-            if idx < self.pwmin_state.len() {
-                self.pwmin_state[idx].cmd.signal(PwmInCommand::Start(idx));
-                //TODO use AutomicBool
-                self.pwmin_state[idx].run = true;
-                Ok(())
-            } else {
-                Err(PwmInError::PinError)
+fn pwmin_stats_cmd(args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    for ch in selected_channels(args) {
+        let stats = match unsafe {PWMIN.stats_mut(ch)} {
+            Some(stats) => stats,
+            None => {
+                write!(out, "[pwmin] {}: not running\r\n", ch)?;
+                continue;
             }
+        };
+        write!(out, "[pwmin] {}: {}\r\n", ch, stats.display(&PWMIN_TIMEBASE))?;
+        //a new width only fits new periods
+        if let Some(width) = args.int("-w") {
+            *stats = PwmStats::new(width as u32);
+        } else if args.switch("-r") {
+            stats.reset();
         }
     }
+    Ok(())
+}
 
+fn pwmin_config_cmd(args:&Args, out:&mut dyn core::fmt::Write) -> ShellResult {
+    if args.int("-t").is_some() && args.int("-p").is_some() {
+        write!(out, "[pwmin] -t and -p exclude each other\r\n")?;
+        return Err(ashell::ShellError::ExecuteError(-1));
+    }
+    for ch in selected_channels(args) {
+        let policy = unsafe {&mut PWMIN.policies[ch]};
+        if let Some(ticks) = args.int("-t") {
+            policy.threshold = Threshold::Ticks(ticks as u32);
+        }
+        if let Some(ppm) = args.int("-p") {
+            policy.threshold = Threshold::Ppm(ppm as u32);
+        }
+        if let Some(every) = args.int("-n") {
+            policy.every = every as u32;
+        }
+        if let Some(ms) = args.int("-i") {
+            policy.min_interval_ms = ms as u32;
+        }
+        if let Some(change_only) = args.choice("-c") {
+            policy.change_only = change_only == "on";
+        }
+        write!(out, "[pwmin] {}: {}\r\n", ch, policy)?;
+    }
+    Ok(())
 }
 
-fn pwmin_cmd(cmd:&str, args:&str) -> ShellResult {
-    let (sub_cmd , sub_args) = args.split_once(" ").unwrap_or((args, &""));
-    let stop_all = if sub_args.starts_with("all") {
-        true
-    } else {
-        false
+/// print the captures of the selected channels, runs until cancelled
+pub async fn pwmin_watch_cmd(args:&Args<'_>, out:&mut dyn CommandOutput) -> ShellResult {
+    let channels = selected_mask(args);
+    let mut sub = match PWM_PUBSUB_CHANNEL.subscriber() {
+        Ok(sub) => sub,
+        Err(_) => {
+            write!(out, "[pwmin] too many watchers\r\n")?;
+            return Err(ashell::ShellError::ExecuteError(-1));
+        }
     };
-    match sub_cmd {
-        "start" => {
-            //start pwmin
-            sub_args.split_ascii_whitespace().map(|a| {a.parse::<usize>().unwrap()}).for_each(|pin| { 
-                let ret = unsafe {PWMIN.start(pin)};
-                match ret {
-                    Err(PwmInError::PinInUse) => log::info!("[pwmin] {} already started", pin),
-                    Err(PwmInError::PinError) => log::info!("[pwmin] {} invalid", pin),
-                    Ok(_) => log::info!("[pwmin] {} start success", pin),
-                    _ => (),
-                }
-            });
-            Ok(())
-        },
-        "stop" => {
-            //stop pwmin
-            sub_args.split_ascii_whitespace().map(|a| {a.parse::<usize>().unwrap()}).for_each(|pin| { 
-                unsafe {PWMIN.stop(pin)};
-                log::info!("[pwmin] {} stop success", pin);
-            });
-            Ok(())
-        },
-        _ => {
-            Err(ashell::ShellError::ExecuteError(-1))
+    loop {
+        if let WaitResult::Message(msg) = sub.next_message().await {
+            if msg.pin < 32 && channels & (1 << msg.pin) != 0 {
+                //one record a line, for `| field` and `seventest monitor`: the ticks,
+                //then the same in real units
+                let measured = msg.measure();
+                let mut line: heapless::String<128> = heapless::String::new();
+                write!(line, "[PwmIn]:{}:{}:{}:{}:{}:{}Hz:{}us:{}us:{}%\r\n", msg.pin, msg.time, msg.count,
+                    msg.high_period, msg.low_period, measured.frequency(), measured.high_us(), measured.low_us(), measured.duty())?;
+                //a busy link slows the records down, it does not cut them
+                output::write_all(out, &line).await?;
+            }
+        }
+    }
+}
+
+/// only running channels can be stopped
+fn pwmin_running_channels(add: &mut dyn FnMut(&str)) {
+    add("all");
+    for state in unsafe {PWMIN.pwmin_state.iter()} {
+        if let Some(gpio) = state.gpio {
+            let mut name: heapless::String<2> = heapless::String::new();
+            let _ = write!(name, "{}", gpio);
+            add(&name);
         }
     }
 }
 
 //register pwmin cmd
 pub fn pwmin_register_cmd() {
-    register_shell_cmd("pwmin", pwmin_cmd);
+    register_shell_cmd(&PWMIN_CMD);
 }
 
 macro_rules! impl_pwmin_pio {
     ($pio:ident, $sm:ident, $fn:ident) => {
         #[embassy_executor::task]
-        pub async fn $fn(mut sm: PioStateMachineInstance<$pio, $sm>, pin:AnyPin, signal_no:usize, wrap_source:u8, wrap_target:u8) {
-            //setup msg
-            let mut msg:PwmInfo = PwmInfo::default();
-            msg.pin = pin.pin() as u32;
+        pub async fn $fn(mut sm: PioStateMachineInstance<$pio, $sm>, signal_no:usize, wrap_source:u8, wrap_target:u8) {
             let publisher = PWM_PUBSUB_CHANNEL.publisher().unwrap();
+            let publish = |msg:PwmInfo| {
+                unsafe {PWMIN.set_last_capture(signal_no, msg)};
+                publisher.publish_immediate(msg);
+            };
             let signal = unsafe {PWMIN.get_stop_signal(signal_no).unwrap()};
 
             // setup sm
             sm.set_enable(false);
-            sm.restart();
-            sm.clear_fifos();
-            let _wait_irq = sm.sm_no();
-            pio_instr_util::exec_jmp(&mut sm, 0);
             sm.set_wrap(wrap_source, wrap_target);
-
-            let pin = sm.make_pio_pin(pin);
-            sm.set_jmp_pin(pin.pin());
-            sm.set_in_base_pin(&pin);
-
-            let clkdiv:u32 = (125e6 / (SM_CLK as f32)) as u32;
-            sm.set_clkdiv(clkdiv << 8);
-
-            // sm.set_autopull(false);
+            sm.set_clkdiv(PWMIN_TIMEBASE.clkdiv);
             sm.set_fifo_join(FifoJoin::RxOnly);
             sm.set_in_shift_dir(ShiftDirection::Left);
-            // sm.set_pull_threshold(2);
-
-            let mut high_period:u32 = 10; 
-            let mut low_period:u32 = 20; 
-            // let mut tmp_period_1:u32 = 0;
-            // let mut tmp_period_2:u32 = 0;
 
+            let mut cmd = signal.wait().await;
             loop {
-                let cmd = signal.wait().await;
-                // log::info!("cmd:{:?}", cmd);
-                if let PwmInCommand::Start(_) = cmd {
-                    //have received start cmd
-                    sm.set_enable(true);
-                    loop {
-                        // sm.wait_irq(_wait_irq).await;
-                        high_period = sm.wait_pull().await * 2;
-                        low_period = sm.wait_pull().await * 2;
-                        sm.clear_fifos();
-                        // if tmp_period_1 & 0xF0000000 != 0 {
-                        //     //tmp_period_1 is low_period
-                        //     high_period = tmp_period_2 * 2;
-                        //     low_period = tmp_period_1 * 2;
-                        // } else {
-                        //     //tmp_period_1 is high_period
-                        //     low_period = tmp_period_2 * 2;
-                        //     high_period = tmp_period_1 * 2;
-                        // }
-                        if msg.high_period.abs_diff(high_period) > 10 || msg.low_period.abs_diff(low_period) > 10 {
+                let gpio = match cmd {
+                    PwmInCommand::Start(gpio) => gpio,
+                    PwmInCommand::Stop => {
+                        cmd = signal.wait().await;
+                        continue;
+                    }
+                };
+                //owned since `pwmin_init` and handed to one state machine at a time,
+                //see `PwmInShellEnv::start`
+                let pin = sm.make_pio_pin(unsafe {AnyPin::steal(gpio)});
+                sm.set_jmp_pin(pin.pin());
+                sm.set_in_base_pin(&pin);
+                sm.restart();
+                sm.clear_fifos();
+                pio_instr_util::exec_jmp(&mut sm, 0);
+
+                //setup msg
+                let mut msg = PwmInfo { pin: gpio as u32, ..PwmInfo::default() };
+                let mut sent = Instant::now();
+                sm.set_enable(true);
+                //until the next command, a pin without edges waits for it too
+                cmd = loop {
+                    let high = match select(sm.wait_pull(), signal.wait()).await {
+                        Either::First(high) => high,
+                        Either::Second(cmd) => break cmd,
+                    };
+                    //pushed right after the high period: not cancelled, so a stop
+                    //never leaves half a pair in the FIFO
+                    let low = sm.wait_pull().await;
+                    //both loops take 2 cycles a count
+                    let (high_period, low_period) = (PWMIN_TIMEBASE.ticks(high), PWMIN_TIMEBASE.ticks(low));
+                    sm.clear_fifos();
+                    unsafe {PWMIN.add_sample(signal_no, high_period, low_period)};
+                    let policy = unsafe {PWMIN.policy(gpio as usize)};
+                    let last = (msg.time != 0).then_some((msg.high_period, msg.low_period));
+                    let now = Instant::now();
+                    match report(&policy, last, high_period, low_period, msg.count, (now - sent).as_millis()) {
+                        Report::Count => msg.count += 1,
+                        Report::Wait => {}
+                        Report::Repeat => {
+                            msg.count += 1;
+                            msg.time = now.as_micros();
+                            publish(msg);
+                            msg.count = 0;
+                            sent = now;
+                        }
+                        Report::Change => {
                             if msg.time != 0 {
                                 //send previous msg
-                                msg.time = Instant::now().as_micros();
-                                publisher.publish_immediate(msg);
+                                msg.time = now.as_micros();
+                                publish(msg);
                             }
                             msg.count = 1;
                             msg.high_period = high_period;
                             msg.low_period = low_period;
-                            msg.time = Instant::now().as_micros();
-                            publisher.publish_immediate(msg);
-                        } else {
-                            //add count
-                            msg.count += 1;
-                            if msg.count >= 100 {
-                                //send
-                                msg.time = Instant::now().as_micros();
-                                publisher.publish_immediate(msg);
-                                msg.count = 0;
-                            }
-                        }
-
-                        //check whether we need exit
-                        if signal.signaled() {
-                            log::info!("[pwmin] pin {} exited", pin.pin());
-                            sm.set_enable(false);
-                            break;
+                            msg.time = now.as_micros();
+                            publish(msg);
+                            sent = now;
                         }
                     }
-                }
-                else {
-                    signal.reset();
-                }
+                };
+                log::info!("[pwmin] gpio {} exited", gpio);
+                sm.set_enable(false);
             }
         }
     };
@@ -283,44 +623,34 @@ impl_pwmin_pio!(Pio0, Sm1, pio0_sm1_pwmin_task);
 impl_pwmin_pio!(Pio0, Sm2, pio0_sm2_pwmin_task);
 impl_pwmin_pio!(Pio0, Sm3, pio0_sm3_pwmin_task);
 impl_pwmin_pio!(Pio1, Sm0, pio1_sm0_pwmin_task);
-// impl_pwmin_pio!(Pio1, Sm1, pio1_sm1_pwmin_task);
-// impl_pwmin_pio!(Pio1, Sm2, pio1_sm2_pwmin_task);
-// impl_pwmin_pio!(Pio1, Sm3, pio1_sm3_pwmin_task);
-
-// pub fn pwmin_init(pio0sm0:PioStateMachineInstance<Pio0, Sm0>, pio0sm1:PioStateMachineInstance<Pio0, Sm1>, pio0sm2:PioStateMachineInstance<Pio0, Sm2>,
-                //   pio0sm3:PioStateMachineInstance<Pio0, Sm3>, pio1sm0:PioStateMachineInstance<Pio1, Sm0>) {
-pub async fn pwmin_init(pio0:PIO0, pio1:PIO1, pin0:AnyPin, pin1:AnyPin, pin2:AnyPin, pin3:AnyPin, pin4:AnyPin) {
-    register_shell_cmd("pwmin", pwmin_cmd);
+impl_pwmin_pio!(Pio1, Sm1, pio1_sm1_pwmin_task);
+impl_pwmin_pio!(Pio1, Sm2, pio1_sm2_pwmin_task);
+impl_pwmin_pio!(Pio1, Sm3, pio1_sm3_pwmin_task);
+
+/// Load `PwmIn.pio` into both PIO blocks and start a task for each state
+/// machine, all free until `pwmin start` gives them one of `pins`.
+pub async fn pwmin_init(pio0:PIO0, pio1:PIO1, pins:impl IntoIterator<Item = AnyPin>) {
+    register_shell_cmd(&PWMIN_CMD);
+    for pin in pins {
+        unsafe {PWMIN.owned |= 1 << pin.pin()};
+    }
 
-    //spawn task
     let (mut pio0common, sm0, sm1, sm2, sm3) = pio0.split();
-    let (mut pio1common, sm4, ..) = pio1.split();
+    let (mut pio1common, sm4, sm5, sm6, sm7) = pio1.split();
 
-    //setup pwmin_program for PIO0 and PIO1 for share
     let prg = pio_proc::pio_file!("./src/PwmIn.pio");
     let relocated = RelocatedProgram::new(&prg.program);
     let pio::Wrap{ source, target } = relocated.wrap();
     pio0common.write_instr(relocated.origin() as usize, relocated.code());
-    // pio1common.write_instr(relocated.origin() as usize, relocated.code());
-
-    Spawner::for_current_executor().await.spawn(pio0_sm0_pwmin_task(sm0, pin0, 0, source, target)).unwrap();
-    //Spawner::for_current_executor().await.spawn(pio0_sm1_pwmin_task(sm1, pin1, 1, source, target)).unwrap();
-    //Spawner::for_current_executor().await.spawn(pio0_sm2_pwmin_task(sm2, pin2, 2, source, target)).unwrap();
-    //Spawner::for_current_executor().await.spawn(pio0_sm3_pwmin_task(sm3, pin3, 3, source, target)).unwrap();
-    //Spawner::for_current_executor().await.spawn(pio1_sm0_pwmin_task(sm4, pin4, 4, source, target)).unwrap();
-    
-    Spawner::for_current_executor().await.spawn(pwmin_log_task()).unwrap();
-}
-
-#[embassy_executor::task]
-pub async fn pwmin_log_task() {
-    let mut sub = PWM_PUBSUB_CHANNEL.subscriber().unwrap();
-
-    loop {
-        if let WaitResult::Message(msg) = sub.next_message().await {
-            // log::info!("[PwmIn]:{}:{}:{}:{}", msg.pin, msg.clk, msg.high_period, msg.low_period);
-            //do not send clock
-            log::info!("[PwmIn]:{}:{}:{}:{}:{}", msg.pin, msg.time, msg.count, msg.high_period, msg.low_period);
-        }
-    }
+    pio1common.write_instr(relocated.origin() as usize, relocated.code());
+
+    let spawner = Spawner::for_current_executor().await;
+    spawner.spawn(pio0_sm0_pwmin_task(sm0, 0, source, target)).unwrap();
+    spawner.spawn(pio0_sm1_pwmin_task(sm1, 1, source, target)).unwrap();
+    spawner.spawn(pio0_sm2_pwmin_task(sm2, 2, source, target)).unwrap();
+    spawner.spawn(pio0_sm3_pwmin_task(sm3, 3, source, target)).unwrap();
+    spawner.spawn(pio1_sm0_pwmin_task(sm4, 4, source, target)).unwrap();
+    spawner.spawn(pio1_sm1_pwmin_task(sm5, 5, source, target)).unwrap();
+    spawner.spawn(pio1_sm2_pwmin_task(sm6, 6, source, target)).unwrap();
+    spawner.spawn(pio1_sm3_pwmin_task(sm7, 7, source, target)).unwrap();
 }
@@ -0,0 +1,168 @@
+//! The binary protocol of `seventest-proto` next to the text shell of a
+//! link. Frames are taken out of the input before the shell sees it, the
+//! answers and the events go out with the shell output.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use ashell::output::Output;
+use ashell::{Event, Source};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_sync::pubsub::WaitResult;
+use seventest_proto::frame::Byte;
+use seventest_proto::{decode, encode, Command, Error, FrameReader, Message, Reply, Request, MAX_FRAME_LEN, PROTOCOL_VERSION};
+
+use crate::pwmin_pio::{self, PwmInError, PWMIN_CHANNELS};
+use crate::shell::{LOG_BUFF_SIZE, UART_SHELL_PIPE};
+#[cfg(all(usb_shell, not(scpi)))]
+use crate::shell::USB_SHELL_PIPE;
+
+/// The rpc side of a link.
+pub struct RpcLink {
+    /// where answers and events go, with the shell output
+    out: &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>,
+    /// channels to send captures of
+    subscribed: AtomicU32,
+}
+
+pub static UART_RPC: RpcLink = RpcLink::new(&UART_SHELL_PIPE);
+#[cfg(all(usb_shell, not(scpi)))]
+pub static USB_RPC: RpcLink = RpcLink::new(&USB_SHELL_PIPE);
+
+impl RpcLink {
+    pub const fn new(out: &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>) -> Self {
+        Self {
+            out,
+            subscribed: AtomicU32::new(0),
+        }
+    }
+
+    fn send(&self, msg: &Message) {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        if let Ok(len) = encode(msg, &mut buf) {
+            //a frame is sent whole or not at all, the host reads a cut one as text
+            let mut out = self.out;
+            if out.room() >= len {
+                Output::try_write(&mut out, &buf[..len]);
+            }
+        }
+    }
+
+    fn answer(&self, request: Option<Request>) {
+        let msg = match request {
+            Some(request) => Message::Response { id: request.id, result: self.handle(request.command) },
+            None => Message::Response { id: 0, result: Err(Error::BadRequest) },
+        };
+        self.send(&msg);
+    }
+
+    fn handle(&self, command: Command) -> Result<Reply, Error> {
+        let valid = |channels: u32| match channels >> PWMIN_CHANNELS {
+            0 => Ok(channels),
+            _ => Err(Error::NoSuchChannel),
+        };
+        match command {
+            Command::Hello => Ok(Reply::Hello { version: PROTOCOL_VERSION, channels: PWMIN_CHANNELS }),
+            Command::PwmInStart { channels } => {
+                let mut busy = false;
+                for ch in pwmin_pio::channels_in(valid(channels)?) {
+                    match pwmin_pio::start(ch) {
+                        Ok(()) => {}
+                        Err(PwmInError::PinInUse) => busy = true,
+                        Err(PwmInError::PinAllocFail) => return Err(Error::NoFreeChannel),
+                        Err(PwmInError::PinError | PwmInError::PinNotFree) => return Err(Error::NoSuchChannel),
+                    }
+                }
+                //the other channels are started anyway
+                match busy {
+                    true => Err(Error::Busy),
+                    false => Ok(Reply::Done),
+                }
+            }
+            Command::PwmInStop { channels } => {
+                for ch in pwmin_pio::channels_in(valid(channels)?) {
+                    pwmin_pio::stop(ch);
+                }
+                Ok(Reply::Done)
+            }
+            Command::PwmInStatus => {
+                let running = pwmin_pio::channels_in(u32::MAX)
+                    .filter(|ch| pwmin_pio::running(*ch))
+                    .fold(0, |mask, ch| mask | 1 << ch);
+                Ok(Reply::PwmInStatus { running, threshold: pwmin_pio::threshold() })
+            }
+            Command::PwmInConfig { threshold } => {
+                pwmin_pio::set_threshold(threshold);
+                Ok(Reply::Done)
+            }
+            Command::Subscribe { channels } => {
+                self.subscribed.store(valid(channels)?, Ordering::Relaxed);
+                Ok(Reply::Done)
+            }
+        }
+    }
+}
+
+/// The input of a link without the frames, those are answered here.
+pub struct RpcSource<S: Source> {
+    inner: S,
+    link: &'static RpcLink,
+    reader: FrameReader<MAX_FRAME_LEN>,
+}
+
+impl<S: Source> RpcSource<S> {
+    pub fn new(inner: S, link: &'static RpcLink) -> Self {
+        Self {
+            inner,
+            link,
+            reader: FrameReader::new(),
+        }
+    }
+}
+
+impl<S: Source> Source for RpcSource<S> {
+    async fn next(&mut self, buf: &mut [u8]) -> Event {
+        loop {
+            let n = match self.inner.next(buf).await {
+                Event::Input(n) => n,
+                print => return print,
+            };
+            let mut text = 0;
+            for idx in 0..n {
+                match self.reader.feed(buf[idx]) {
+                    Byte::Text(byte) => {
+                        buf[text] = byte;
+                        text += 1;
+                    }
+                    Byte::Frame => {}
+                    Byte::End => self.link.answer(decode(self.reader.frame()).ok()),
+                    Byte::Overrun => self.link.answer(None),
+                }
+            }
+            //input of frames only is not for the shell
+            if text > 0 {
+                return Event::Input(text);
+            }
+        }
+    }
+}
+
+/// Send the captures of the channels `link` subscribed to.
+#[embassy_executor::task(pool_size = 2)]
+pub async fn rpc_event_task(link: &'static RpcLink) {
+    let mut sub = match pwmin_pio::subscribe() {
+        Some(sub) => sub,
+        None => {
+            log::info!("[rpc] no pwmin subscriber left, no events");
+            return;
+        }
+    };
+    loop {
+        if let WaitResult::Message(msg) = sub.next_message().await {
+            let ch = msg.channel();
+            if ch < 32 && link.subscribed.load(Ordering::Relaxed) & (1 << ch) != 0 {
+                link.send(&Message::Event(seventest_proto::Event::PwmIn(msg.capture())));
+            }
+        }
+    }
+}
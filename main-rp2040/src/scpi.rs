@@ -0,0 +1,136 @@
+//! SCPI on the usb port, built with `--cfg scpi`: the pwmin channels as an
+//! instrument for lab automation.
+//!
+//! ```text
+//! MEASure:PWM:FREQuency? (@0,1)    Hz
+//! MEASure:PWM:PERiod? (@0)         seconds
+//! MEASure:PWM:DUTY? (@0)           percent high
+//! CONFigure:PWMIN[:STATe] ON|OFF,(@0:4)
+//! CONFigure:PWMIN[:STATe]? (@0)    1 running, 0 stopped
+//! CONFigure:PWMIN:THREShold <ticks>|MIN|MAX
+//! CONFigure:PWMIN:THREShold?
+//! ```
+//!
+//! The threshold is that of all channels, setting it replaces the ones
+//! `pwmin config` gave single channels; the query answers the one last set
+//! here.
+//!
+//! Channels are GPIO numbers, each started on a free state machine. A
+//! channel without a capture yet measures 9.91E37, the SCPI not a number.
+
+use core::fmt::Write;
+
+use ashell::scpi::{Instrument, Params, Scpi, ScpiCommand, ScpiError};
+use ashell::{Event, Source};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+use seventest_proto::DEFAULT_THRESHOLD;
+
+use crate::mylog::MyWriter;
+use crate::pwmin_pio::{self, PwmInfo, PWMIN_CHANNELS};
+use crate::shell::LOG_BUFF_SIZE;
+
+/// errors kept for `SYST:ERR?`
+pub const ERROR_QUEUE_LEN: usize = 16;
+const NOT_A_NUMBER: &str = "9.91E37";
+const MAX_THRESHOLD: i32 = 100_000;
+
+const MEAS_FREQ: u16 = 1;
+const MEAS_PERIOD: u16 = 2;
+const MEAS_DUTY: u16 = 3;
+const CONF_STATE: u16 = 4;
+const CONF_STATE_QUERY: u16 = 5;
+const CONF_THRESHOLD: u16 = 6;
+const CONF_THRESHOLD_QUERY: u16 = 7;
+
+static COMMANDS: [ScpiCommand; 7] = [
+    ScpiCommand { pattern: "MEASure:PWM:FREQuency?", id: MEAS_FREQ },
+    ScpiCommand { pattern: "MEASure:PWM:PERiod?", id: MEAS_PERIOD },
+    ScpiCommand { pattern: "MEASure:PWM:DUTY?", id: MEAS_DUTY },
+    ScpiCommand { pattern: "CONFigure:PWMIN[:STATe]", id: CONF_STATE },
+    ScpiCommand { pattern: "CONFigure:PWMIN[:STATe]?", id: CONF_STATE_QUERY },
+    ScpiCommand { pattern: "CONFigure:PWMIN:THREShold", id: CONF_THRESHOLD },
+    ScpiCommand { pattern: "CONFigure:PWMIN:THREShold?", id: CONF_THRESHOLD_QUERY },
+];
+
+pub type SevenScpi = Scpi<SevenInstrument, ERROR_QUEUE_LEN>;
+
+pub struct SevenInstrument;
+
+impl Instrument for SevenInstrument {
+    fn identity(&self) -> &str {
+        concat!("Seven,SevenTestHW,0,", env!("CARGO_PKG_VERSION"))
+    }
+
+    fn reset(&mut self) {
+        for ch in 0..PWMIN_CHANNELS as usize {
+            pwmin_pio::stop(ch);
+        }
+        pwmin_pio::set_threshold(DEFAULT_THRESHOLD);
+    }
+
+    fn commands(&self) -> &'static [ScpiCommand] {
+        &COMMANDS
+    }
+
+    async fn execute(&mut self, id: u16, params: &mut Params<'_>, out: &mut dyn Write) -> Result<(), ScpiError> {
+        match id {
+            MEAS_FREQ | MEAS_PERIOD | MEAS_DUTY => {
+                let channels = params.channels(PWMIN_CHANNELS - 1)?;
+                for (n, ch) in pwmin_pio::channels_in(channels).enumerate() {
+                    let sep = if n == 0 { "" } else { "," };
+                    let value = pwmin_pio::last_capture(ch).map(|capture: PwmInfo| {
+                        let measured = capture.measure();
+                        match id {
+                            MEAS_FREQ => measured.frequency(),
+                            MEAS_PERIOD => measured.period_s(),
+                            _ => measured.duty(),
+                        }
+                    });
+                    let _ = match value {
+                        Some(value) => write!(out, "{}{}", sep, value),
+                        None => write!(out, "{}{}", sep, NOT_A_NUMBER),
+                    };
+                }
+            }
+            CONF_STATE => {
+                let on = params.boolean()?;
+                let channels = params.channels(PWMIN_CHANNELS - 1)?;
+                for ch in pwmin_pio::channels_in(channels) {
+                    if !on {
+                        pwmin_pio::stop(ch);
+                    } else if !pwmin_pio::running(ch) && pwmin_pio::start(ch).is_err() {
+                        return Err(ScpiError::HardwareMissing);
+                    }
+                }
+            }
+            CONF_STATE_QUERY => {
+                let channels = params.channels(PWMIN_CHANNELS - 1)?;
+                for (n, ch) in pwmin_pio::channels_in(channels).enumerate() {
+                    let sep = if n == 0 { "" } else { "," };
+                    let _ = write!(out, "{}{}", sep, pwmin_pio::running(ch) as u8);
+                }
+            }
+            CONF_THRESHOLD => pwmin_pio::set_threshold(params.int(0, MAX_THRESHOLD)? as u32),
+            CONF_THRESHOLD_QUERY => {
+                let _ = write!(out, "{}", pwmin_pio::threshold());
+            }
+            _ => return Err(ScpiError::UndefinedHeader),
+        }
+        Ok(())
+    }
+}
+
+/// Serve SCPI on `source`, the answers go to `out`. Text to print is not
+/// SCPI, it is dropped.
+pub async fn serve(scpi: &mut SevenScpi, source: &mut impl Source, out: &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>) -> ! {
+    let mut out = MyWriter(out);
+    let mut buf = [0u8; 64];
+    loop {
+        if let Event::Input(n) = source.next(&mut buf).await {
+            for byte in &buf[..n] {
+                scpi.feed(*byte, &mut out).await;
+            }
+        }
+    }
+}
@@ -1,21 +1,36 @@
 #![feature(async_fn_in_trait)]
 #![allow(incomplete_features)]
 
-use core::{cell::RefCell, f32::consts::E};
-use core::str::FromStr;
+use core::cell::RefCell;
+use core::fmt::Write as _;
 use heapless::String;
 use ashell::{
-                ShellResult,Environment, 
-                autocomplete::{FnAutocomplete, Autocomplete}, 
-                history::{LRUHistory, History}, AShell
+                ShellResult,ArgvEnvironment, Event, Source,
+                autocomplete::Autocomplete, 
+                command::{Arg, ArgKind, Args, Command, Handler, Registry, Task},
+                flash_history::FlashHistory,
+                flash_script::FlashScripts,
+                history::{History, LRUHistory}, AShell,
+                jobs::Jobs,
+                output::{CommandOutput, OutputWriter},
+                script::SCRIPT_LEN,
+                alias::ALIAS_LEN
             };
-use heapless::FnvIndexMap;
-use embassy_sync::{blocking_mutex::ThreadModeMutex, mutex::MutexGuard};
+use embassy_rp::flash::Flash;
+use embassy_rp::peripherals::FLASH;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 // use embassy_sync::mutex::Mutex;
 use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::pipe::Pipe;
+use embassy_futures::select::{select, Either};
+use embassy_rp::peripherals::UART0;
+use embassy_rp::uart::BufferedUartRx;
+use embassy_time::{Duration, Timer};
+use embedded_io::asynch::Read;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
 
 use crate::mylog::LOG_PIPE;
+use crate::pwmin_pio;
 // use embassy_sync::blocking_mutex::CriticalSectionMutex;
 
 // type ShellMutex = ThreadModeRawMutex;
@@ -24,26 +39,58 @@ use crate::mylog::LOG_PIPE;
 pub const MAX_CMD_LEN:usize = 64;
 pub const TOTAL_CMDS:usize = 16;
 pub const LOG_BUFF_SIZE:usize = 1024;
+//room for a few `| filter`s
+pub const MAX_ARGC:usize = 16;
+/// background jobs per shell, and the jobs running at once
+pub const MAX_JOBS:usize = 4;
+pub const JOB_RUNNERS:usize = 2;
+pub const FLASH_SIZE:usize = 2 * 1024 * 1024;
+/// the last 16K of flash keep the shell history, memory.x leaves them out
+pub const HISTORY_FLASH_START:u32 = (FLASH_SIZE - 16 * 1024) as u32;
+/// stored scripts, shared by both shells
+pub const MAX_SCRIPTS:usize = 8;
+/// the 4K before the history keep the scripts
+pub const SCRIPT_FLASH_START:u32 = HISTORY_FLASH_START - 4 * 1024;
+pub const MAX_ALIASES:usize = 16;
+/// and the 4K before them the aliases
+pub const ALIAS_FLASH_START:u32 = SCRIPT_FLASH_START - 4 * 1024;
 
 // pub static CMD_LIST:[&str;TOTAL_CMDS] = [
     // "help",
     // "pwmin"
 // ];
 
-pub type SevenShell = AShell<FnAutocomplete<MAX_CMD_LEN>, LRUHistory<MAX_CMD_LEN, TOTAL_CMDS>, MAX_CMD_LEN, LOG_BUFF_SIZE>;
+pub type SevenFlash = Flash<'static, FLASH, FLASH_SIZE>;
+pub type SevenHistory = FlashHistory<SharedFlash, MAX_CMD_LEN, TOTAL_CMDS>;
+pub type SevenScripts = FlashScripts<ThreadModeRawMutex, SharedFlash, MAX_SCRIPTS, SCRIPT_LEN>;
+pub type SevenAliases = FlashScripts<ThreadModeRawMutex, SharedFlash, MAX_ALIASES, ALIAS_LEN>;
+pub type RamHistory = LRUHistory<MAX_CMD_LEN, TOTAL_CMDS>;
+pub type ShellOutput = &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>;
+pub type SevenShell<H = SevenHistory> = AShell<ShellEnvAutocomplete, H, ShellOutput, MAX_CMD_LEN>;
+pub type SevenJobs = Jobs<ThreadModeRawMutex, MAX_JOBS, MAX_CMD_LEN>;
 
-pub static mut SHELL_ENV: SevenShellEnv<TOTAL_CMDS> = SevenShellEnv::new();
+/// Output of the shell on the UART, drained by `mylog::log_task`
+pub static UART_SHELL_PIPE: Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE> = Pipe::new();
+/// Output of the shell on USB CDC-ACM, drained by `usb_shell`
+pub static USB_SHELL_PIPE: Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE> = Pipe::new();
+
+/// `cmd &` on each shell, the jobs print like log lines
+pub static UART_JOBS: SevenJobs = Jobs::new();
+pub static USB_JOBS: SevenJobs = Jobs::new();
+
+/// The commands, shared by the shells and the job runners: each runs them
+/// through its own `&SHELL_ENV`.
+pub static SHELL_ENV: SevenShellEnv<TOTAL_CMDS> = SevenShellEnv::new();
 
 // pub struct SevenShellEnv<'a, const N: usize> {
     // env_map: FnvIndexMap<&'static str, &'a mut dyn Environment, N>,
     // cmd_names: [&'static str; N],
 // }
 
-type CmdHandler = fn(&str, &str) -> ShellResult;
 // type CmdHandler = impl Fn(&str, &str) -> ShellResult;
 pub struct SevenShellEnv<const N:usize> 
 {
-    inner: Mutex<ThreadModeRawMutex, RefCell<FnvIndexMap<&'static str, CmdHandler, N>>>
+    inner: Mutex<ThreadModeRawMutex, RefCell<Registry<N>>>
 }
 
 unsafe impl<const N:usize> Sync for  SevenShellEnv<N> {}
@@ -52,100 +99,59 @@ impl<const N: usize> SevenShellEnv<N>
 {
     pub const fn new() -> Self {
         Self {
-            inner: Mutex::new(RefCell::new(FnvIndexMap::new())),
+            inner: Mutex::new(RefCell::new(Registry::new())),
         }
     }
 
-    // pub async fn lock(&mut self) -> MutexGuard<ThreadModeRawMutex, RefCell<FnvIndexMap<&'static str, CmdHandler, N>>> {
-    pub fn lock<R>(&self, f: impl FnOnce(&RefCell<FnvIndexMap<&'static str, CmdHandler, N>>)->R) -> R {
-        // self.inner.lock().await
+    pub fn lock<R>(&self, f: impl FnOnce(&RefCell<Registry<N>>)->R) -> R {
         self.inner.lock(f)
     }
 
-    pub fn register_cmd(&mut self, cmd_name: &'static str, handler: CmdHandler){
-        // let env_map = self.inner.lock().await;
-        // let env_map = &mut *env_map.borrow_mut();
-        // env_map.insert(cmd_name, handler);
-        self.inner.lock(|map| {
-            let mut map = map.borrow_mut();
-            map.insert(cmd_name, handler);
+    pub fn register_cmd(&self, cmd: &'static Command){
+        self.inner.lock(|registry| {
+            let mut registry = registry.borrow_mut();
+            if registry.register(cmd).is_err() {
+                log::info!("too many cmds, {} not registered", cmd.name);
+            }
         })
     }
 
-    pub fn unregister_cmd(&mut self, cmd_name: &'static str) {
-        // let env_map = self.inner.lock().await;
-        // let env_map = &mut *env_map.borrow_mut();
-        // env_map.remove(cmd_name);
-        self.inner.lock(|map| {
-            let mut map = map.borrow_mut();
-            map.remove(cmd_name);
+    pub fn unregister_cmd(&self, cmd_name: &'static str) {
+        self.inner.lock(|registry| {
+            let mut registry = registry.borrow_mut();
+            registry.unregister(cmd_name);
         });
     }
 
 }
 
-pub fn register_shell_cmd(name: &'static str, handler: CmdHandler)
+pub fn register_shell_cmd(cmd: &'static Command)
 {
-    unsafe { SHELL_ENV.register_cmd(name, handler); }
+    SHELL_ENV.register_cmd(cmd);
 }
 
 pub fn unregister_shell_cmd(name: &'static str) {
-    unsafe { SHELL_ENV.unregister_cmd(name); }
+    SHELL_ENV.unregister_cmd(name);
 }
 
-// impl<A, H, const CMD_LEN:usize, const LOG_LEN:usize> Environment<A, H, CMD_LEN, LOG_LEN> for SevenShellEnv 
-// where
-//     // S: AsyncRead + AsyncWrite,
-//     A: Autocomplete<CMD_LEN>,
-//     H: History<CMD_LEN>
-impl<const N:usize> Environment for SevenShellEnv<N>
+//by reference: shells and job runners run commands at once, all state is
+//behind the mutex
+impl<const N:usize> ArgvEnvironment for &SevenShellEnv<N>
 {
 
     async fn command(
         &mut self,
-        // _shell: &mut AShell<A, H, CMD_LEN, LOG_LEN>,
-        cmd: &str,
-        args: &str,
+        argv: &[&str],
+        mut out: &mut dyn CommandOutput,
     ) -> ShellResult 
     {
-        // match cmd {
-        //     "help" => log::info!("help for cmds"),
-        //     "pwmin" => {
-        //         //create pwmin task
-        //         // let mut pins :[u8;8]= [0xFF;8];
-        //         args.split_ascii_whitespace().map(|a| {a.parse::<u32>().unwrap()}).for_each(|pin| {
-        //                 log::info!("create pio task for pin:{}", pin);
-        //         });
-        //         // log::info!("pins:{:?}-{:?}", pins.next(), pins.next());
-        //         // let p = unsafe {Peripherals::steal()};
-        //         // let pio0 = p.PIO0;
-        //         // let pio1 = p.PIO1;
-        //     },
-        //     _ => log::info!("unknown cmd"),
-        // }
-
-        // let map= self.inner.lock().await;
-        // let map = &mut *map.borrow_mut();
-        // if let Some(handler) = map.get_mut(cmd) {
-            // handler.command(cmd, args)
-            // handler(cmd, args)
-        // }
-        // else
-        // {
-            // log::info!("unkonwn cmd");
-            // Err(ashell::ShellError::CommandNotFound)
-        // }
-
-        self.inner.lock(|map| {
-            let map = map.borrow();
-            if let Some(handler) = map.get(cmd) {
-                handler(cmd, args)
-            }
-            else {
-                log::info!("unknown cmd");
-                Err(ashell::ShellError::CommandNotFound)
-            }
-        })
+        //errors are printed by the shell, `cmd: command not found`
+        let task = self.inner.lock(|registry| registry.borrow().dispatch(argv, &mut out))?;
+        //async commands run outside the lock, the shell drops them on Ctrl-C
+        match task {
+            Some(task) => run_task(task, out).await,
+            None => Ok(()),
+        }
     }
 
     async fn control(
@@ -167,40 +173,138 @@ impl<const N:usize> Environment for SevenShellEnv<N>
     }
 }
 
-fn shell_cmd_complete(prefix: &str) -> Option<String<MAX_CMD_LEN>> {
-    if prefix.len() == 0 {
-        return None;
+pub const SLEEP_TASK:u16 = 1;
+
+static SLEEP_CMD: Command = Command {
+    name: "sleep",
+    summary: "wait, Ctrl-C to stop",
+    usage: "sleep <ms>",
+    args: &[Arg {
+        name: "ms",
+        kind: ArgKind::Int { min: 0, max: i32::MAX },
+        required: true,
+        help: "milliseconds",
+        complete: None,
+    }],
+    subcommands: &[],
+    handler: Some(Handler::Async(SLEEP_TASK)),
+};
+
+/// register the commands of the shell itself
+pub fn register_builtin_cmds() {
+    register_shell_cmd(&SLEEP_CMD);
+}
+
+/// Run the async command `task`, see `Handler::Async`
+async fn run_task(task: Task<'_>, out: &mut dyn CommandOutput) -> ShellResult {
+    match task.id {
+        SLEEP_TASK => sleep_cmd(&task.args).await,
+        pwmin_pio::PWMIN_WATCH_TASK => pwmin_pio::pwmin_watch_cmd(&task.args, out).await,
+        _ => Err(ashell::ShellError::CommandNotFound),
     }
+}
+
+async fn sleep_cmd(args: &Args<'_>) -> ShellResult {
+    let ms = args.int("ms").unwrap_or(0) as u64;
+    Timer::after(Duration::from_millis(ms)).await;
+    Ok(())
+}
+
+/// Run the background jobs of `jobs`, their output goes above the prompt of
+/// the shell reading `print`. Spawn `JOB_RUNNERS` of them per shell.
+#[embassy_executor::task(pool_size = 4)]
+pub async fn job_task(jobs: &'static SevenJobs, mut print: &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>) {
+    //waits for room like a command in the foreground
+    let mut out = OutputWriter(&mut print);
+    jobs.run::<MAX_ARGC>(&mut &SHELL_ENV, &mut out).await
+}
+
+/// Input of the uart shell: bytes from the uart, log lines to print.
+pub struct UartSource {
+    pub rx: BufferedUartRx<'static, UART0>,
+}
 
-    // let env = SHELL_ENV.lock().await;
-    // let env = env.borrow();
-
-    // for cmd_name in env.keys() {
-    //     if cmd_name.starts_with(prefix) {
-    //         let (_, suffix) = cmd_name.split_at(prefix.len());
-    //         return String::from_str(suffix).ok();
-    //     }
-    // }
-    // None
-    unsafe {
-        SHELL_ENV.lock(|map| {
-            let map = map.borrow();
-            for cmd_name in map.keys() {
-                if cmd_name.starts_with(prefix) {
-                    let (_, suffix) = cmd_name.split_at(prefix.len());
-                    return String::from_str(suffix).ok();
-                }
+impl Source for UartSource {
+    async fn next(&mut self, buf: &mut [u8]) -> Event {
+        let half = buf.len() / 2;
+        let (rx_buf, log_buf) = buf.split_at_mut(half);
+        match select(self.rx.read(rx_buf), LOG_PIPE.read(log_buf)).await {
+            Either::First(n) => Event::Input(n.unwrap_or(0)),
+            Either::Second(n) => {
+                buf.copy_within(half..half + n, 0);
+                Event::Print(n)
             }
-            None
+        }
+    }
+}
+
+/// Completes from the commands registered in `SHELL_ENV`.
+pub struct ShellEnvAutocomplete;
+
+impl Autocomplete<MAX_CMD_LEN> for ShellEnvAutocomplete {
+    fn suggest(&self, prefix: &str) -> Option<String<MAX_CMD_LEN>> {
+        SHELL_ENV.lock(|registry| {
+            let registry = registry.borrow();
+            Autocomplete::<MAX_CMD_LEN>::suggest(&*registry, prefix)
         })
     }
+
+    fn candidates(&self, line: &str, add: &mut dyn FnMut(&str)) {
+        SHELL_ENV.lock(|registry| registry.borrow().complete(line, add))
+    }
+}
+
+/// The flash, shared by the history, the scripts and the aliases.
+#[derive(Clone, Copy)]
+pub struct SharedFlash(pub &'static Mutex<ThreadModeRawMutex, RefCell<SevenFlash>>);
+
+impl ErrorType for SharedFlash {
+    type Error = embassy_rp::flash::Error;
+}
+
+impl ReadNorFlash for SharedFlash {
+    const READ_SIZE: usize = <SevenFlash as ReadNorFlash>::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().read(offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for SharedFlash {
+    const WRITE_SIZE: usize = <SevenFlash as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <SevenFlash as NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().erase(from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().write(offset, bytes))
+    }
+}
+
+pub fn create_history(flash: SharedFlash) -> SevenHistory {
+    FlashHistory::new(flash, HISTORY_FLASH_START, FLASH_SIZE as u32)
+}
+
+pub fn create_scripts(flash: SharedFlash) -> SevenScripts {
+    FlashScripts::new(flash, SCRIPT_FLASH_START)
+}
+
+pub fn create_aliases(flash: SharedFlash) -> SevenAliases {
+    FlashScripts::new(flash, ALIAS_FLASH_START)
 }
 
-pub async fn create_shell() -> SevenShell {
+/// A shell writing to `out`, one per transport.
+pub async fn create_shell<H: History<MAX_CMD_LEN>>(history: H, out: &'static Pipe<ThreadModeRawMutex, LOG_BUFF_SIZE>) -> SevenShell<H> {
     SevenShell::new(
-        FnAutocomplete(shell_cmd_complete),
-        LRUHistory::default(),
-        &LOG_PIPE
+        ShellEnvAutocomplete,
+        history,
+        out
     ).await
 }
 
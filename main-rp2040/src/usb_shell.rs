@@ -1,20 +1,24 @@
 
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select3, Either3};
 use embassy_futures::join::join;
 // use embassy_sync::pipe::Pipe;
 use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
 use embassy_usb::driver::Driver;
 use embassy_usb::{Builder, Config};
-use ashell::{autocomplete::{StaticAutocomplete}, history::{LRUHistory}, AShell};
-use embedded_hal_1::i2c::SevenBitAddress;
-use crate::shell::{SHELL_ENV, create_shell, SevenShell};
-
-use crate::mylog::LOG_PIPE;
+use ashell::{Event, Source};
+use crate::shell::{SHELL_ENV, MAX_ARGC, USB_JOBS, USB_SHELL_PIPE, create_shell, RamHistory, SevenAliases, SevenScripts, SevenShell};
+use crate::mylog::USB_LOG_PIPE;
+#[cfg(scpi)]
+use crate::scpi::{self, SevenInstrument, SevenScpi};
+#[cfg(not(scpi))]
+use crate::rpc::{RpcSource, USB_RPC};
 // use log::{Metadata, Record};
 // use crate::shell::CmdParser;
 
 // type CS = embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 
+const MAX_PACKET_SIZE: u8 = 64;
+
 /// The logger state containing buffers that must live as long as the USB peripheral.
 pub struct LoggerState<'d> {
     state: State<'d>,
@@ -50,7 +54,9 @@ impl UsbShell {
     // }
 
     /// Run the USB logger using the state and USB driver. Never returns.
-    pub async fn run<'d, D>(&'d self, state: &'d mut LoggerState<'d>, driver: D) -> !
+    /// The scripts and aliases are the ones of the uart shell. Built with
+    /// `--cfg scpi` the port speaks SCPI instead of the shell.
+    pub async fn run<'d, D>(&'d self, state: &'d mut LoggerState<'d>, driver: D, scripts: &'static SevenScripts, aliases: &'static SevenAliases) -> !
     where
         D: Driver<'d>,
         Self: 'd,
@@ -59,10 +65,21 @@ impl UsbShell {
         // let history = LRUHistory::default();
         // let completer = StaticAutocomplete(CMD_LIST);
         // let mut shell:SevenShell = AShell::new(completer, history, &LOG_PIPE).await;
-        let mut shell: SevenShell = create_shell().await;
+        //the flash history belongs to the uart shell
+        #[cfg(not(scpi))]
+        let mut shell: SevenShell<RamHistory> = create_shell(RamHistory::default(), &USB_SHELL_PIPE).await;
+        #[cfg(not(scpi))]
+        {
+            shell.set_jobs(&USB_JOBS);
+            shell.set_scripts(scripts);
+            shell.set_aliases(aliases);
+        }
+        #[cfg(scpi)]
+        let _ = (scripts, aliases);
+        #[cfg(scpi)]
+        let mut scpi = SevenScpi::new(SevenInstrument);
 
 
-        const MAX_PACKET_SIZE: u8 = 64;
         let mut config = Config::new(0xc0de, 0xcafe);
         config.manufacturer = Some("Seven");
         config.product = Some("SevenTestHW");
@@ -104,29 +121,52 @@ impl UsbShell {
         loop {
             let run_fut = device.run();
             let shell_fut = async  {
-                let mut log_buf: [u8; MAX_PACKET_SIZE as usize] = [0; MAX_PACKET_SIZE as usize];
-                let mut recv_buf: [u8; MAX_PACKET_SIZE as usize] = [0; MAX_PACKET_SIZE as usize];
                 // let mut env = SevenShellEnv::default();
-                loop {
-                    class.wait_connection().await;
-                    // let len = self.buffer.read(&mut log_buf[..]).await;
-                    // let _ = class.write_packet(&log_buf[..len]).await;
-
-                    match select(LOG_PIPE.read(&mut log_buf[..]), class.read_packet(&mut recv_buf[..])).await {
-                        Either::First(n) => {
-                            let _ = class.write_packet(&log_buf[..n]).await;
-                        },
-                        Either::Second(Ok(n)) => {
-                            //process cmd
-                            for byte in &recv_buf[..n] {
-                                unsafe {shell.feed(&mut SHELL_ENV, *byte).await;}
-                            }
-                        },
-                        _ => {},
-                    }
-                }
+                let mut source = UsbSource { class: &mut class, connected: false };
+                #[cfg(not(scpi))]
+                let mut source = RpcSource::new(source, &USB_RPC);
+                #[cfg(not(scpi))]
+                shell.run::<MAX_ARGC>(&mut &SHELL_ENV, &mut source).await;
+                #[cfg(scpi)]
+                scpi::serve(&mut scpi, &mut source, &USB_SHELL_PIPE).await;
             };
             join(run_fut, shell_fut).await;
         }
     }
 }
+
+/// Input of the usb shell. The shell output is sent from here too, while
+/// the shell waits for input.
+struct UsbSource<'a, 'd, D: Driver<'d>> {
+    class: &'a mut CdcAcmClass<'d, D>,
+    connected: bool,
+}
+
+impl<'a, 'd, D: Driver<'d>> Source for UsbSource<'a, 'd, D> {
+    async fn next(&mut self, buf: &mut [u8]) -> Event {
+        let mut out_buf = [0u8; MAX_PACKET_SIZE as usize];
+        let half = buf.len() / 2;
+        loop {
+            if !self.connected {
+                self.class.wait_connection().await;
+                self.connected = true;
+            }
+            let (recv_buf, line_buf) = buf.split_at_mut(half);
+            match select3(
+                USB_SHELL_PIPE.read(&mut out_buf[..]),
+                self.class.read_packet(recv_buf),
+                USB_LOG_PIPE.read(line_buf),
+            ).await {
+                Either3::First(n) => {
+                    let _ = self.class.write_packet(&out_buf[..n]).await;
+                },
+                Either3::Second(Ok(n)) => return Event::Input(n),
+                Either3::Second(Err(_)) => self.connected = false,
+                Either3::Third(n) => {
+                    buf.copy_within(half..half + n, 0);
+                    return Event::Print(n);
+                },
+            }
+        }
+    }
+}
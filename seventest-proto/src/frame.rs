@@ -0,0 +1,135 @@
+//! Frames on a link shared with text.
+//!
+//! ```text
+//! | 0x00 | COBS(postcard message, crc16 of it (le)) | 0x00 |
+//! ```
+//!
+//! COBS leaves no 0 in a frame, and the text shell never sends or reads 0
+//! bytes, so text goes between frames. A reader takes what comes after an
+//! odd 0 as a frame, two 0 in a row start over. The CRC is
+//! CRC-16/IBM-SDLC.
+
+use crc::{Crc, CRC_16_IBM_SDLC};
+use serde::{Deserialize, Serialize};
+
+/// Longest frame, both 0 included.
+pub const MAX_FRAME_LEN: usize = 64;
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_SDLC);
+/// message and CRC, what COBS of it fits a frame
+const MAX_DATA_LEN: usize = MAX_FRAME_LEN - 2 - (MAX_FRAME_LEN - 2) / 254 - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    TooLong,
+    /// not COBS, or the CRC does not match
+    Corrupt,
+    /// the CRC matches, the message is not one of this version
+    Decode,
+}
+
+/// Write `msg` as a frame into `buf`, returns its length.
+pub fn encode<T: Serialize>(msg: &T, buf: &mut [u8]) -> Result<usize, FrameError> {
+    let mut data = [0u8; MAX_DATA_LEN];
+    let len = postcard::to_slice(msg, &mut data[..MAX_DATA_LEN - 2])
+        .map_err(|_| FrameError::TooLong)?
+        .len();
+    let crc = CRC.checksum(&data[..len]);
+    data[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+    let len = len + 2;
+    if buf.len() < cobs::max_encoding_length(len) + 2 {
+        return Err(FrameError::TooLong);
+    }
+    buf[0] = 0;
+    let n = cobs::encode(&data[..len], &mut buf[1..]);
+    buf[n + 1] = 0;
+    Ok(n + 2)
+}
+
+/// Read the message of `frame`, the bytes between the two 0. The frame is
+/// decoded in place.
+pub fn decode<'a, T: Deserialize<'a>>(frame: &'a mut [u8]) -> Result<T, FrameError> {
+    let len = cobs::decode_in_place(frame).map_err(|_| FrameError::Corrupt)?;
+    if len < 2 {
+        return Err(FrameError::Corrupt);
+    }
+    let (data, crc) = frame[..len].split_at(len - 2);
+    if CRC.checksum(data).to_le_bytes() != crc {
+        return Err(FrameError::Corrupt);
+    }
+    postcard::from_bytes(data).map_err(|_| FrameError::Decode)
+}
+
+/// What a byte read from the link is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Byte {
+    /// text, outside frames
+    Text(u8),
+    /// part of a frame
+    Frame,
+    /// the end of a frame, it is in [`FrameReader::frame`]
+    End,
+    /// the end of a frame too long for `N`
+    Overrun,
+}
+
+/// Splits what is read from a link into text and frames of up to `N`
+/// bytes.
+pub struct FrameReader<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    in_frame: bool,
+    overrun: bool,
+}
+
+impl<const N: usize> FrameReader<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            in_frame: false,
+            overrun: false,
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Byte {
+        match (self.in_frame, byte) {
+            (false, 0) => {
+                self.in_frame = true;
+                self.len = 0;
+                self.overrun = false;
+                Byte::Frame
+            }
+            (false, byte) => Byte::Text(byte),
+            //`0 0`, the first 0 ended a frame lost on the way
+            (true, 0) if self.len == 0 && !self.overrun => Byte::Frame,
+            (true, 0) => {
+                self.in_frame = false;
+                match self.overrun {
+                    true => Byte::Overrun,
+                    false => Byte::End,
+                }
+            }
+            (true, byte) => {
+                match self.buf.get_mut(self.len) {
+                    Some(slot) => {
+                        *slot = byte;
+                        self.len += 1;
+                    }
+                    None => self.overrun = true,
+                }
+                Byte::Frame
+            }
+        }
+    }
+
+    /// The frame ended last, for [`decode`].
+    pub fn frame(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> Default for FrameReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
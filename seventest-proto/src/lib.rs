@@ -0,0 +1,101 @@
+//! The binary protocol between SevenTestHW and host tools, next to the text
+//! shell on the same UART or USB link.
+//!
+//! The host sends [`Request`]s, the device answers each with a
+//! [`Message::Response`] of the same `id`, and sends [`Message::Event`]s
+//! for what the host subscribed to. Messages are postcard encoded and go in
+//! frames, see [`frame`].
+//!
+//! A session starts with [`Command::Hello`], the device answers with its
+//! [`PROTOCOL_VERSION`]. New commands, replies, errors and events are only
+//! ever added at the end of their enum, so an old peer reads what it knows;
+//! what it does not know it answers with [`Error::BadRequest`]. Any other
+//! change bumps the version.
+#![no_std]
+
+pub mod frame;
+pub mod measure;
+pub mod report;
+pub mod stats;
+
+use serde::{Deserialize, Serialize};
+
+pub use frame::{decode, encode, FrameError, FrameReader, MAX_FRAME_LEN};
+pub use measure::{Fixed, Measurement, Timebase, PWMIN_TIMEBASE};
+pub use report::{report, Report, ReportPolicy, Threshold, DEFAULT_REPORT_POLICY, DEFAULT_THRESHOLD};
+pub use stats::{Histogram, PwmStats, Stats};
+
+/// Answered by [`Command::Hello`]. Since 2 channels are GPIO numbers, in 1
+/// they were state machines.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Host to device.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request {
+    /// echoed in the response, chosen by the host
+    pub id: u16,
+    pub command: Command,
+}
+
+/// Channels are GPIO numbers, in bit masks GPIO 0 is bit 0.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// [`Reply::Hello`]
+    Hello,
+    PwmInStart { channels: u32 },
+    PwmInStop { channels: u32 },
+    /// [`Reply::PwmInStatus`]
+    PwmInStatus,
+    /// Ticks high or low periods may change by before a capture is sent,
+    /// for every channel: it replaces the thresholds set on the shell with
+    /// `pwmin config`, in ppm too.
+    PwmInConfig { threshold: u32 },
+    /// Send [`Event::PwmIn`] for `channels` from now on, 0 for none.
+    Subscribe { channels: u32 },
+}
+
+/// Device to host.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    Response { id: u16, result: Result<Reply, Error> },
+    Event(Event),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// channels are below `channels`
+    Hello { version: u16, channels: u8 },
+    Done,
+    /// `threshold` is the one last set with [`Command::PwmInConfig`]
+    PwmInStatus { running: u32, threshold: u32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// the request did not decode, its `id` is 0
+    BadRequest,
+    NoSuchChannel,
+    /// already started
+    Busy,
+    /// all state machines capture already
+    NoFreeChannel,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    PwmIn(PwmCapture),
+}
+
+/// A capture of a pwmin channel, as `pwmin watch` prints it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PwmCapture {
+    pub channel: u8,
+    /// ticks per second
+    pub clk: u32,
+    pub high_ticks: u32,
+    pub low_ticks: u32,
+    /// periods seen like this one
+    pub count: u32,
+    /// µs since boot
+    pub time: u64,
+}
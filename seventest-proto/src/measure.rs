@@ -0,0 +1,146 @@
+//! PWM captures in real units. Ticks are state machine cycles; all math is
+//! integer, so it is the same on the board, which has no FPU, and on the
+//! host.
+
+use core::fmt;
+
+use crate::PwmCapture;
+
+/// How the pwmin state machines count time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timebase {
+    /// system clock, Hz
+    pub sys_clk: u32,
+    /// state machine clock divider, 16.8 fixed point: 256 is 1
+    pub clkdiv: u32,
+    /// state machine cycles per count of the PIO loops
+    pub cycles_per_count: u32,
+}
+
+/// The timebase of `PwmIn.pio` at 125 MHz: both loops take 2 cycles.
+pub const PWMIN_TIMEBASE: Timebase = Timebase {
+    sys_clk: 125_000_000,
+    clkdiv: 1 << 8,
+    cycles_per_count: 2,
+};
+
+impl Timebase {
+    /// State machine cycles, ticks, of `count` loops.
+    pub const fn ticks(&self, count: u32) -> u32 {
+        count.saturating_mul(self.cycles_per_count)
+    }
+
+    /// Ticks per second, rounded.
+    pub const fn tick_hz(&self) -> u32 {
+        div_round(self.sys_clk as u128 * 256, self.clkdiv as u128) as u32
+    }
+
+    /// `ticks` in ns, rounded.
+    pub const fn ns(&self, ticks: u64) -> u64 {
+        div_round(ticks as u128 * self.clkdiv as u128 * 1_000_000_000, self.sys_clk as u128 * 256) as u64
+    }
+}
+
+/// A capture in real units, see [`Measurement::new`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Measurement {
+    /// mHz, 0 before the first period
+    pub frequency_mhz: u64,
+    pub period_ns: u64,
+    /// the pulse width
+    pub high_ns: u64,
+    pub low_ns: u64,
+    /// high time, parts per million of the period
+    pub duty_ppm: u32,
+}
+
+impl Measurement {
+    /// The measurement of a period `high_ticks` high and `low_ticks` low.
+    pub fn new(timebase: &Timebase, high_ticks: u32, low_ticks: u32) -> Self {
+        let period_ticks = high_ticks as u64 + low_ticks as u64;
+        if period_ticks == 0 {
+            return Self::default();
+        }
+        let tick_mhz = timebase.sys_clk as u128 * 256 * 1000;
+        Self {
+            frequency_mhz: div_round(tick_mhz, timebase.clkdiv as u128 * period_ticks as u128) as u64,
+            period_ns: timebase.ns(period_ticks),
+            high_ns: timebase.ns(high_ticks as u64),
+            low_ns: timebase.ns(low_ticks as u64),
+            duty_ppm: div_round(high_ticks as u128 * 1_000_000, period_ticks as u128) as u32,
+        }
+    }
+
+    /// Frequency in Hz, 3 decimals.
+    pub fn frequency(&self) -> Fixed {
+        Fixed(self.frequency_mhz, 3)
+    }
+
+    /// Period in µs, 3 decimals.
+    pub fn period_us(&self) -> Fixed {
+        Fixed(self.period_ns, 3)
+    }
+
+    pub fn high_us(&self) -> Fixed {
+        Fixed(self.high_ns, 3)
+    }
+
+    pub fn low_us(&self) -> Fixed {
+        Fixed(self.low_ns, 3)
+    }
+
+    /// Period in seconds, 9 decimals.
+    pub fn period_s(&self) -> Fixed {
+        Fixed(self.period_ns, 9)
+    }
+
+    /// Duty cycle in percent, 4 decimals.
+    pub fn duty(&self) -> Fixed {
+        Fixed(self.duty_ppm as u64, 4)
+    }
+}
+
+impl fmt::Display for Measurement {
+    /// `1000.000 Hz, high 500.000 us, low 500.000 us, duty 50.0000 %`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz, high {} us, low {} us, duty {} %",
+            self.frequency(),
+            self.high_us(),
+            self.low_us(),
+            self.duty()
+        )
+    }
+}
+
+impl PwmCapture {
+    /// The capture in real units. Its ticks are cycles of a `clk` Hz clock.
+    pub fn measure(&self) -> Measurement {
+        let timebase = Timebase {
+            sys_clk: self.clk,
+            clkdiv: 1 << 8,
+            cycles_per_count: 1,
+        };
+        Measurement::new(&timebase, self.high_ticks, self.low_ticks)
+    }
+}
+
+/// A fixed point number: `.0` with its last `.1` digits decimals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixed(pub u64, pub u32);
+
+impl fmt::Display for Fixed {
+    /// `Fixed(1500, 3)` is `1.500`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u64.pow(self.1);
+        match self.1 {
+            0 => write!(f, "{}", self.0),
+            digits => write!(f, "{}.{:0width$}", self.0 / scale, self.0 % scale, width = digits as usize),
+        }
+    }
+}
+
+const fn div_round(n: u128, d: u128) -> u128 {
+    (n + d / 2) / d
+}
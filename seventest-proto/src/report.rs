@@ -0,0 +1,107 @@
+//! When a pwmin channel sends a capture. The board samples every period,
+//! sending them all would flood the captures channel for fast signals, so
+//! each channel has a [`ReportPolicy`] and [`report`] decides, sample by
+//! sample.
+
+use core::fmt;
+
+/// How much a period has to change to be a new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Threshold {
+    /// high or low time changes by more than this many ticks
+    Ticks(u32),
+    /// high or low time changes by more than this, parts per million of
+    /// the period
+    Ppm(u32),
+}
+
+impl Threshold {
+    /// Whether `high`/`low` ticks are a new period after `last`.
+    pub fn exceeded(&self, last: (u32, u32), high: u32, low: u32) -> bool {
+        let diff = last.0.abs_diff(high).max(last.1.abs_diff(low));
+        match *self {
+            Threshold::Ticks(ticks) => diff > ticks,
+            Threshold::Ppm(ppm) => diff as u64 * 1_000_000 > ppm as u64 * (last.0 as u64 + last.1 as u64),
+        }
+    }
+}
+
+/// When a channel sends captures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportPolicy {
+    pub threshold: Threshold,
+    /// send the same period again every this many samples, 0 never
+    pub every: u32,
+    /// ms between captures at least, changes wait too
+    pub min_interval_ms: u32,
+    /// only send new periods, `every` is not used
+    pub change_only: bool,
+}
+
+/// Ticks of the default threshold.
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// 10 ticks, every 100 samples, as fast as that.
+pub const DEFAULT_REPORT_POLICY: ReportPolicy = ReportPolicy {
+    threshold: Threshold::Ticks(DEFAULT_THRESHOLD),
+    every: 100,
+    min_interval_ms: 0,
+    change_only: false,
+};
+
+impl Default for ReportPolicy {
+    fn default() -> Self {
+        DEFAULT_REPORT_POLICY
+    }
+}
+
+impl fmt::Display for ReportPolicy {
+    /// `threshold 10 ticks, every 100, min interval 0 ms, change only off`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.threshold {
+            Threshold::Ticks(ticks) => write!(f, "threshold {} ticks", ticks)?,
+            Threshold::Ppm(ppm) => write!(f, "threshold {} ppm", ppm)?,
+        }
+        write!(
+            f,
+            ", every {}, min interval {} ms, change only {}",
+            self.every,
+            self.min_interval_ms,
+            if self.change_only { "on" } else { "off" }
+        )
+    }
+}
+
+/// What to do with a sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Report {
+    /// like the last capture, count it
+    Count,
+    /// like the last capture, count it and send the capture again
+    Repeat,
+    /// a new period, send it
+    Change,
+    /// a new period too soon, drop it
+    Wait,
+}
+
+/// What to do with a sample `high`/`low` ticks long. `last` is the period
+/// of the last capture sent, `None` before the first one; `count` the
+/// samples counted since, without this one; `elapsed_ms` the time since it
+/// was sent.
+pub fn report(policy: &ReportPolicy, last: Option<(u32, u32)>, high: u32, low: u32, count: u32, elapsed_ms: u64) -> Report {
+    let last = match last {
+        Some(last) => last,
+        None => return Report::Change,
+    };
+    let due = elapsed_ms >= policy.min_interval_ms as u64;
+    if policy.threshold.exceeded(last, high, low) {
+        return if due { Report::Change } else { Report::Wait };
+    }
+    let repeat = !policy.change_only && policy.every != 0 && count.saturating_add(1) >= policy.every;
+    if repeat && due {
+        Report::Repeat
+    } else {
+        Report::Count
+    }
+}
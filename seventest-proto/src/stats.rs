@@ -0,0 +1,262 @@
+//! Running statistics of pwmin periods, in integers, for the board: every
+//! period goes in, nothing is kept but the sums.
+
+use core::fmt;
+
+use crate::measure::{Fixed, Timebase};
+
+/// Bins of the jitter histogram of a channel.
+pub const HISTOGRAM_BINS: usize = 16;
+/// Default bin width, ticks.
+pub const DEFAULT_BIN_WIDTH: u32 = 8;
+/// mean and deviation are kept in millionths, so the rounding of each step
+/// does not show in thousandths
+const SCALE: i64 = 1_000_000;
+
+/// Count, extremes, mean and standard deviation of a series, with
+/// Welford's algorithm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    n: u32,
+    min: u32,
+    max: u32,
+    /// millionths
+    mean: i64,
+    /// sum of squared differences from the mean, in millionths squared
+    m2: i128,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            n: 0,
+            min: 0,
+            max: 0,
+            mean: 0,
+            m2: 0,
+        }
+    }
+
+    pub fn add(&mut self, x: u32) {
+        if self.n == u32::MAX {
+            return;
+        }
+        self.n += 1;
+        if self.n == 1 {
+            self.min = x;
+            self.max = x;
+        }
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        let x = x as i64 * SCALE;
+        let delta = x - self.mean;
+        self.mean += div_round(delta, self.n as i64);
+        self.m2 = self.m2.saturating_add(delta as i128 * (x - self.mean) as i128);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.n
+    }
+
+    /// `None` before the first value
+    pub fn min(&self) -> Option<u32> {
+        (self.n > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        (self.n > 0).then_some(self.max)
+    }
+
+    /// thousandths
+    pub fn mean_milli(&self) -> u64 {
+        div_round(self.mean.max(0), SCALE / 1000) as u64
+    }
+
+    /// Population standard deviation, thousandths.
+    pub fn stddev_milli(&self) -> u64 {
+        match self.n {
+            0 => 0,
+            n => {
+                let micro = isqrt(self.m2.max(0) as u128 / n as u128) as i64;
+                div_round(micro, SCALE / 1000) as u64
+            }
+        }
+    }
+}
+
+/// Counts of values by how far they are from the first one, in bins of
+/// `width`. The bins are centered on the first value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Histogram<const N: usize> {
+    center: Option<u32>,
+    width: u32,
+    bins: [u32; N],
+    below: u32,
+    above: u32,
+}
+
+impl<const N: usize> Histogram<N> {
+    /// `width` is at least 1.
+    pub const fn new(width: u32) -> Self {
+        Self {
+            center: None,
+            width: if width == 0 { 1 } else { width },
+            bins: [0; N],
+            below: 0,
+            above: 0,
+        }
+    }
+
+    pub fn add(&mut self, x: u32) {
+        let center = *self.center.get_or_insert(x);
+        let bin = (x as i64 - center as i64).div_euclid(self.width as i64) + (N / 2) as i64;
+        let count = match bin {
+            bin if bin < 0 => &mut self.below,
+            bin if bin >= N as i64 => &mut self.above,
+            bin => &mut self.bins[bin as usize],
+        };
+        *count = count.saturating_add(1);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The bins, `(from, count)`: the values in `from..from + width` away
+    /// from the first one.
+    pub fn bins(&self) -> impl Iterator<Item = (i64, u32)> + '_ {
+        let first = -((N / 2) as i64) * self.width as i64;
+        let width = self.width as i64;
+        self.bins.iter().enumerate().map(move |(n, count)| (first + n as i64 * width, *count))
+    }
+
+    /// Values left of the first bin.
+    pub fn below(&self) -> u32 {
+        self.below
+    }
+
+    /// Values right of the last bin.
+    pub fn above(&self) -> u32 {
+        self.above
+    }
+}
+
+/// The statistics of a pwmin channel: period in ticks, duty in ppm, and a
+/// histogram of the period, its jitter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PwmStats {
+    pub period: Stats,
+    pub duty: Stats,
+    pub jitter: Histogram<HISTOGRAM_BINS>,
+}
+
+impl PwmStats {
+    pub const fn new(bin_width: u32) -> Self {
+        Self {
+            period: Stats::new(),
+            duty: Stats::new(),
+            jitter: Histogram::new(bin_width),
+        }
+    }
+
+    /// Add a period `high_ticks` high and `low_ticks` low.
+    pub fn add(&mut self, high_ticks: u32, low_ticks: u32) {
+        let period = high_ticks.saturating_add(low_ticks);
+        if period == 0 {
+            return;
+        }
+        self.period.add(period);
+        self.duty.add(((high_ticks as u64 * 1_000_000 + period as u64 / 2) / period as u64) as u32);
+        self.jitter.add(period);
+    }
+
+    /// Start over, with the same bin width.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.jitter.width());
+    }
+
+    /// The statistics in µs and percent, ticks of `timebase`.
+    pub fn display<'a>(&'a self, timebase: &'a Timebase) -> impl fmt::Display + 'a {
+        StatsDisplay { stats: self, timebase }
+    }
+}
+
+impl Default for PwmStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_BIN_WIDTH)
+    }
+}
+
+struct StatsDisplay<'a> {
+    stats: &'a PwmStats,
+    timebase: &'a Timebase,
+}
+
+impl fmt::Display for StatsDisplay<'_> {
+    /// ```text
+    /// 1000 periods
+    /// period us: min 999.992 max 1000.008 mean 1000.000000 stddev 0.004000
+    /// duty %: min 49.9000 max 50.1000 mean 50.0000 stddev 0.0100
+    /// jitter ns: <-512 0, -512 0, ..., -64 12, 0 976, 64 12, ..., >=512 0
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (stats, timebase) = (self.stats, self.timebase);
+        write!(f, "{} periods", stats.period.count())?;
+        if stats.period.count() == 0 {
+            return Ok(());
+        }
+        //ns of thousandths of a tick are ps
+        let us = |ticks: u32| Fixed(timebase.ns(ticks as u64), 3);
+        let period = &stats.period;
+        write!(
+            f,
+            "\r\nperiod us: min {} max {} mean {} stddev {}",
+            us(period.min),
+            us(period.max),
+            Fixed(timebase.ns(period.mean_milli()), 6),
+            Fixed(timebase.ns(period.stddev_milli()), 6)
+        )?;
+        let duty = &stats.duty;
+        write!(
+            f,
+            "\r\nduty %: min {} max {} mean {} stddev {}",
+            Fixed(duty.min as u64, 4),
+            Fixed(duty.max as u64, 4),
+            Fixed((duty.mean_milli() + 500) / 1000, 4),
+            Fixed((duty.stddev_milli() + 500) / 1000, 4)
+        )?;
+        let ns = |ticks: i64| match ticks < 0 {
+            true => -(timebase.ns(ticks.unsigned_abs()) as i64),
+            false => timebase.ns(ticks as u64) as i64,
+        };
+        let jitter = &stats.jitter;
+        let first = -((HISTOGRAM_BINS / 2) as i64) * jitter.width() as i64;
+        write!(f, "\r\njitter ns: <{} {}", ns(first), jitter.below())?;
+        for (from, count) in jitter.bins() {
+            write!(f, ", {} {}", ns(from), count)?;
+        }
+        write!(f, ", >={} {}", ns(-first), jitter.above())
+    }
+}
+
+fn div_round(n: i64, d: i64) -> i64 {
+    match n < 0 {
+        true => (n - d / 2) / d,
+        false => (n + d / 2) / d,
+    }
+}
+
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    //Newton from above, it only goes down
+    let mut x = 1u128 << ((128 - n.leading_zeros()) / 2 + 1);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
@@ -0,0 +1,99 @@
+use seventest_proto::frame::Byte;
+use seventest_proto::*;
+
+fn capture(channel: u8) -> Message {
+    Message::Event(Event::PwmIn(PwmCapture {
+        channel,
+        clk: 125_000_000,
+        high_ticks: 62_500,
+        low_ticks: 0x1_0000,
+        count: 100,
+        time: u64::MAX,
+    }))
+}
+
+/// The text and the frames of `bytes`, each frame decoded.
+fn read(bytes: &[u8]) -> (Vec<u8>, Vec<Result<Message, FrameError>>) {
+    let mut reader = FrameReader::<MAX_FRAME_LEN>::new();
+    let mut text = Vec::new();
+    let mut messages = Vec::new();
+    for byte in bytes {
+        match reader.feed(*byte) {
+            Byte::Text(byte) => text.push(byte),
+            Byte::Frame => {}
+            Byte::End => messages.push(decode(reader.frame())),
+            Byte::Overrun => messages.push(Err(FrameError::TooLong)),
+        }
+    }
+    (text, messages)
+}
+
+#[test]
+fn messages_survive_a_round_trip() {
+    let request = Request {
+        id: 7,
+        command: Command::PwmInConfig { threshold: 0 },
+    };
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&request, &mut buf).unwrap();
+    assert_eq!((buf[0], buf[len - 1]), (0, 0));
+    assert!(!buf[1..len - 1].contains(&0));
+    assert_eq!(decode::<Request>(&mut buf[1..len - 1]), Ok(request));
+
+    let len = encode(&capture(4), &mut buf).unwrap();
+    assert_eq!(decode::<Message>(&mut buf[1..len - 1]), Ok(capture(4)));
+}
+
+#[test]
+fn frames_go_between_text() {
+    let mut stream = b"#>pwmin start 0\r\n".to_vec();
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let response = Message::Response {
+        id: 1,
+        result: Ok(Reply::PwmInStatus { running: 1, threshold: 10 }),
+    };
+    for msg in [response, capture(0)] {
+        let len = encode(&msg, &mut buf).unwrap();
+        stream.extend_from_slice(&buf[..len]);
+    }
+    stream.extend_from_slice(b"[pwmin] 0 start success");
+    let (text, messages) = read(&stream);
+    assert_eq!(text, b"#>pwmin start 0\r\n[pwmin] 0 start success");
+    assert_eq!(messages, [Ok(response), Ok(capture(0))]);
+}
+
+#[test]
+fn broken_frames_are_rejected() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&capture(1), &mut buf).unwrap();
+
+    let mut flipped = buf[..len].to_vec();
+    flipped[len / 2] ^= 0x40;
+    assert_eq!(read(&flipped).1, [Err(FrameError::Corrupt)]);
+
+    // with the end of a frame lost the next one is read as text, the one
+    // after it is found again
+    let mut stream = buf[..len - 1].to_vec();
+    for channel in [2, 3] {
+        let len = encode(&capture(channel), &mut buf).unwrap();
+        stream.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(read(&stream).1, [Ok(capture(1)), Ok(capture(3))]);
+    let len = encode(&capture(1), &mut buf).unwrap();
+
+    let mut long = vec![0];
+    long.extend_from_slice(&[1; MAX_FRAME_LEN + 1]);
+    long.push(0);
+    long.extend_from_slice(&buf[..len]);
+    assert_eq!(read(&long).1, [Err(FrameError::TooLong), Ok(capture(1))]);
+
+    // a message this version does not know
+    let mut data = [9u8, 0, 0];
+    let crc = crc::Crc::<u16>::new(&crc::CRC_16_IBM_SDLC).checksum(&data[..1]);
+    data[1..].copy_from_slice(&crc.to_le_bytes());
+    let mut frame = [0u8; 8];
+    let n = cobs::encode(&data, &mut frame);
+    assert_eq!(decode::<Message>(&mut frame[..n]), Err(FrameError::Decode));
+
+    assert_eq!(encode(&capture(0), &mut [0u8; 8]), Err(FrameError::TooLong));
+}
@@ -0,0 +1,73 @@
+use seventest_proto::*;
+
+#[test]
+fn ticks_and_time() {
+    assert_eq!(PWMIN_TIMEBASE.tick_hz(), 125_000_000);
+    assert_eq!(PWMIN_TIMEBASE.ticks(250), 500);
+    assert_eq!(PWMIN_TIMEBASE.ticks(u32::MAX), u32::MAX);
+    assert_eq!(PWMIN_TIMEBASE.ns(1), 8);
+    assert_eq!(PWMIN_TIMEBASE.ns(u32::MAX as u64 * 2), 68_719_476_720);
+
+    // divided by 2.5: 50 MHz
+    let slow = Timebase {
+        clkdiv: 640,
+        ..PWMIN_TIMEBASE
+    };
+    assert_eq!(slow.tick_hz(), 50_000_000);
+    assert_eq!(slow.ns(3), 60);
+    // divided by 3: 41.666 MHz, 24 ns a tick
+    let odd = Timebase {
+        clkdiv: 768,
+        ..PWMIN_TIMEBASE
+    };
+    assert_eq!(odd.tick_hz(), 41_666_667);
+    assert_eq!(odd.ns(1), 24);
+    assert_eq!(odd.ns(1000), 24_000);
+}
+
+#[test]
+fn measurements() {
+    let khz = Measurement::new(&PWMIN_TIMEBASE, 62_500, 62_500);
+    assert_eq!(
+        khz,
+        Measurement {
+            frequency_mhz: 1_000_000,
+            period_ns: 1_000_000,
+            high_ns: 500_000,
+            low_ns: 500_000,
+            duty_ppm: 500_000,
+        }
+    );
+    assert_eq!(khz.to_string(), "1000.000 Hz, high 500.000 us, low 500.000 us, duty 50.0000 %");
+    assert_eq!(khz.period_s().to_string(), "0.001000000");
+
+    // the fastest the loops can see, rounded
+    let fast = Measurement::new(&PWMIN_TIMEBASE, 2, 4);
+    assert_eq!(fast.frequency().to_string(), "20833333.333");
+    assert_eq!(fast.period_us().to_string(), "0.048");
+    assert_eq!(fast.duty().to_string(), "33.3333");
+
+    // a 0.1 Hz period does not overflow
+    let slow = Measurement::new(&PWMIN_TIMEBASE, 250_000_000, 1_000_000_000);
+    assert_eq!(slow.frequency().to_string(), "0.100");
+    assert_eq!(slow.period_ns, 10_000_000_000);
+
+    assert_eq!(Measurement::new(&PWMIN_TIMEBASE, 0, 0), Measurement::default());
+    assert_eq!(Measurement::new(&PWMIN_TIMEBASE, 100, 0).duty().to_string(), "100.0000");
+}
+
+#[test]
+fn captures_are_measured_with_their_clock() {
+    let capture = PwmCapture {
+        channel: 0,
+        clk: PWMIN_TIMEBASE.tick_hz(),
+        high_ticks: 1250,
+        low_ticks: 3750,
+        count: 1,
+        time: 0,
+    };
+    assert_eq!(capture.measure(), Measurement::new(&PWMIN_TIMEBASE, 1250, 3750));
+    assert_eq!(capture.measure().frequency().to_string(), "25000.000");
+    assert_eq!(Fixed(5, 3).to_string(), "0.005");
+    assert_eq!(Fixed(42, 0).to_string(), "42");
+}
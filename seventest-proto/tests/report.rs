@@ -0,0 +1,64 @@
+use seventest_proto::*;
+
+const LAST: Option<(u32, u32)> = Some((500, 1500));
+
+#[test]
+fn thresholds() {
+    let ticks = Threshold::Ticks(10);
+    assert!(!ticks.exceeded((500, 1500), 510, 1490));
+    assert!(ticks.exceeded((500, 1500), 511, 1500));
+    assert!(ticks.exceeded((500, 1500), 500, 1489));
+    // 1000 ppm of 2000 ticks is 2
+    let ppm = Threshold::Ppm(1000);
+    assert!(!ppm.exceeded((500, 1500), 502, 1498));
+    assert!(ppm.exceeded((500, 1500), 503, 1500));
+    assert!(ppm.exceeded((u32::MAX, u32::MAX), 0, u32::MAX));
+    // nothing is a change from nothing
+    assert!(!ppm.exceeded((0, 0), 0, 0));
+}
+
+#[test]
+fn default_policy() {
+    let policy = ReportPolicy::default();
+    assert_eq!(policy, DEFAULT_REPORT_POLICY);
+    assert_eq!(report(&policy, None, 500, 1500, 0, 0), Report::Change);
+    assert_eq!(report(&policy, LAST, 505, 1500, 1, 0), Report::Count);
+    assert_eq!(report(&policy, LAST, 505, 1500, 98, 0), Report::Count);
+    assert_eq!(report(&policy, LAST, 505, 1500, 99, 0), Report::Repeat);
+    assert_eq!(report(&policy, LAST, 600, 1500, 1, 0), Report::Change);
+    assert_eq!(policy.to_string(), "threshold 10 ticks, every 100, min interval 0 ms, change only off");
+}
+
+#[test]
+fn policies() {
+    let never = ReportPolicy { every: 0, ..DEFAULT_REPORT_POLICY };
+    assert_eq!(report(&never, LAST, 500, 1500, u32::MAX, 0), Report::Count);
+
+    let change_only = ReportPolicy {
+        every: 1,
+        change_only: true,
+        ..DEFAULT_REPORT_POLICY
+    };
+    assert_eq!(report(&change_only, LAST, 500, 1500, 1000, 0), Report::Count);
+    assert_eq!(report(&change_only, LAST, 700, 1500, 0, 0), Report::Change);
+
+    // changes and repeats wait for the interval, the first capture does not
+    let slow = ReportPolicy {
+        every: 1,
+        min_interval_ms: 50,
+        ..DEFAULT_REPORT_POLICY
+    };
+    assert_eq!(report(&slow, None, 500, 1500, 0, 0), Report::Change);
+    assert_eq!(report(&slow, LAST, 700, 1500, 0, 49), Report::Wait);
+    assert_eq!(report(&slow, LAST, 700, 1500, 0, 50), Report::Change);
+    assert_eq!(report(&slow, LAST, 500, 1500, 0, 49), Report::Count);
+    assert_eq!(report(&slow, LAST, 500, 1500, 0, 50), Report::Repeat);
+
+    let relative = ReportPolicy {
+        threshold: Threshold::Ppm(10_000),
+        ..DEFAULT_REPORT_POLICY
+    };
+    assert_eq!(report(&relative, LAST, 520, 1500, 0, 0), Report::Count);
+    assert_eq!(report(&relative, LAST, 521, 1500, 0, 0), Report::Change);
+    assert_eq!(relative.to_string(), "threshold 10000 ppm, every 100, min interval 0 ms, change only off");
+}
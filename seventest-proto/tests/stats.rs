@@ -0,0 +1,73 @@
+use seventest_proto::stats::{DEFAULT_BIN_WIDTH, HISTOGRAM_BINS};
+use seventest_proto::*;
+
+#[test]
+fn running_statistics() {
+    let mut stats = Stats::new();
+    assert_eq!((stats.count(), stats.min(), stats.max()), (0, None, None));
+    assert_eq!((stats.mean_milli(), stats.stddev_milli()), (0, 0));
+    for x in [2, 4, 4, 4, 5, 5, 7, 9] {
+        stats.add(x);
+    }
+    assert_eq!((stats.count(), stats.min(), stats.max()), (8, Some(2), Some(9)));
+    assert_eq!((stats.mean_milli(), stats.stddev_milli()), (5000, 2000));
+
+    // large values jittering by one tick: no overflow, nothing lost
+    let mut stats = Stats::new();
+    for n in 0..10_000u32 {
+        stats.add(u32::MAX - 1 + n % 2);
+    }
+    assert_eq!(stats.mean_milli(), (u32::MAX as u64 - 1) * 1000 + 500);
+    assert_eq!(stats.stddev_milli(), 500);
+
+    let mut stats = Stats::new();
+    for x in [1, 2] {
+        stats.add(x);
+    }
+    assert_eq!((stats.mean_milli(), stats.stddev_milli()), (1500, 500));
+}
+
+#[test]
+fn histogram_bins_are_centered_on_the_first_value() {
+    let mut histogram = Histogram::<4>::new(10);
+    for x in [100, 95, 110, 119, 120, 79, 80, 0] {
+        histogram.add(x);
+    }
+    let bins: Vec<_> = histogram.bins().collect();
+    assert_eq!(bins, [(-20, 1), (-10, 1), (0, 1), (10, 2)]);
+    assert_eq!((histogram.below(), histogram.above()), (2, 1));
+    assert_eq!(Histogram::<4>::new(0).width(), 1);
+}
+
+#[test]
+fn pwm_statistics() {
+    let mut stats = PwmStats::default();
+    // 1 kHz, 50 % with a tick of jitter either way
+    for n in 0..1000 {
+        let jitter = [0, 1, 0, -1][n % 4];
+        stats.add((62_500 + jitter) as u32, 62_500);
+    }
+    assert_eq!(stats.period.count(), 1000);
+    assert_eq!((stats.period.min(), stats.period.max()), (Some(124_999), Some(125_001)));
+    assert_eq!(stats.period.mean_milli(), 125_000_000);
+    assert_eq!(stats.period.stddev_milli(), 707);
+    let center = stats.jitter.bins().find(|(from, _)| *from == 0).unwrap();
+    assert_eq!(center, (0, 750));
+    assert_eq!(stats.jitter.bins().map(|(_, count)| count).sum::<u32>(), 1000);
+    assert_eq!(
+        stats.display(&PWMIN_TIMEBASE).to_string(),
+        "1000 periods\r\n\
+         period us: min 999.992 max 1000.008 mean 1000.000000 stddev 0.005656\r\n\
+         duty %: min 49.9996 max 50.0004 mean 50.0000 stddev 0.0003\r\n\
+         jitter ns: <-512 0, -512 0, -448 0, -384 0, -320 0, -256 0, -192 0, -128 0, \
+         -64 250, 0 750, 64 0, 128 0, 192 0, 256 0, 320 0, 384 0, 448 0, >=512 0"
+    );
+
+    // a period of nothing is no period
+    stats.add(0, 0);
+    assert_eq!(stats.period.count(), 1000);
+    stats.reset();
+    assert_eq!(stats, PwmStats::new(DEFAULT_BIN_WIDTH));
+    assert_eq!(stats.display(&PWMIN_TIMEBASE).to_string(), "0 periods");
+    assert_eq!(HISTOGRAM_BINS, 16);
+}
@@ -0,0 +1,262 @@
+//! Drive SevenTestHW from a host. A [`Device`] on the UART or USB port of
+//! the board runs shell commands and reads what the board logs, with the
+//! shell in machine mode:
+//!
+//! ```text
+//! host:  <seq> <command line>\r
+//! board: @R <seq> <status> <len>\r\n<output>\r\n
+//! board: @L 0 0 <len>\r\n<log text>\r\n
+//! ```
+//!
+//! See `ashell::machine` for the board side.
+
+pub mod record;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::from_utf8;
+use std::time::Duration;
+
+use serialport::SerialPort;
+
+pub use record::{parse_record, PWMIN_CLK};
+pub use seventest_proto::PwmCapture;
+
+pub const DEFAULT_BAUD: u32 = 921_600;
+/// How long a read waits for the board.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const ENTER: &str = "@machine";
+const LEAVE: &str = "@interactive";
+const CTRL_C: u8 = 0x03;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Port(serialport::Error),
+    /// nothing came in time
+    Timeout,
+    /// the port was closed on the other side
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Port(err) => write!(f, "{}", err),
+            Error::Timeout => f.write_str("no answer from the board"),
+            Error::Closed => f.write_str("the port was closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(err: serialport::Error) -> Self {
+        Error::Port(err)
+    }
+}
+
+/// What the board sends in machine mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// the answer to request `seq`
+    Response { seq: u32, status: i32, output: String },
+    /// text nobody asked for, log lines and output of background jobs
+    Log(String),
+}
+
+/// What a command printed, and its exit status.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: i32,
+    pub output: String,
+}
+
+impl Response {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// A board, its shell in machine mode.
+pub struct Device<P: Read + Write> {
+    port: P,
+    /// read, not a whole frame yet
+    buf: Vec<u8>,
+    seq: u32,
+    /// log lines read while waiting for a response
+    logs: VecDeque<String>,
+}
+
+impl Device<Box<dyn SerialPort>> {
+    /// Open the port at `path`, see [`Device::new`].
+    pub fn open(path: &str, baud: u32) -> Result<Self, Error> {
+        let port = serialport::new(path, baud).timeout(DEFAULT_TIMEOUT).open()?;
+        Device::new(port)
+    }
+}
+
+impl<P: Read + Write> Device<P> {
+    /// Switch the shell on `port` to machine mode. Reads on `port` must
+    /// time out, or a board that does not answer blocks for good.
+    pub fn new(port: P) -> Result<Self, Error> {
+        let mut device = Self {
+            port,
+            buf: Vec::new(),
+            seq: 0,
+            logs: VecDeque::new(),
+        };
+        //Ctrl-C drops a line half typed or cancels a command. In machine
+        //mode already, `@machine` is a request that fails, it is answered
+        //all the same
+        device.port.write_all(&[CTRL_C])?;
+        write!(device.port, "{}\r", ENTER)?;
+        device.port.flush()?;
+        loop {
+            match device.next_frame()? {
+                Frame::Response { .. } => return Ok(device),
+                Frame::Log(text) => device.keep_log(&text),
+            }
+        }
+    }
+
+    /// Run `line` and wait for its response. Log frames that come first
+    /// are kept for [`Device::next_log`].
+    pub fn run(&mut self, line: &str) -> Result<Response, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        write!(self.port, "{} {}\r", seq, line)?;
+        self.port.flush()?;
+        loop {
+            match self.next_frame()? {
+                Frame::Response { seq: answered, status, output } if answered == seq => {
+                    return Ok(Response { status, output })
+                }
+                //the answer to a request given up on
+                Frame::Response { .. } => {}
+                Frame::Log(text) => self.keep_log(&text),
+            }
+        }
+    }
+
+    /// The next line logged, [`Error::Timeout`] if none comes in time.
+    pub fn next_log(&mut self) -> Result<String, Error> {
+        loop {
+            if let Some(line) = self.logs.pop_front() {
+                return Ok(line);
+            }
+            if let Frame::Log(text) = self.next_frame()? {
+                self.keep_log(&text);
+            }
+        }
+    }
+
+    /// A log line already read, without waiting.
+    pub fn pending_log(&mut self) -> Option<String> {
+        self.logs.pop_front()
+    }
+
+    /// Switch the shell back to interactive mode, for people.
+    pub fn close(mut self) -> Result<P, Error> {
+        self.run(LEAVE)?;
+        Ok(self.port)
+    }
+
+    /// The next frame, text before it is skipped.
+    pub fn next_frame(&mut self) -> Result<Frame, Error> {
+        let mut chunk = [0u8; 256];
+        loop {
+            if let Some(frame) = self.take_frame() {
+                return Ok(frame);
+            }
+            match self.port.read(&mut chunk) {
+                Ok(0) => return Err(Error::Closed),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<Frame> {
+        loop {
+            let start = match self.buf.iter().position(|&byte| byte == b'@') {
+                Some(start) => start,
+                None => {
+                    self.buf.clear();
+                    return None;
+                }
+            };
+            self.buf.drain(..start);
+            let end = self.buf.windows(2).position(|pair| pair == b"\r\n")?;
+            let header = match Header::parse(&self.buf[..end]) {
+                Some(header) => header,
+                //an `@` of an echo, not a frame
+                None => {
+                    self.buf.drain(..1);
+                    continue;
+                }
+            };
+            let payload = end + 2;
+            if self.buf.len() < payload + header.len + 2 {
+                return None;
+            }
+            let text = String::from_utf8_lossy(&self.buf[payload..payload + header.len]).into_owned();
+            self.buf.drain(..payload + header.len + 2);
+            return Some(match header.response {
+                true => Frame::Response {
+                    seq: header.seq,
+                    status: header.status,
+                    output: text,
+                },
+                false => Frame::Log(text),
+            });
+        }
+    }
+
+    fn keep_log(&mut self, text: &str) {
+        let lines = text.split('\n').map(|line| line.trim_end_matches('\r'));
+        self.logs.extend(lines.filter(|line| !line.is_empty()).map(String::from));
+    }
+}
+
+/// `@R 1 0 5`, the first line of a frame.
+struct Header {
+    response: bool,
+    seq: u32,
+    status: i32,
+    len: usize,
+}
+
+impl Header {
+    fn parse(line: &[u8]) -> Option<Header> {
+        let mut fields = from_utf8(line).ok()?.strip_prefix('@')?.split(' ');
+        let response = match fields.next()? {
+            "R" => true,
+            "L" => false,
+            _ => return None,
+        };
+        let header = Header {
+            response,
+            seq: fields.next()?.parse().ok()?,
+            status: fields.next()?.parse().ok()?,
+            len: fields.next()?.parse().ok()?,
+        };
+        match fields.next() {
+            None => Some(header),
+            Some(_) => None,
+        }
+    }
+}
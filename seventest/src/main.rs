@@ -0,0 +1,159 @@
+//! `seventest`: run shell commands on a SevenTestHW board and watch its
+//! pwmin channels from the host.
+//!
+//! ```text
+//! seventest -p /dev/ttyACM0 run "pwmin start 0 1" "pwmin status"
+//! seventest -p /dev/ttyACM0 monitor 0 1 -n 100 --csv
+//! seventest -p /dev/ttyACM0 script bench.txt
+//! ```
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use seventest::{parse_record, Device, Error, DEFAULT_BAUD};
+
+#[derive(Parser)]
+#[command(version, about = "Drive a SevenTestHW board over its shell port")]
+struct Cli {
+    /// serial port of the board, like /dev/ttyACM0 or COM3
+    #[arg(short, long)]
+    port: String,
+    #[arg(short, long, default_value_t = DEFAULT_BAUD)]
+    baud: u32,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Run command lines, stop at the first that fails
+    Run {
+        #[arg(required = true)]
+        lines: Vec<String>,
+    },
+    /// Print pwmin captures as they come
+    Monitor {
+        /// channels, as `pwmin watch` takes them
+        #[arg(default_value = "all")]
+        channels: Vec<String>,
+        /// stop after this many captures
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+        /// print comma separated values, with a header line
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Run the lines of a file, `#` starts a comment line
+    Script {
+        file: PathBuf,
+        /// go on after a line fails
+        #[arg(short, long)]
+        keep_going: bool,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = Device::open(&cli.port, cli.baud).and_then(|mut device| {
+        let status = match cli.command {
+            Cmd::Run { lines } => run_lines(&mut device, lines.iter().map(String::as_str), false)?,
+            Cmd::Monitor { channels, count, csv } => monitor(&mut device, &channels.join(" "), count, csv)?,
+            Cmd::Script { file, keep_going } => {
+                let text = fs::read_to_string(&file).map_err(Error::Io)?;
+                let lines = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+                run_lines(&mut device, lines, keep_going)?
+            }
+        };
+        device.close()?;
+        Ok(status)
+    });
+    match result {
+        Ok(status) => ExitCode::from(exit_code(status)),
+        Err(err) => {
+            eprintln!("seventest: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run `lines`, print their output and what was logged meanwhile. The
+/// status of the last line that failed, 0 if none did.
+fn run_lines<'a, P: Read + Write>(device: &mut Device<P>, lines: impl Iterator<Item = &'a str>, keep_going: bool) -> Result<i32, Error> {
+    let mut status = 0;
+    for line in lines {
+        let response = device.run(line)?;
+        print_logs(device);
+        if !response.output.is_empty() {
+            println!("{}", response.output.trim_end());
+        }
+        if !response.success() {
+            eprintln!("seventest: `{}` failed with status {}", line, response.status);
+            status = response.status;
+            if !keep_going {
+                break;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Log lines already read, to stderr, so stdout is only command output.
+fn print_logs<P: Read + Write>(device: &mut Device<P>) {
+    while let Some(line) = device.pending_log() {
+        eprintln!("{}", line);
+    }
+}
+
+/// Watch `channels` in a job on the board and print its captures, other
+/// log lines go to stderr. Without `count` it runs until killed.
+fn monitor<P: Read + Write>(device: &mut Device<P>, channels: &str, count: Option<usize>, csv: bool) -> Result<i32, Error> {
+    let started = device.run(&format!("pwmin watch {} &", channels))?;
+    if !started.success() {
+        eprintln!("seventest: {}", started.output);
+        return Ok(started.status);
+    }
+    //answered with `[n]`
+    let job = started.output.trim().trim_start_matches('[').trim_end_matches(']').to_string();
+    if csv {
+        println!("channel,time_us,count,high_ticks,low_ticks,frequency_hz,high_us,low_us,duty_percent");
+    }
+    let mut seen = 0;
+    while count.map_or(true, |count| seen < count) {
+        let line = match device.next_log() {
+            Ok(line) => line,
+            Err(Error::Timeout) => continue,
+            Err(err) => return Err(err),
+        };
+        let capture = match parse_record(&line) {
+            Some(capture) => capture,
+            None => {
+                eprintln!("{}", line);
+                continue;
+            }
+        };
+        let measured = capture.measure();
+        if csv {
+            let (ch, time, n, high, low) = (capture.channel, capture.time, capture.count, capture.high_ticks, capture.low_ticks);
+            let (freq, high_us, low_us, duty) = (measured.frequency(), measured.high_us(), measured.low_us(), measured.duty());
+            println!("{},{},{},{},{},{},{},{},{}", ch, time, n, high, low, freq, high_us, low_us, duty);
+        } else {
+            println!("ch {} at {} us, {} x {}", capture.channel, capture.time, capture.count, measured);
+        }
+        let _ = std::io::stdout().flush();
+        seen += 1;
+    }
+    device.run(&format!("kill {}", job))?;
+    Ok(0)
+}
+
+/// A status as a process exit code, like sh does.
+fn exit_code(status: i32) -> u8 {
+    match status {
+        0 => 0,
+        1..=255 => status as u8,
+        _ => 1,
+    }
+}
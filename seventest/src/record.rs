@@ -0,0 +1,43 @@
+//! `[PwmIn]` records, as `pwmin watch` prints them:
+//!
+//! ```text
+//! [PwmIn]:<channel>:<time>:<count>:<high ticks>:<low ticks>:<frequency>Hz:<high>us:<low>us:<duty>%
+//! ```
+//!
+//! `time` is in µs since boot, `count` is how many periods in a row were
+//! like this one. The fields with units are the ticks measured on the
+//! board, [`PwmCapture::measure`] gives them back.
+
+use std::str::{FromStr, Split};
+
+use seventest_proto::{PwmCapture, PWMIN_TIMEBASE};
+
+/// Ticks per second of the pwmin state machines, records leave it out.
+pub const PWMIN_CLK: u32 = PWMIN_TIMEBASE.tick_hz();
+const TAG: &str = "[PwmIn]:";
+/// units of the fields after the ticks
+const UNITS: [&str; 4] = ["Hz", "us", "us", "%"];
+
+/// The capture in a record line, `None` for any other line.
+pub fn parse_record(line: &str) -> Option<PwmCapture> {
+    let mut fields = line.trim_end().strip_prefix(TAG)?.split(':');
+    let capture = PwmCapture {
+        channel: field(&mut fields)?,
+        time: field(&mut fields)?,
+        count: field(&mut fields)?,
+        high_ticks: field(&mut fields)?,
+        low_ticks: field(&mut fields)?,
+        clk: PWMIN_CLK,
+    };
+    for unit in UNITS {
+        fields.next()?.strip_suffix(unit)?;
+    }
+    match fields.next() {
+        None => Some(capture),
+        Some(_) => None,
+    }
+}
+
+fn field<T: FromStr>(fields: &mut Split<char>) -> Option<T> {
+    fields.next()?.parse().ok()
+}
@@ -0,0 +1,195 @@
+//! A board on a pseudo-terminal: an `AShell` with job control serves one
+//! end, like the firmware serves its UART, and a [`Device`] opens the
+//! other.
+//!
+//! The board commands: `pwm` prints `ok`, `exit <n>` fails with status
+//! `n`, `note <text>` logs `text`, and `pwmin watch <ch>` prints a
+//! `[PwmIn]` record for `ch` over and over.
+
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ashell::autocomplete::StaticAutocomplete;
+use ashell::command::MAX_ARGS;
+use ashell::history::LRUHistory;
+use ashell::jobs::Jobs;
+use ashell::output::{self, CommandOutput};
+use ashell::{AShell, ArgvEnvironment, Event, ShellError, ShellResult, Source};
+use embassy_futures::select::select;
+use embassy_futures::{block_on, yield_now};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pipe::Pipe;
+use serialport::{SerialPort, TTYPort};
+use seventest::{parse_record, Device, Error, Response};
+
+const CMD_LEN: usize = 64;
+const LOG_LEN: usize = 4096;
+
+/// What the board logs, shared by its commands and its port.
+#[derive(Clone, Default)]
+struct Log(Rc<RefCell<String>>);
+
+impl std::fmt::Write for Log {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.borrow_mut().push_str(s);
+        Ok(())
+    }
+}
+
+impl CommandOutput for Log {
+    fn poll_room(&mut self, _cx: &mut Context<'_>, _len: usize) -> Poll<()> {
+        Poll::Ready(())
+    }
+}
+
+struct Bench {
+    log: Log,
+}
+
+impl ArgvEnvironment for Bench {
+    async fn command(&mut self, argv: &[&str], out: &mut dyn CommandOutput) -> ShellResult {
+        match argv {
+            ["pwm", ..] => Ok(out.write_str("ok")?),
+            ["exit", status] => Err(ShellError::ExecuteError(status.parse().unwrap_or(1))),
+            ["note", text] => {
+                write!(self.log, "{}\r\n", text)?;
+                //long enough for the port to send it
+                for _ in 0..16 {
+                    yield_now().await;
+                }
+                Ok(())
+            }
+            ["pwmin", "watch", ch] => {
+                for n in 0.. {
+                    output::write_all(out, &format!("[PwmIn]:{}:{}:1:500:1500:62500.000Hz:4.000us:12.000us:25.0000%\r\n", ch, n)).await?;
+                    std::thread::sleep(Duration::from_millis(1));
+                    yield_now().await;
+                }
+                Ok(())
+            }
+            _ => Err(ShellError::CommandNotFound),
+        }
+    }
+
+    async fn control(&mut self, _code: u8) -> ShellResult {
+        Ok(())
+    }
+}
+
+/// The board end of the pseudo-terminal. Shell output is sent from here,
+/// while the shell waits for input, like `UsbSource` does.
+struct Port {
+    port: TTYPort,
+    pipe: &'static Pipe<ThreadModeRawMutex, LOG_LEN>,
+    log: Log,
+}
+
+impl Source for Port {
+    async fn next(&mut self, buf: &mut [u8]) -> Event {
+        let mut out = [0u8; LOG_LEN];
+        loop {
+            while let Ok(n) = self.pipe.try_read(&mut out) {
+                let _ = self.port.write_all(&out[..n]);
+            }
+            let text: Vec<u8> = {
+                let mut log = self.log.0.borrow_mut();
+                let n = log.len().min(buf.len());
+                log.drain(..n).collect::<String>().into_bytes()
+            };
+            if !text.is_empty() {
+                buf[..text.len()].copy_from_slice(&text);
+                return Event::Print(text.len());
+            }
+            match self.port.read(buf) {
+                Ok(n) if n > 0 => return Event::Input(n),
+                _ => yield_now().await,
+            }
+        }
+    }
+}
+
+/// Start a board, the host end of its port.
+fn board() -> TTYPort {
+    let (mut board, mut host) = TTYPort::pair().unwrap();
+    board.set_timeout(Duration::from_millis(1)).unwrap();
+    host.set_timeout(Duration::from_secs(2)).unwrap();
+    //`ThreadModeRawMutex` only works on a thread named `main` on the host
+    std::thread::Builder::new()
+        .name("main".into())
+        .spawn(move || {
+            let pipe: &'static Pipe<ThreadModeRawMutex, LOG_LEN> = Box::leak(Box::new(Pipe::new()));
+            let jobs: &'static Jobs<ThreadModeRawMutex, 2, CMD_LEN> = Box::leak(Box::new(Jobs::new()));
+            let log = Log::default();
+            let mut shell: AShell<_, LRUHistory<CMD_LEN, 16>, _, CMD_LEN> =
+                block_on(AShell::new(StaticAutocomplete(["pwm", "pwmin"]), LRUHistory::default(), pipe));
+            shell.set_jobs(jobs);
+            let mut port = Port { port: board, pipe, log: log.clone() };
+            let mut env = Bench { log: log.clone() };
+            let mut job_env = Bench { log: log.clone() };
+            let mut job_out = log;
+            block_on(select(
+                shell.run::<MAX_ARGS>(&mut env, &mut port),
+                jobs.run::<MAX_ARGS>(&mut job_env, &mut job_out),
+            ));
+        })
+        .unwrap();
+    host
+}
+
+fn response(status: i32, output: &str) -> Response {
+    Response {
+        status,
+        output: output.into(),
+    }
+}
+
+#[test]
+fn commands_run_with_their_status() {
+    let mut host = board();
+    //a line half typed is dropped
+    host.write_all(b"pw").unwrap();
+    let mut device = Device::new(host).unwrap();
+    assert_eq!(device.run("pwm 1").unwrap(), response(0, "ok"));
+    assert_eq!(device.run("exit 3").unwrap(), response(3, "exit: failed with status 3"));
+    assert_eq!(device.run("nosuch").unwrap(), response(127, "nosuch: command not found"));
+    assert_eq!(device.run("pwm; exit 2 || pwm").unwrap(), response(0, "ok\r\nexit: failed with status 2\r\nok"));
+
+    // back to a shell for people, it echoes
+    let mut host = device.close().unwrap();
+    host.write_all(b"pwm\r").unwrap();
+    let mut seen = Vec::new();
+    while !seen.ends_with(b"pwm\r\nok\r\n#>") {
+        let mut buf = [0u8; 64];
+        let n = host.read(&mut buf).unwrap();
+        seen.extend_from_slice(&buf[..n]);
+    }
+    // and to machine mode again
+    let mut device = Device::new(host).unwrap();
+    assert!(device.run("pwm").unwrap().success());
+}
+
+#[test]
+fn logs_are_kept_apart() {
+    let mut device = Device::new(board()).unwrap();
+    assert_eq!(device.run("note hello").unwrap(), response(0, ""));
+    assert_eq!(device.run("note world").unwrap(), response(0, ""));
+    assert_eq!(device.pending_log().as_deref(), Some("hello"));
+    assert_eq!(device.next_log().unwrap(), "world");
+    assert!(matches!(device.next_log(), Err(Error::Timeout)));
+}
+
+#[test]
+fn records_of_a_job_are_read() {
+    let mut device = Device::new(board()).unwrap();
+    assert_eq!(device.run("pwmin watch 3 &").unwrap(), response(0, "[1]"));
+    let captures: Vec<_> = (0..5)
+        .map(|_| parse_record(&device.next_log().unwrap()).expect("a record"))
+        .collect();
+    assert!(captures.iter().all(|capture| capture.channel == 3 && capture.high_ticks == 500));
+    assert!(captures.windows(2).all(|pair| pair[0].time < pair[1].time));
+    assert_eq!(device.run("kill 1").unwrap(), response(0, ""));
+}
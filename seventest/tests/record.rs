@@ -0,0 +1,24 @@
+use seventest::{parse_record, PwmCapture, PWMIN_CLK};
+
+#[test]
+fn records_are_parsed() {
+    let capture = PwmCapture {
+        channel: 2,
+        clk: PWMIN_CLK,
+        high_ticks: 62_500,
+        low_ticks: 187_500,
+        count: 40,
+        time: 12_345_678,
+    };
+    let line = "[PwmIn]:2:12345678:40:62500:187500:500.000Hz:500.000us:1500.000us:25.0000%";
+    assert_eq!(parse_record(line), Some(capture));
+    assert_eq!(parse_record(&format!("{}\r\n", line)), Some(capture));
+
+    assert_eq!(parse_record("[pwmin] 2 start success"), None);
+    assert_eq!(parse_record("[PwmIn]:2:12345678:40:62500:187500"), None);
+    assert_eq!(parse_record("[PwmIn]:2:12345678:40:62500:187500:500.000Hz:500.000us:1500.000us"), None);
+    assert_eq!(parse_record("[PwmIn]:2:12345678:40:62500:187500:500.000:500.000:1500.000:25.0000"), None);
+    assert_eq!(parse_record(&format!("{}:1", line)), None);
+    assert_eq!(parse_record("[PwmIn]:256:0:1:1:1:0Hz:0us:0us:0%"), None);
+    assert_eq!(parse_record("[PwmIn]:x:0:1:1:1:0Hz:0us:0us:0%"), None);
+}